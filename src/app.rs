@@ -4,15 +4,18 @@ use glutin::{
     dpi::PhysicalSize,
     event::{self, Event, MouseButton, WindowEvent},
     event_loop::{ControlFlow, EventLoop, EventLoopProxy},
-    platform::{windows::RawHandle, ContextTraitExt},
+    platform::ContextTraitExt,
     window::{Window, WindowBuilder, WindowId},
-    ContextWrapper, NotCurrent, PossiblyCurrent, RawContext,
+    ContextWrapper, NotCurrent, PossiblyCurrent,
 };
 
-use glutin::platform::windows::RawContextExt;
+#[cfg(target_os = "linux")]
+use glutin::platform::unix::RawHandle;
+#[cfg(windows)]
+use glutin::platform::windows::{RawContextExt, RawHandle};
 
 // use event_loop::{ControlFlow, EventLoopProxy};
-use gst::{prelude::*, StructureRef};
+use gst::prelude::*;
 use gst_gl::{ContextGLExt, GLContextExt};
 use gstreamer as gst;
 use gstreamer_app as gst_app;
@@ -25,20 +28,38 @@ use anyhow::Result;
 use futures::channel::mpsc::UnboundedSender;
 use std::{
     collections::HashMap,
+    convert::TryFrom,
     ops::Deref,
     sync::{
+        atomic::{AtomicU64, Ordering},
         mpsc::{channel, Receiver, Sender},
-        Arc, Mutex,
+        Arc, Mutex, Weak,
     },
     time::{Duration, Instant},
 };
 use window_message::{ViewSample, WindowMessage};
 
-use crate::message::{AppMessage, ClientConfig, LayoutRect};
+use crate::message::{AppMessage, ClientConfig, DataMessage, LayoutRect};
 use crate::window_message;
 use crate::{
-    glvideo::GlRenderer,
-    util::{element_timer::ElementTimer, window_timer::WindowTimer},
+    codec::{Codec, DecoderSelection},
+    display::DisplaySettings,
+    glvideo::{Frame, GlRenderer},
+    main_context::MainContext,
+    network::NetworkConfig,
+    recording::{Recording, RecordingConfig},
+    renderer::{Damage, PresentMode, Renderer},
+    stats::{
+        FrameCounters, JitterStats, LatencySummary, SessionStats, StatsCollector, StatsCsv,
+        StatsHandle, StreamSource,
+    },
+    util::{
+        element_timer::ElementTimer,
+        frame_guard::FrameGuard,
+        recovery::RecoveryTracker,
+        rtp_times::{rtp_timestamp, RtpTimes},
+        window_timer::WindowTimer,
+    },
     view::ViewControl,
     AppConfig,
 };
@@ -46,23 +67,97 @@ use crate::{
 // use super::view::ViewCollection;
 type MessageSender<T> = Sender<Event<'static, T>>;
 
+// A stream that fails more often than this is given up and the session is restarted.
+const MAX_STREAM_RECOVERIES: usize = 3;
+const STREAM_RECOVERY_WINDOW: Duration = Duration::from_secs(30);
+// A stream that gets no buffer to swap its decode bin in this time restarts the session.
+const STREAM_REBUILD_TIMEOUT: Duration = Duration::from_secs(5);
+// Jitterbuffer latency change in ms for the -/+ keys.
+const LATENCY_STEP: i32 = 50;
+// Gamma and contrast change for the display keys.
+const DISPLAY_STEP: f32 = 0.1;
+// Frame ages kept for the present latency between two stats collections.
+const MAX_PRESENT_AGES: usize = 10_000;
+// Longest a latest-at-vsync render waits for the queued events, so a steady flood
+// of events can not hold it back.
+const MAX_RENDER_DEFER: Duration = Duration::from_millis(8);
+
 #[derive(Debug)]
 struct SharedState {
     message_sender: Option<MessageSender<WindowMessage>>,
     timers: Vec<ElementTimer>,
     samples: HashMap<usize, Option<ViewSample>>,
+    // Decoder element used for each stream
+    stream_decoders: HashMap<usize, String>,
+    streams: HashMap<usize, DecodeStream>,
+    // Jitterbuffers created by rtpbin, keyed on ssrc
+    jitter_buffers: HashMap<u32, gst::Element>,
+    // The last reply to get-stats from webrtcbin
+    webrtc_stats: Option<gst::Structure>,
+    // Set while the streams are recorded, new streams start recording when linked.
+    recording: bool,
+}
+
+// The decode bin linked to a webrtcbin src pad.
+#[derive(Debug)]
+struct DecodeStream {
+    pad: gst::Pad,
+    bin: gst::Bin,
+    codec: Codec,
+    counters: Arc<FrameCounters>,
+    guard: Arc<FrameGuard>,
+    recording: Option<Recording>,
+    // Since when a blocking probe on the pad waits to swap in a new decode bin.
+    rebuilding: Option<Instant>,
+}
+
+/// Points the signalling thread at the App that should handle incomming messages.
+/// The target is replaced when the session is rebuilt after a reconnect.
+#[derive(Debug, Clone, Default)]
+pub struct SignalTarget {
+    app: Arc<Mutex<Weak<AppInner>>>,
+    // Number of the last session created, each App tags its messages with its session.
+    session: Arc<AtomicU64>,
+}
+
+impl SignalTarget {
+    fn set(&self, app: &App) {
+        *self.app.lock().unwrap() = Arc::downgrade(&app.0);
+    }
+
+    pub fn upgrade(&self) -> Option<App> {
+        self.app.lock().unwrap().upgrade().map(App)
+    }
+
+    fn next_session(&self) -> u64 {
+        self.session.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    /// The session of the last App created.
+    pub fn session(&self) -> u64 {
+        self.session.load(Ordering::SeqCst)
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct App(pub Arc<AppInner>);
 #[derive(Debug)]
 pub struct AppInner {
-    signaller: UnboundedSender<AppMessage>,
+    // Messages to the server, tagged with the session they belong to.
+    signaller: UnboundedSender<(u64, AppMessage)>,
+    target: SignalTarget,
+    session: u64,
     webrtcbin: gst::Element,
     pipeline: gst::Pipeline,
     shared: Mutex<SharedState>,
     tcp: bool,
-    decoder: Decoder,
+    // The decoder picked for each codec, kept up to date with the decoders that start.
+    decoders: Mutex<DecoderSelection>,
+    // Changed at runtime, e.g. the latency.
+    network: Mutex<NetworkConfig>,
+    recording: RecordingConfig,
+    // Hold the last good frame after unrecovered loss, until the next key frame.
+    hold_corrupt: bool,
 }
 
 impl Deref for App {
@@ -73,23 +168,35 @@ impl Deref for App {
 }
 impl Drop for AppInner {
     fn drop(&mut self) {
+        // Finalize the recordings while the pipeline is still running.
+        let recordings: Vec<_> = self
+            .shared
+            .lock()
+            .unwrap()
+            .streams
+            .values_mut()
+            .filter_map(|s| s.recording.take().map(|r| (r, s.bin.clone())))
+            .collect();
+        let finalizing: Vec<_> = recordings
+            .into_iter()
+            .map(|(recording, bin)| recording.stop(&bin))
+            .collect();
+        for handle in finalizing {
+            let _ = handle.join();
+        }
         let _ = self.pipeline.set_state(gst::State::Null);
     }
 }
 
-#[derive(Debug, Copy, Clone)]
-pub enum Decoder {
-    Software,
-    Hardware,
-    FastSoftware,
-}
-
 impl App {
     pub fn new(
-        signaller: UnboundedSender<AppMessage>,
+        signaller: UnboundedSender<(u64, AppMessage)>,
+        target: SignalTarget,
         tcp: bool,
-        decoder: Decoder,
-        jitter: u32,
+        decoders: DecoderSelection,
+        mut network: NetworkConfig,
+        recording: RecordingConfig,
+        hold_corrupt: bool,
     ) -> Self {
         let pipeline = gst::Pipeline::new(None);
         let webrtcbin = gst::ElementFactory::make("webrtcbin", Some("webrtcbin"))
@@ -112,17 +219,6 @@ impl App {
             agent
                 .set_property("ice-udp", &false)
                 .expect("Failed to disable UDP");
-
-        // let rtpbin = pipeline
-        //     .get_by_name("rtpbin")
-        //     .expect("Failed to get rtpbin");
-
-        // // rtpbin
-        // //     .set_property("latency", &500_u32)
-        // //     .expect("Failed to set latency");
-
-        // println!("Setting 'synced' rtpjitterbuffer mode");
-        // rtpbin.set_property_from_str("buffer-mode", "synced");
         } else {
             // let agent = webrtcbin
             //     .get_property("ice-agent")
@@ -148,84 +244,141 @@ impl App {
                     None
                 })
                 .expect("Failed to attach handler");
-
-            // let rtpbin = pipeline
-            //     .get_by_name("rtpbin")
-            //     .expect("Failed to get rtpbin");
-
-            // TODO: Handle configuring the latency in the buffer
-            // this helps A LOT for the problem of corrrupt frames!
-            // A too small buffer leads to that frames might be
-            // dropped while waiting for retransmission etc.
-            // this is likely what causes the large artifacts.
-
-            // rtpbin
-            //     .set_property("latency", &50_u32)
-            //     .expect("Failed to set latency");
-
-            // rtpbin
-            //     .set_property("do-retransmission", &true)
-            //     .expect("Failed to set retransmission");
-            // rtpbin
-            //     .set_property("drop-on-latency", &true)
-            //     .expect("Failed to set drop on latency");
-            // rtpbin
-            //     .set_property("do-lost", &true)
-            //     .expect("Failed to set do-lost");
         }
 
         let rtpbin = pipeline
             .get_by_name("rtpbin")
             .expect("Failed to get rtpbin");
 
-        log::debug!("Setting 'synced' rtpjitterbuffer mode");
-        rtpbin.set_property_from_str("buffer-mode", "synced");
-        log::debug!("Setting jitter buffer latency to {}", jitter);
-        rtpbin
-            .set_property("latency", &jitter)
-            .expect("Failed to set jitter buffer latency");
-
-        // END TODO
+        // Corrupt frames are only held back if the loss is reported.
+        if hold_corrupt {
+            network.report_loss();
+        }
+        // A large enough buffer gives retransmissions time to arrive, which avoids corrupt frames.
+        network.apply(&rtpbin);
 
+        let session = target.next_session();
         let inner = AppInner {
             signaller,
+            target,
+            session,
             pipeline,
             webrtcbin,
             shared: Mutex::new(SharedState {
                 message_sender: None,
                 timers: Vec::default(),
                 samples: HashMap::default(),
+                stream_decoders: HashMap::default(),
+                streams: HashMap::default(),
+                jitter_buffers: HashMap::default(),
+                webrtc_stats: None,
+                recording: false,
             }),
             tcp,
-            decoder,
+            decoders: Mutex::new(decoders),
+            network: Mutex::new(network),
+            recording,
+            hold_corrupt,
         };
         let app = App(Arc::new(inner));
 
         app.setup_bus_handling();
+        app.setup_jitter_buffer_callback(&rtpbin);
         app.setup_ice_callback();
         app.setup_stream_callback();
         app.setup_datachannel();
 
+        // Route signalling messages to the new App
+        app.target.set(&app);
+
         app
     }
 
+    fn new_session(&self) -> App {
+        // The old webrtcbin can't be renegotiated with a restarted server,
+        // so build a new pipeline with the same settings.
+        App::new(
+            self.signaller.clone(),
+            self.target.clone(),
+            self.tcp,
+            self.decoders.lock().unwrap().clone(),
+            self.network.lock().unwrap().clone(),
+            self.recording.clone(),
+            self.hold_corrupt,
+        )
+    }
+
+    pub fn on_reconnected(&self) {
+        self.send_window_message(WindowMessage::Reconnected);
+    }
+
     fn setup_bus_handling(&self) {
         let bus = self.pipeline.get_bus().expect("Failed to get pipeline bus");
         let weak_app = Arc::downgrade(&self.0);
-        bus.connect_message(move |_bus, msg| {
-            match msg.view() {
-                gst::MessageView::Error(e) => {
-                    log::error!("Pipeline error: {:?}", e);
-                    // Post an error message on the message thread.
-                    if let Some(app) = weak_app.upgrade().map(App) {
-                        app.send_window_message(WindowMessage::PipelineError);
+        // Handle messages synchronously, there is no GLib main loop running a bus watch.
+        bus.set_sync_handler(move |_bus, msg| {
+            if let gst::MessageView::Error(e) = msg.view() {
+                let src = e.get_src();
+                log::error!(
+                    "Pipeline error from {}: {} ({:?})",
+                    src.as_ref()
+                        .map_or_else(|| "unknown".into(), |s| s.get_path_string()),
+                    e.get_error(),
+                    e.get_debug()
+                );
+                // Post an error message on the message thread.
+                if let Some(app) = weak_app.upgrade().map(App) {
+                    let stream = src.and_then(|s| app.stream_for_element(&s));
+                    app.send_window_message(WindowMessage::PipelineError(stream));
+                }
+            } else if let gst::MessageView::Qos(qos) = msg.view() {
+                // Decoders post QoS messages with the number of frames they dropped.
+                let app = weak_app.upgrade().map(App);
+                if let (Some(app), Some(src)) = (app, qos.get_src()) {
+                    let dropped = qos.get_stats().1.get_value();
+                    if dropped >= 0 {
+                        app.stream_for_element(&src)
+                            .and_then(|idx| app.stream_counters(idx))
+                            .map(|counters| counters.set_dropped(dropped as u64));
+                    }
+                }
+            } else if let gst::MessageView::Warning(w) = msg.view() {
+                // Decoders warn about frames they failed to decode and carry on.
+                let app = weak_app.upgrade().map(App);
+                if let (Some(app), Some(src)) = (app, w.get_src()) {
+                    if let Some(idx) = app.stream_for_element(&src) {
+                        log::warn!("Stream {}: {} ({:?})", idx, w.get_error(), w.get_debug());
+                        // Other elements of the bin warn about things that don't corrupt frames.
+                        if src.get_name() == format!("decoder{}", idx) {
+                            app.on_stream_corrupt(idx);
+                        }
                     }
                 }
-                _ => {}
             }
+            gst::BusSyncReply::Drop
         });
     }
 
+    fn setup_jitter_buffer_callback(&self, rtpbin: &gst::Element) {
+        // Keep track of the jitterbuffer of each ssrc, so stats can be read per stream.
+        let weak_app = Arc::downgrade(&self.0);
+        rtpbin
+            .connect("new-jitterbuffer", false, move |values| {
+                let jitter_buffer = values[1]
+                    .get::<gst::Element>()
+                    .expect("Failed to get jitterbuffer")
+                    .unwrap();
+                let ssrc = values[3].get_some::<u32>().expect("Failed to get ssrc");
+                log::debug!("New jitterbuffer for ssrc {}", ssrc);
+                if let Some(app) = weak_app.upgrade().map(App) {
+                    let mut shared = app.shared.lock().unwrap();
+                    shared.jitter_buffers.insert(ssrc, jitter_buffer);
+                }
+                None
+            })
+            .expect("Failed to attach new-jitterbuffer signal");
+    }
+
     fn setup_datachannel(&self) {
         let weak_app = Arc::downgrade(&self.0);
         self.webrtcbin
@@ -236,6 +389,7 @@ impl App {
                         .get::<gst_webrtc::WebRTCDataChannel>()
                         .expect("Failed to get datachannel from values")
                         .unwrap();
+                    app.setup_datachannel_messages(&datachannel);
                     let shared = app.shared.lock().unwrap();
                     shared.message_sender.as_ref().map(|sender| {
                         sender
@@ -250,6 +404,41 @@ impl App {
             .expect("Failed to attach data-channel signal");
     }
 
+    fn setup_datachannel_messages(&self, datachannel: &gst_webrtc::WebRTCDataChannel) {
+        let label = datachannel
+            .get_property_label()
+            .expect("Failed to get datachannel label")
+            .to_string();
+        let weak_app = Arc::downgrade(&self.0);
+        datachannel
+            .connect("on-message-string", false, move |values| {
+                let msg = values[1]
+                    .get::<String>()
+                    .expect("Failed to get datachannel message")
+                    .unwrap_or_default();
+                log::trace!("DC received: {}", &msg);
+                match DataMessage::try_from(msg) {
+                    // The server rendered the state, from the frame with the RTP timestamp on.
+                    Ok(DataMessage::Rendered { seq, rtp_time }) => {
+                        if let Some(app) = weak_app.upgrade().map(App) {
+                            let shared = app.shared.lock().unwrap();
+                            if let Some(sender) = shared.message_sender.as_ref() {
+                                let rendered =
+                                    WindowMessage::Rendered(label.clone(), seq, rtp_time);
+                                sender
+                                    .send(rendered.into())
+                                    .expect("Failed to send rendered seq");
+                            }
+                        }
+                    }
+                    Ok(msg) => log::debug!("Unexpected datachannel message {:?}", msg),
+                    Err(e) => log::error!("Failed to parse datachannel message: {:?}", e),
+                }
+                None
+            })
+            .expect("Failed to attach on-message-string signal");
+    }
+
     fn setup_stream_callback(&self) {
         let weak_app = Arc::downgrade(&self.0);
         self.webrtcbin.connect_pad_added(move |_webrtc, pad| {
@@ -268,8 +457,12 @@ impl App {
             }
         });
 
-        self.webrtcbin.connect_pad_removed(|_, _| {
+        let weak_app = Arc::downgrade(&self.0);
+        self.webrtcbin.connect_pad_removed(move |_webrtc, pad| {
             log::debug!("Pad removed...");
+            if let Some(app) = weak_app.upgrade().map(App) {
+                app.on_stream_removed(pad);
+            }
         });
     }
 
@@ -286,30 +479,199 @@ impl App {
             .expect("Transceiver was empty");
 
         let mlineidx = transceiver.get_property_mlineindex();
+        // A stream that comes back on the same mline replaces anything left from before.
+        if self.remove_decode_bin(mlineidx as usize).is_some() {
+            log::debug!("Replaced stale decode bin for stream {}", mlineidx);
+        }
+        self.link_decode_bin(pad, mlineidx)
+    }
 
-        log::debug!("Linking new stream with id {}", mlineidx);
-        // let pipeline_description = "identity name=ident ! application/x-rtp, media=(string)video, clock-rate=(int)90000, encoding-name=(string)H264, payload=(int)96
-        //  ! rtph264depay name=depay ! h264parse ! avdec_h264 ! videoconvert ! videoscale ! d3d11upload ! d3d11videosink";
-
-        // let pipeline_description = "rtph264depay name=depay ! h264parse ! avdec_h264 ! d3d11upload ! d3d11convert ! d3d11videosink sync=false";
-        let decoder_template = match self.decoder {
-            Decoder::Software => "openh264dec",
-            Decoder::Hardware => "nvh264dec",
-            Decoder::FastSoftware => "avdec_h264",
-        };
+    fn link_decode_bin(&self, pad: &gst::Pad, mlineidx: u32) -> Result<()> {
+        // Pick depayloader, parser and decoder from the RTP caps of the pad.
+        let caps = pad
+            .get_current_caps()
+            .or_else(|| pad.query_caps(None))
+            .ok_or_else(|| anyhow::anyhow!("No caps on incomming pad"))?;
+        let encoding_name = caps
+            .get_structure(0)
+            .and_then(|s| s.get::<String>("encoding-name").ok().flatten())
+            .ok_or_else(|| anyhow::anyhow!("No encoding-name in caps {:?}", caps))?;
+        let codec = Codec::from_encoding_name(&encoding_name)
+            .ok_or_else(|| anyhow::anyhow!("Unsupported codec {}", encoding_name))?;
+        if !codec.is_available() {
+            anyhow::bail!("Missing depayloader or parser for {:?}", codec);
+        }
 
-        let pipeline_template =
-            "rtph264depay name=depay{idx} ! h264parse name=parse{idx} config-interval=-1 ! {decoder_tpl} name=decoder{idx} qos=true ! queue ! glupload name=upload{idx} ! glcolorconvert name=convert{idx} ! appsink name=appsink{idx}";
-        // let pipeline_template =
-        //     "rtpjpegdepay name=depay{idx} ! jpegparse name=parse{idx} ! jpegdec name=decoder{idx} qos=true ! queue ! glupload name=upload{idx} ! glcolorconvert name=convert{idx} ! appsink name=appsink{idx}";
-        // Get the selected decoder
-        let pipeline_template = pipeline_template.replace("{decoder_tpl}", decoder_template);
-        let pipeline_description = pipeline_template.replace("{idx}", &mlineidx.to_string());
+        // The RTP timestamps of the frames, recorded at the depayloader.
+        let rtp_times = Arc::new(RtpTimes::default());
+
+        // Start with the selected decoder and fall back on the rest of the chain
+        // if it fails to instantiate or to start.
+        let candidates = self.decoders.lock().unwrap().candidates(codec);
+        let started = candidates
+            .into_iter()
+            .find_map(|decoder| {
+                let pipeline_template = format!(
+                    "{} ! queue ! glupload name=upload{{idx}} ! glcolorconvert name=convert{{idx}} ! appsink name=appsink{{idx}}",
+                    codec.decode_template(&decoder)
+                );
+                let pipeline_description =
+                    pipeline_template.replace("{idx}", &mlineidx.to_string());
+                let decodebin = match gst::parse_bin_from_description(&pipeline_description, true) {
+                    Ok(decodebin) => decodebin,
+                    Err(e) => {
+                        log::warn!("Failed to create decoder {}: {:?}", decoder, e);
+                        return None;
+                    }
+                };
+                self.setup_appsink(&decodebin, mlineidx, rtp_times.clone());
+                match self.start_decode_bin(pad, &decodebin) {
+                    Ok(()) => Some((decoder, pipeline_template, decodebin)),
+                    Err(e) => {
+                        log::warn!("Failed to start decoder {}: {:?}", decoder, e);
+                        None
+                    }
+                }
+            });
+        // Later streams and sessions start with the decoder that worked.
+        self.decoders.lock().unwrap().update(
+            codec,
+            started.as_ref().map(|(decoder, _, _)| decoder.as_str()),
+        );
+        let (decoder_name, pipeline_template, decodebin) =
+            started.ok_or_else(|| anyhow::anyhow!("No working decoder for {:?}", codec))?;
+
+        log::info!(
+            "Decoding {:?} stream {} with {}",
+            codec,
+            mlineidx,
+            decoder_name
+        );
         println!("Using decoder bin: {}", &pipeline_template);
+        {
+            let mut shared = self.shared.lock().unwrap();
+            shared
+                .stream_decoders
+                .insert(mlineidx as usize, decoder_name);
+        }
+
+        let decoder = decodebin
+            .get_by_name(&format!("decoder{}", mlineidx))
+            .expect("Failed to get decoder");
+        let convert = decodebin
+            .get_by_name(&format!("convert{}", mlineidx))
+            .expect("Failed to get convert");
+
+        let depay_sink = decodebin
+            .get_by_name(&format!("depay{}", mlineidx))
+            .and_then(|depay| depay.get_static_pad("sink"))
+            .expect("Failed to get depayloader sink pad");
+        let guard = Arc::new(FrameGuard::new(self.hold_corrupt));
+
+        // The jitterbuffer sends GstRTPPacketLost once it gives up on a packet.
+        let probe_guard = guard.clone();
+        depay_sink.add_probe(gst::PadProbeType::EVENT_DOWNSTREAM, move |pad, info| {
+            if let Some(gst::PadProbeData::Event(ref event)) = info.data {
+                let lost =
+                    matches!(event.get_structure(), Some(s) if s.get_name() == "GstRTPPacketLost");
+                if lost && probe_guard.mark_corrupt() {
+                    log::debug!("Stream {} lost packets, requesting a key frame", mlineidx);
+                    request_key_frame(pad);
+                }
+            }
+            gst::PadProbeReturn::Ok
+        });
+        // Packets dropped without an event, e.g. with do-lost off, leave a DISCONT gap.
+        let probe_guard = guard.clone();
+        depay_sink.add_probe(gst::PadProbeType::BUFFER, move |pad, info| {
+            if let Some(gst::PadProbeData::Buffer(ref buffer)) = info.data {
+                let discont = buffer.get_flags().contains(gst::BufferFlags::DISCONT);
+                if probe_guard.on_packet(discont) {
+                    log::debug!("Stream {} has a gap, requesting a key frame", mlineidx);
+                    request_key_frame(pad);
+                }
+                let rtp_time = buffer
+                    .map_readable()
+                    .ok()
+                    .and_then(|map| rtp_timestamp(map.as_slice()));
+                if let (Some(pts), Some(rtp_time)) = (buffer.get_pts().nseconds(), rtp_time) {
+                    rtp_times.record(pts, rtp_time);
+                }
+            }
+            gst::PadProbeReturn::Ok
+        });
+
+        // Note the first clean key frame that goes into the decoder after a loss.
+        let probe_guard = guard.clone();
+        decoder
+            .get_static_pad("sink")
+            .expect("Failed to get decoder sink pad")
+            .add_probe(gst::PadProbeType::BUFFER, move |_pad, info| {
+                if let Some(gst::PadProbeData::Buffer(ref buffer)) = info.data {
+                    let flags = buffer.get_flags();
+                    probe_guard.on_input(
+                        !flags.contains(gst::BufferFlags::DELTA_UNIT),
+                        flags.contains(gst::BufferFlags::DISCONT),
+                        buffer.get_pts().nseconds(),
+                    );
+                }
+                gst::PadProbeReturn::Ok
+            });
+
+        // Count the frames coming out of the decoder, and hold back the corrupt ones.
+        let counters = Arc::new(FrameCounters::default());
+        let probe_counters = counters.clone();
+        let probe_guard = guard.clone();
+        decoder
+            .get_static_pad("src")
+            .expect("Failed to get decoder src pad")
+            .add_probe(gst::PadProbeType::BUFFER, move |_pad, info| {
+                probe_counters.add_decoded();
+                let pts = match info.data {
+                    Some(gst::PadProbeData::Buffer(ref buffer)) => buffer.get_pts().nseconds(),
+                    _ => None,
+                };
+                let pass = probe_guard.on_output(pts);
+                if probe_guard.retry_request() {
+                    request_key_frame(&depay_sink);
+                }
+                if pass {
+                    gst::PadProbeReturn::Ok
+                } else {
+                    gst::PadProbeReturn::Drop
+                }
+            });
 
-        let decodebin = gst::parse_bin_from_description(&pipeline_description, true)
-            .expect("Failed to parse decodebin");
+        let mut shared = self.shared.lock().unwrap();
+        if log::log_enabled!(log::Level::Trace) {
+            let timer =
+                ElementTimer::new(&format!("decoder-convert{}", mlineidx), decoder, convert);
+            shared.timers.push(timer);
+        }
+        shared.streams.insert(
+            mlineidx as usize,
+            DecodeStream {
+                pad: pad.clone(),
+                bin: decodebin,
+                codec,
+                counters,
+                guard,
+                recording: None,
+                rebuilding: None,
+            },
+        );
+        let recording = shared.recording;
+        drop(shared);
+
+        self.apply_stream_network(mlineidx as usize);
+        if recording {
+            self.start_recording(mlineidx as usize);
+        }
+        Ok(())
+    }
 
+    // Hands the decoded frames of the bin on to the views.
+    fn setup_appsink(&self, decodebin: &gst::Bin, mlineidx: u32, rtp_times: Arc<RtpTimes>) {
         let appsink_name = format!("appsink{}", mlineidx);
         let appsink = decodebin
             .get_by_name(&appsink_name)
@@ -324,6 +686,10 @@ impl App {
                 .new_sample(move |appsink| {
                     if let Some(app) = weak_app.upgrade().map(App) {
                         let sample = appsink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
+                        let rtp_time = sample
+                            .get_buffer()
+                            .and_then(|buffer| buffer.get_pts().nseconds())
+                            .and_then(|pts| rtp_times.lookup(pts));
                         let mut shared = app.shared.lock().unwrap();
                         // Set the sample in the slot for the mlineidx.
                         shared.samples.insert(
@@ -332,6 +698,7 @@ impl App {
                                 sample,
                                 id: mlineidx as _,
                                 timer: std::time::Instant::now(),
+                                rtp_time,
                             }),
                         );
 
@@ -372,38 +739,251 @@ impl App {
             .field("texture-target", &"2D")
             .build();
         appsink.set_caps(Some(&caps));
+    }
 
-        self.pipeline
-            .add(&decodebin)
-            .expect("Failed to add decodebin element to pipeline");
-
+    // Adds the bin to the pipeline, links it to the pad and starts it.
+    // A decoder that fails to start is removed again.
+    fn start_decode_bin(&self, pad: &gst::Pad, decodebin: &gst::Bin) -> Result<()> {
+        self.pipeline.add(decodebin)?;
         let sinkpad = decodebin
             .get_static_pad("sink")
             .expect("Failed to get sink pad of decodebin");
-        pad.link(&sinkpad)
-            .expect("Failed to link incomming stream to decodebin");
+        let started = pad
+            .link(&sinkpad)
+            .map_err(anyhow::Error::from)
+            .and_then(|_| Ok(decodebin.sync_state_with_parent()?));
+        if started.is_err() {
+            let _ = pad.unlink(&sinkpad);
+            let _ = decodebin.set_state(gst::State::Null);
+            let _ = self.pipeline.remove(decodebin);
+        }
+        started
+    }
 
-        decodebin
-            .sync_state_with_parent()
-            .expect("Failed to sync decodebin with parent");
+    fn start_recording(&self, idx: usize) {
+        let (bin, codec) = match self.shared.lock().unwrap().streams.get(&idx) {
+            Some(stream) if stream.recording.is_none() => (stream.bin.clone(), stream.codec),
+            _ => return,
+        };
+        // Not under the lock, errors from the new elements go through the bus handler.
+        let recording = match Recording::start(&bin, idx, codec, &self.recording) {
+            Ok(recording) => recording,
+            Err(e) => {
+                log::error!("Failed to start recording stream {}: {:?}", idx, e);
+                return;
+            }
+        };
+        let state_log = recording.path().with_extension("json");
+        let stale = {
+            let mut shared = self.shared.lock().unwrap();
+            match shared.streams.get_mut(&idx) {
+                Some(stream) if stream.bin == bin => {
+                    stream.recording = Some(recording);
+                    None
+                }
+                // The stream was rebuilt meanwhile.
+                _ => Some(recording),
+            }
+        };
+        if let Some(recording) = stale {
+            recording.stop(&bin);
+            return;
+        }
+        self.send_window_message(WindowMessage::RecordingStarted(idx, state_log));
+    }
 
-        let depay = decodebin
-            .get_by_name(&format!("decoder{}", mlineidx))
-            .expect("Failed to get decoder");
-        let convert = decodebin
-            .get_by_name(&format!("convert{}", mlineidx))
-            .expect("Failed to get convert");
+    fn stop_recording(&self, idx: usize) {
+        let recording = {
+            let mut shared = self.shared.lock().unwrap();
+            shared
+                .streams
+                .get_mut(&idx)
+                .and_then(|s| s.recording.take().map(|r| (r, s.bin.clone())))
+        };
+        if let Some((recording, bin)) = recording {
+            recording.stop(&bin);
+        }
+    }
 
-        if log::log_enabled!(log::Level::Trace) {
-            let timer = ElementTimer::new(&format!("decoder-convert{}", mlineidx), depay, convert);
+    pub fn is_recording(&self) -> bool {
+        self.shared.lock().unwrap().recording
+    }
+
+    /// Starts or stops recording all streams, each to its own file.
+    pub fn set_recording(&self, enabled: bool) {
+        let streams: Vec<usize> = {
+            let mut shared = self.shared.lock().unwrap();
+            shared.recording = enabled;
+            shared.streams.keys().copied().collect()
+        };
+        for idx in streams {
+            if enabled {
+                self.start_recording(idx);
+            } else {
+                self.stop_recording(idx);
+            }
+        }
+    }
+
+    fn stream_jitter_buffer(&self, idx: usize) -> Option<gst::Element> {
+        let shared = self.shared.lock().unwrap();
+        let ssrc = stream_ssrc(&shared.streams.get(&idx)?.pad)?;
+        shared.jitter_buffers.get(&ssrc).cloned()
+    }
+
+    fn apply_stream_network(&self, idx: usize) {
+        // The jitterbuffer of a stream is only known once the stream is linked.
+        let config = self.network.lock().unwrap().stream(idx);
+        match self.stream_jitter_buffer(idx) {
+            Some(jitter_buffer) => config.apply(&jitter_buffer),
+            None => log::warn!("No jitterbuffer found for stream {}", idx),
+        }
+    }
+
+    /// The jitterbuffer latency in ms, of a stream or the default for all streams.
+    pub fn latency(&self, stream: Option<usize>) -> u32 {
+        let network = self.network.lock().unwrap();
+        match stream {
+            Some(idx) => network.stream(idx).latency.unwrap_or(network.latency),
+            None => network.latency,
+        }
+    }
+
+    /// Changes the jitterbuffer latency of a stream, or of all streams if `stream` is None.
+    pub fn set_latency(&self, stream: Option<usize>, latency: u32) {
+        log::info!(
+            "Setting jitter buffer latency of {:?} to {}",
+            stream,
+            latency
+        );
+        match stream {
+            Some(idx) => {
+                {
+                    let mut network = self.network.lock().unwrap();
+                    network.streams.entry(idx).or_default().latency = Some(latency);
+                }
+                self.apply_stream_network(idx);
+            }
+            None => {
+                {
+                    let mut network = self.network.lock().unwrap();
+                    network.latency = latency;
+                    network.streams.values_mut().for_each(|s| s.latency = None);
+                }
+                // rtpbin passes the latency on to all of its jitterbuffers.
+                self.pipeline
+                    .get_by_name("rtpbin")
+                    .expect("Failed to get rtpbin")
+                    .set_property("latency", &latency)
+                    .expect("Failed to set jitter buffer latency");
+            }
+        }
+    }
+
+    fn on_stream_removed(&self, pad: &gst::Pad) {
+        let idx = {
+            let shared = self.shared.lock().unwrap();
+            shared
+                .streams
+                .iter()
+                .find(|(_, stream)| stream.pad == *pad)
+                .map(|(idx, _)| *idx)
+        };
+        // Sink pads and streams we failed to link have nothing to tear down.
+        if let Some(idx) = idx {
+            log::info!("Stream {} was removed, tearing down its decode bin", idx);
+            self.remove_decode_bin(idx);
             {
                 let mut shared = self.shared.lock().unwrap();
-                shared.timers.push(timer);
+                shared.stream_decoders.remove(&idx);
             }
+            self.send_window_message(WindowMessage::StreamRemoved(idx));
         }
+    }
+
+    fn stream_for_element(&self, element: &gst::Object) -> Option<usize> {
+        // Find the decode bin that the element belongs to.
+        let shared = self.shared.lock().unwrap();
+        shared
+            .streams
+            .iter()
+            .find(|(_, stream)| element.has_as_ancestor(&stream.bin))
+            .map(|(idx, _)| *idx)
+    }
+
+    fn remove_decode_bin(&self, idx: usize) -> Option<DecodeStream> {
+        let mut stream = {
+            let mut shared = self.shared.lock().unwrap();
+            let timer_name = format!("decoder-convert{}", idx);
+            shared.timers.retain(|t| t.name() != timer_name);
+            shared.samples.remove(&idx);
+            shared.streams.remove(&idx)
+        }?;
+
+        if let Some(recording) = stream.recording.take() {
+            recording.stop(&stream.bin);
+        }
+        if let Some(sinkpad) = stream.bin.get_static_pad("sink") {
+            let _ = stream.pad.unlink(&sinkpad);
+        }
+        let _ = stream.bin.set_state(gst::State::Null);
+        let _ = self.pipeline.remove(&stream.bin);
+        Some(stream)
+    }
+
+    /// Replaces the decode bin of a stream with a new one, linked to the same webrtcbin pad.
+    /// The bins are swapped from the streaming thread once the pad is blocked, so the
+    /// jitterbuffer never pushes into an unlinked pad.
+    pub fn rebuild_stream(&self, idx: usize) -> Result<()> {
+        let pad = {
+            let mut shared = self.shared.lock().unwrap();
+            let stream = shared
+                .streams
+                .get_mut(&idx)
+                .ok_or_else(|| anyhow::anyhow!("No decode bin for stream {}", idx))?;
+            if stream.rebuilding.is_some() {
+                return Ok(());
+            }
+            stream.rebuilding = Some(Instant::now());
+            stream.pad.clone()
+        };
+        log::info!("Rebuilding decode bin for stream {}", idx);
+        let weak_app = Arc::downgrade(&self.0);
+        pad.add_probe(gst::PadProbeType::BLOCK_DOWNSTREAM, move |pad, _info| {
+            if let Some(app) = weak_app.upgrade().map(App) {
+                app.remove_decode_bin(idx);
+                if let Err(e) = app.link_decode_bin(pad, idx as u32) {
+                    log::error!("Failed to rebuild stream {}: {:?}", idx, e);
+                    if let Some(stream) = app.shared.lock().unwrap().streams.get_mut(&idx) {
+                        stream.rebuilding = None;
+                    }
+                    app.send_window_message(WindowMessage::PipelineError(None));
+                }
+            }
+            // Unblocks the pad, the data goes on to the new bin.
+            gst::PadProbeReturn::Remove
+        })
+        .ok_or_else(|| anyhow::anyhow!("Failed to block the pad of stream {}", idx))?;
         Ok(())
     }
 
+    /// Returns true if a stream has waited longer than `timeout` for its decode bin to be swapped.
+    /// The probe only runs when a buffer arrives, a stalled stream never gets one.
+    pub fn rebuild_stalled(&self, timeout: Duration) -> bool {
+        let shared = self.shared.lock().unwrap();
+        shared
+            .streams
+            .values()
+            .filter_map(|stream| stream.rebuilding)
+            .any(|started| started.elapsed() > timeout)
+    }
+
+    /// Closes the session with the server, the signalling reconnects and the session is rebuilt.
+    pub fn restart_session(&self) -> Result<()> {
+        log::warn!("Restarting the session");
+        self.send_app_message(AppMessage::Close)
+    }
+
     pub fn get_sample(&self, index: usize) -> Option<ViewSample> {
         let mut shared = self.shared.lock().unwrap();
         if let Some(sample) = shared.samples.get_mut(&index) {
@@ -550,12 +1130,93 @@ impl App {
     }
 
     fn send_app_message(&self, msg: AppMessage) -> Result<()> {
-        self.signaller.unbounded_send(msg).map_err(|e| e.into())
+        self.signaller
+            .unbounded_send((self.session, msg))
+            .map_err(|e| e.into())
+    }
+
+    fn supported_codecs(&self) -> Vec<String> {
+        let decoders = self.decoders.lock().unwrap();
+        Codec::ALL
+            .iter()
+            .filter(|codec| codec.is_available() && decoders.contains(**codec))
+            .map(|codec| codec.encoding_name().to_owned())
+            .collect()
+    }
+
+    fn on_stream_corrupt(&self, idx: usize) {
+        let stream = {
+            let shared = self.shared.lock().unwrap();
+            shared
+                .streams
+                .get(&idx)
+                .map(|s| (s.guard.clone(), s.bin.clone()))
+        };
+        if let Some((guard, bin)) = stream {
+            let pad = bin
+                .get_by_name(&format!("depay{}", idx))
+                .and_then(|depay| depay.get_static_pad("sink"));
+            if let (true, Some(pad)) = (guard.mark_corrupt(), pad) {
+                request_key_frame(&pad);
+            }
+        }
+    }
+
+    fn stream_counters(&self, idx: usize) -> Option<Arc<FrameCounters>> {
+        let shared = self.shared.lock().unwrap();
+        shared.streams.get(&idx).map(|s| s.counters.clone())
+    }
+
+    fn request_webrtc_stats(&self) {
+        // The reply comes back on a webrtcbin thread, it is used at the next collection.
+        let weak_app = Arc::downgrade(&self.0);
+        let promise = gst::Promise::with_change_func(move |reply| {
+            if let (Some(app), Ok(Some(reply))) = (weak_app.upgrade().map(App), reply) {
+                let mut shared = app.shared.lock().unwrap();
+                shared.webrtc_stats = Some(reply.to_owned());
+            }
+        });
+        self.webrtcbin
+            .emit("get-stats", &[&None::<gst::Pad>, &promise])
+            .expect("Failed to emit get-stats signal");
     }
 
-    fn connect(&self, cfg: Vec<ClientConfig>) {
+    fn collect_stats(&self, collector: &mut StatsCollector) -> SessionStats {
+        self.request_webrtc_stats();
+
+        let shared = self.shared.lock().unwrap();
+        let sources = shared
+            .streams
+            .iter()
+            .map(|(idx, stream)| {
+                let ssrc = stream_ssrc(&stream.pad);
+                let jitter_buffer = ssrc
+                    .and_then(|ssrc| shared.jitter_buffers.get(&ssrc))
+                    .and_then(|jb| jb.get_property("stats").ok())
+                    .and_then(|stats| stats.get::<gst::Structure>().ok().flatten())
+                    .map(|stats| JitterStats::from_structure(&stats));
+                StreamSource {
+                    stream: *idx,
+                    ssrc,
+                    decoder: shared.stream_decoders.get(idx).cloned(),
+                    jitter_buffer,
+                    counters: stream.counters.clone(),
+                    guard: stream.guard.clone(),
+                }
+            })
+            .collect();
+        collector.collect(sources, shared.webrtc_stats.as_deref())
+    }
+
+    fn connect(&self, mut cfg: Vec<ClientConfig>) {
         // Get the config from the views, and connect
         // let cfg = self.view_control.get_config();
+
+        // Let the server know which codecs we can decode.
+        let codecs = self.supported_codecs();
+        for c in cfg.iter_mut() {
+            c.codecs = codecs.clone();
+        }
         log::info!("Connecting with {:?}", &cfg);
 
         let msg = AppMessage::Connect(cfg);
@@ -564,12 +1225,18 @@ impl App {
     }
 
     fn create_shared_context(
-        ctx: RawContext<NotCurrent>,
-    ) -> (RawContext<NotCurrent>, gst_gl::GLContext, gst_gl::GLDisplay) {
+        ctx: MainContext<NotCurrent>,
+        native_display: Option<usize>,
+    ) -> (
+        MainContext<NotCurrent>,
+        gst_gl::GLContext,
+        gst_gl::GLDisplay,
+    ) {
         let ctx = unsafe { ctx.make_current().expect("Failed to make context current") };
 
         // Build gstreamer sharable context
         let (gl_context, gl_display, platform) = match unsafe { ctx.raw_handle() } {
+            #[cfg(windows)]
             RawHandle::Wgl(wgl_context) => {
                 let gl_display = gst_gl::GLDisplay::new();
                 (
@@ -578,6 +1245,33 @@ impl App {
                     gst_gl::GLPlatform::WGL,
                 )
             }
+            #[cfg(target_os = "linux")]
+            RawHandle::Glx(glx_context) => {
+                // GLX contexts belong to the X connection of the window, so GStreamer
+                // has to use the same connection.
+                let display = native_display.expect("GLX context without an X11 display");
+                let gl_display = unsafe { gst_gl::GLDisplayX11::with_display(display) }
+                    .expect("Failed to wrap X11 display");
+                (
+                    glx_context as usize,
+                    gl_display.upcast::<gst_gl::GLDisplay>(),
+                    gst_gl::GLPlatform::GLX,
+                )
+            }
+            #[cfg(target_os = "linux")]
+            RawHandle::Egl(egl_context) => {
+                // Used both for EGL on X11 and on Wayland.
+                let egl_display =
+                    unsafe { ctx.get_egl_display() }.expect("EGL context without an EGL display");
+                let gl_display =
+                    unsafe { gst_gl::GLDisplayEGL::with_egl_display(egl_display as usize) }
+                        .expect("Failed to wrap EGL display");
+                (
+                    egl_context as usize,
+                    gl_display.upcast::<gst_gl::GLDisplay>(),
+                    gst_gl::GLPlatform::EGL,
+                )
+            }
             #[allow(unreachable_patterns)]
             handler => panic!("Unsupported platform: {:?}.", handler),
         };
@@ -660,16 +1354,16 @@ impl App {
     }
 
     fn finalize_contexts(
-        ctx: RawContext<NotCurrent>,
+        ctx: MainContext<NotCurrent>,
         own_context: gst_gl::GLContext,
         pipe_context: gst_gl::GLContext,
         inner_size: (u32, u32),
-    ) -> (RawContext<PossiblyCurrent>, GlRenderer) {
+    ) -> (MainContext<PossiblyCurrent>, GlRenderer) {
         // Current the context
         let main_context = unsafe { ctx.make_current().expect("Failed to current context") };
         log::debug!(
             "Using window with settings: {:?}",
-            main_context.get_pixel_format()
+            main_context.pixel_format()
         );
 
         log::debug!("Main context has been currented");
@@ -681,13 +1375,25 @@ impl App {
         // Get the size of the window
         // let inner_size = main_context.window().inner_size();
         renderer.set_window_size((inner_size.0, inner_size.1));
+        // Render into a framebuffer object, headless there is no window to present to.
+        // A window only redraws the views that changed, the rest is kept between frames.
+        if main_context.is_headless() {
+            if let Err(e) = renderer.enable_offscreen() {
+                log::error!("No offscreen target, no frames will be rendered: {:?}", e);
+            }
+        } else if let Err(e) = renderer.enable_retained() {
+            log::warn!("Redrawing the whole window on every frame: {:?}", e);
+        }
         (main_context, renderer)
     }
 
     pub fn start(
         self,
         config: AppConfig,
-        context: RawContext<NotCurrent>,
+        context: MainContext<NotCurrent>,
+        native_display: Option<usize>,
+        frame_sender: Option<Sender<Frame>>,
+        stats: StatsHandle,
     ) -> (
         Sender<Event<'static, WindowMessage>>,
         std::thread::JoinHandle<()>,
@@ -695,7 +1401,15 @@ impl App {
         let (snd, rcv) = channel();
         let messages = snd.clone();
         let handle = std::thread::spawn(move || {
-            self.main_loop(config, context, snd, rcv);
+            self.main_loop(
+                config,
+                context,
+                native_display,
+                frame_sender,
+                stats,
+                snd,
+                rcv,
+            );
         });
 
         (messages, handle)
@@ -704,11 +1418,16 @@ impl App {
     fn main_loop(
         self,
         config: AppConfig,
-        main_context: RawContext<NotCurrent>,
+        main_context: MainContext<NotCurrent>,
+        native_display: Option<usize>,
+        frame_sender: Option<Sender<Frame>>,
+        stats: StatsHandle,
         snd: Sender<Event<'static, WindowMessage>>,
         rcv: Receiver<Event<'static, WindowMessage>>,
     ) {
         log::debug!("Starting app main loop on current thread");
+        // The App is replaced if the session is rebuilt after a reconnect.
+        let mut app = self;
 
         let mut view_control = ViewControl::new(&config);
         view_control.partition(1, 1);
@@ -742,37 +1461,73 @@ impl App {
         // Start a repeat timer that fires with the request timeout
         let duration = Duration::from_millis(1);
         timer.repeat(WindowMessage::Timer(duration), duration);
-        // Start a timer that traces JitterBuffer statistics
-        timer.repeat(WindowMessage::JitterStats, Duration::from_millis(1000));
+        // Start a timer that collects stream statistics
+        timer.repeat(WindowMessage::Stats, Duration::from_millis(1000));
 
         // let _handle = std::thread::spawn(move || {
         // Create GStreamer context
-        let (main_context, own_context, shared_display) = Self::create_shared_context(main_context);
+        let (main_context, own_context, shared_display) =
+            Self::create_shared_context(main_context, native_display);
 
-        self.set_message_sender(message_sender);
-        self.set_shared_context(own_context.clone(), shared_display);
+        app.set_message_sender(message_sender.clone());
+        app.set_shared_context(own_context.clone(), shared_display.clone());
+        // Keep a handle to the shared context in case we need to rebuild the pipeline.
+        let shared_context = own_context.clone();
 
         // Start the pipeline
-        self.pipeline
+        app.pipeline
             .set_state(gst::State::Playing)
             .expect("Failed to set the pipeline to playing");
 
         // Connect to server
-        self.connect(view_control.get_config());
+        app.connect(view_control.get_config());
 
         // We really need to ensure that connect() has been handled before we send another
         // ws-request, otherwise the server might error out.
-        self.send_app_message(AppMessage::GetCases)
+        app.send_app_message(AppMessage::GetCases)
             .expect("Failed to send GetCases");
         // This is the context until we have the first sample, then we know
         // that context sharing is done and we can current the context.
         let mut tmp_ctx = Some(main_context);
-        let mut main_context: Option<RawContext<PossiblyCurrent>> = None;
+        let mut main_context: Option<MainContext<PossiblyCurrent>> = None;
         let mut renderer: Option<GlRenderer> = None;
+        let mut scaling_filter = config.scaling_filter;
+        let mut display = config.display;
         let mut own_context: Option<gst_gl::GLContext> = Some(own_context);
+        // Set when the pipeline was rebuilt and the renderer needs the new pipe context.
+        let mut pipe_context_stale = false;
+        // Set when we wait for cases to restore the session after a reconnect.
+        let mut resuming = false;
+        let mut recovery = RecoveryTracker::new(MAX_STREAM_RECOVERIES, STREAM_RECOVERY_WINDOW);
+        // Set while waiting for the signalling to reconnect after closing the session.
+        let mut restarting = false;
+        let mut stats_collector = StatsCollector::new();
+        let mut stats_csv = config
+            .stats_csv
+            .as_ref()
+            .and_then(|path| match StatsCsv::open(path) {
+                Ok(csv) => Some(csv),
+                Err(e) => {
+                    log::error!("Failed to open stats file {}: {:?}", path, e);
+                    None
+                }
+            });
 
         let mut layout_pending = false;
-        'main_loop: for event in rcv.iter() {
+        // Age of the frames at present, since the last stats collection.
+        let mut present_ages = Vec::new();
+        // An event taken from the queue while deciding whether to render.
+        let mut next_event = None;
+        // Since when a render waits for the events queued behind it.
+        let mut render_pending: Option<Instant> = None;
+        'main_loop: loop {
+            let event = match next_event.take() {
+                Some(event) => event,
+                None => match rcv.recv() {
+                    Ok(event) => event,
+                    Err(_) => break,
+                },
+            };
             // The actual rendering seems not dependant on this loop.
             // So we can wait for new events.
 
@@ -787,32 +1542,49 @@ impl App {
                         println!("Known cases:\n{}", view_control.get_case_string());
                         println!("Known protocols:\n{}", view_control.get_protocol_string());
 
-                        view_control.select_default_display();
+                        if resuming {
+                            resuming = false;
+                            view_control.resume_session();
+                        } else {
+                            view_control.select_default_display();
+                        }
                     }
                     WindowMessage::Datachannel(datachannel) => {
                         view_control.set_datachannel(datachannel);
                     }
+                    WindowMessage::Rendered(data_id, seq, rtp_time) => {
+                        view_control.set_rendered(&data_id, seq, rtp_time);
+                    }
                     WindowMessage::Sample(index) => {
                         // When we get the first sample we can current our context
                         // and build the renderer, since now context-sharing should
                         // be set up.
                         if let Some(ctx) = tmp_ctx.take() {
                             // Move the tmp_ctx into the main context after setting it current
-                            let (context, gl_rend) = Self::finalize_contexts(
+                            let (context, mut gl_rend) = Self::finalize_contexts(
                                 ctx,
                                 own_context.take().expect("Context is empty"),
-                                self.get_pipe_context(index),
+                                app.get_pipe_context(index),
                                 window_size,
                             );
+                            gl_rend.set_scaling_filter(scaling_filter);
+                            gl_rend.set_display(display);
+                            gl_rend.set_scale_factor(view_control.scale_factor());
+                            gl_rend.set_text_style(config.text_style.clone());
                             // Assign the instances that we will use through out.
                             main_context = Some(context);
                             renderer = Some(gl_rend);
+                        } else if pipe_context_stale {
+                            pipe_context_stale = false;
+                            if let Some(r) = renderer.as_mut() {
+                                r.set_pipe_context(app.get_pipe_context(index));
+                            }
                         }
 
                         log::trace!("Main loop got a sample");
 
                         // Get the latest sample for view 'index'
-                        self.get_sample(index)
+                        app.get_sample(index)
                             .map(|sample| view_control.push_sample(sample));
 
                         // Request a redraw
@@ -825,7 +1597,9 @@ impl App {
                         // Let the control react to timer events.
                         view_control.handle_timer_event();
 
-                        view_control.push_state();
+                        // Move the frame on screen along with the new state.
+                        do_render = view_control.push_state();
+                        do_render |= view_control.expire_notice();
                     }
                     WindowMessage::UpdateLayout => {
                         layout_pending = false;
@@ -837,22 +1611,137 @@ impl App {
                             height: window_size.1,
                         });
                     }
-                    WindowMessage::PipelineError => {
-                        log::error!("Got error from pipeline, exiting");
-                        // *flow = ControlFlow::Exit;
-                        break 'main_loop;
-                    }
-                    WindowMessage::JitterStats => {
-                        self.pipeline.get_by_name("rtpjitterbuffer0").map(|e| {
-                            let gst_stats = e.get_property("stats").expect("Failed to get stats");
-                            let stats = gst_stats
-                                .get::<&gst::StructureRef>()
-                                .expect("Failed to cast to StructureRef")
-                                .expect("StructureRef is empty");
-
-                            let jitter_stats = to_jitter_stats(stats);
-                            log::trace!("{:?}", jitter_stats);
+                    WindowMessage::ScaleFactor(scale_factor, size) => {
+                        log::info!("Window scale factor {}, size {:?}", scale_factor, size);
+                        view_control.set_scale_factor(scale_factor);
+                        if let Some(r) = renderer.as_mut() {
+                            r.set_scale_factor(scale_factor);
+                            r.set_window_size(size);
+                        }
+                        if let Some(c) = main_context.as_ref() {
+                            c.resize(PhysicalSize::new(size.0, size.1));
+                        }
+                        // Moved to another monitor, lay out the views right away
+                        // so the pointer hits the panes under it.
+                        window_size = size;
+                        view_control.set_window_size(size);
+                        view_control.set_layout(LayoutRect {
+                            x: 0,
+                            y: 0,
+                            width: size.0,
+                            height: size.1,
                         });
+                        do_render = true;
+                    }
+                    WindowMessage::Reconnected => {
+                        log::info!("Rebuilding session after reconnect");
+                        // Samples and datachannels from the old pipeline are no longer valid.
+                        view_control.reset_session();
+
+                        let recording = app.is_recording();
+                        app = app.new_session();
+                        app.set_message_sender(message_sender.clone());
+                        app.set_shared_context(shared_context.clone(), shared_display.clone());
+                        app.pipeline
+                            .set_state(gst::State::Playing)
+                            .expect("Failed to set the pipeline to playing");
+                        pipe_context_stale = renderer.is_some();
+
+                        // Connect again, the panes are restored once we get the cases.
+                        app.connect(view_control.get_config());
+                        app.send_app_message(AppMessage::GetCases)
+                            .expect("Failed to send GetCases");
+                        resuming = true;
+                        restarting = false;
+                        recovery.reset();
+                        // Keep recording in the new session.
+                        app.set_recording(recording);
+                    }
+                    WindowMessage::StreamRemoved(idx) => {
+                        // Drop the last sample, its texture belongs to the removed decode bin.
+                        view_control.clear_stream(idx);
+                        do_render = true;
+                    }
+                    WindowMessage::PipelineError(stream) => {
+                        if restarting {
+                            // The old session is going away, ignore its errors.
+                            continue;
+                        }
+                        // Try to rebuild the failing stream, otherwise restart the session.
+                        let recovered = match stream {
+                            Some(idx) if recovery.try_attempt(idx) => {
+                                view_control.set_recovering(Some(idx));
+                                match app.rebuild_stream(idx) {
+                                    Ok(_) => true,
+                                    Err(e) => {
+                                        log::error!("Failed to rebuild stream {}: {:?}", idx, e);
+                                        false
+                                    }
+                                }
+                            }
+                            _ => false,
+                        };
+                        if !recovered {
+                            view_control.set_recovering(None);
+                            match app.restart_session() {
+                                Ok(_) => restarting = true,
+                                Err(e) => log::error!("Failed to close the session: {:?}", e),
+                            }
+                        }
+                        do_render = true;
+                    }
+                    WindowMessage::SetLatency(stream, latency) => {
+                        app.set_latency(stream, latency);
+                    }
+                    WindowMessage::SetRecording(enabled) => {
+                        app.set_recording(enabled);
+                        if !enabled {
+                            view_control.stop_state_logs();
+                        }
+                    }
+                    WindowMessage::Snapshot(view, pane) => {
+                        view_control.request_snapshot(view, pane);
+                    }
+                    WindowMessage::SetDisplay(settings) => {
+                        display = settings;
+                        if let Some(r) = renderer.as_mut() {
+                            r.set_display(display);
+                        }
+                        do_render = true;
+                    }
+                    WindowMessage::RecordingStarted(idx, path) => {
+                        // Log the states sent for the view next to its recording.
+                        view_control.start_state_log(idx, &path);
+                    }
+                    WindowMessage::Stats => {
+                        let mut session_stats = app.collect_stats(&mut stats_collector);
+                        session_stats.present_latency = LatencySummary::from_ages(&present_ages);
+                        present_ages.clear();
+                        if let Some(latency) = &session_stats.present_latency {
+                            log::debug!(
+                                "Presented {} frames, age {}",
+                                latency.frames,
+                                latency.overlay_line()
+                            );
+                        }
+                        log::trace!("{:?}", session_stats);
+                        if let Some(csv) = stats_csv.as_mut() {
+                            if let Err(e) = csv.append(&session_stats) {
+                                log::error!("Failed to write stats: {:?}", e);
+                            }
+                        }
+                        // Redraw if a view shows the stats overlay.
+                        do_render = view_control.set_stats(&session_stats);
+                        stats.publish(session_stats);
+                        if !restarting && app.rebuild_stalled(STREAM_REBUILD_TIMEOUT) {
+                            log::warn!("A stream rebuild got no data, restarting the session");
+                            view_control.set_recovering(None);
+                            do_render = true;
+                            match app.restart_session() {
+                                Ok(_) => restarting = true,
+                                Err(e) => log::error!("Failed to close the session: {:?}", e),
+                            }
+                        }
                     }
                 },
                 Event::WindowEvent { event, .. } => {
@@ -882,12 +1771,62 @@ impl App {
                             log::debug!("Got close message");
                             break 'main_loop;
                         }
+                        WindowEvent::KeyboardInput { input, .. }
+                            if input.state == ElementState::Pressed =>
+                        {
+                            // -/+ changes the jitterbuffer latency of all streams.
+                            let step = match input.virtual_keycode {
+                                Some(VirtualKeyCode::Minus) => Some(-LATENCY_STEP),
+                                Some(VirtualKeyCode::Equals) => Some(LATENCY_STEP),
+                                _ => None,
+                            };
+                            if let Some(step) = step {
+                                let latency = (app.latency(None) as i32 + step).max(0);
+                                app.set_latency(None, latency as u32);
+                                true
+                            } else if input.virtual_keycode == Some(VirtualKeyCode::R) {
+                                // R starts and stops recording.
+                                snd.send(WindowMessage::SetRecording(!app.is_recording()).into())
+                                    .expect("Failed to send recording message");
+                                true
+                            } else if input.virtual_keycode == Some(VirtualKeyCode::F) {
+                                // F cycles through the scaling filters.
+                                scaling_filter = scaling_filter.next();
+                                log::info!("Using the {} scaling filter", scaling_filter.name());
+                                view_control
+                                    .show_notice(format!("Filter: {}", scaling_filter.name()));
+                                if let Some(r) = renderer.as_mut() {
+                                    r.set_scaling_filter(scaling_filter);
+                                }
+                                do_render = true;
+                                true
+                            } else if input.virtual_keycode == Some(VirtualKeyCode::O) {
+                                // O shows and hides the info overlay of the panes.
+                                view_control.toggle_info();
+                                do_render = true;
+                                true
+                            } else if display_key(&mut display, input.virtual_keycode) {
+                                log::info!("Display settings {:?}", display);
+                                if let Some(r) = renderer.as_mut() {
+                                    r.set_display(display);
+                                }
+                                do_render = true;
+                                true
+                            } else {
+                                false
+                            }
+                        }
                         _ => false,
                     };
 
                     if !handled {
                         // Let the views handle the event
+                        let decorations = view_control.decoration_state();
                         view_control.handle_window_event(&event);
+                        if view_control.decoration_state() != decorations {
+                            // The focus moved or a pane toggled sync or cine.
+                            do_render = true;
+                        }
                     }
 
                     // Check if we should hide the cursor.
@@ -901,6 +1840,10 @@ impl App {
                     // }
                     view_control.update_focused();
                 }
+                Event::LoopDestroyed => {
+                    log::debug!("Event loop destroyed");
+                    break 'main_loop;
+                }
                 Event::RedrawRequested(_) => {
                     // Render the views
                     do_render = true;
@@ -915,41 +1858,104 @@ impl App {
                 _ => (),
             }
 
+            // Load the measurements of new cases and store edited ones.
+            if view_control.sync_measurements() {
+                do_render = true;
+            }
+
+            // Save the snapshots whose frames have arrived.
+            if let Some(r) = renderer.as_ref() {
+                for (snapshot, sample) in view_control.take_snapshots() {
+                    let frame = match r.read_sample(&sample.sample) {
+                        Some(frame) => frame,
+                        None => {
+                            log::error!("Failed to read back the snapshot frame");
+                            continue;
+                        }
+                    };
+                    // Encoding the PNG takes a while, don't hold up rendering.
+                    let dir = config.recording.dir.clone();
+                    std::thread::spawn(move || match snapshot.save(frame, &dir) {
+                        Ok(path) => log::info!("Saved snapshot {}", path.display()),
+                        Err(e) => log::error!("Failed to save snapshot: {:?}", e),
+                    });
+                }
+            }
+
+            do_render = do_render || render_pending.is_some();
+            if do_render && config.present_mode == PresentMode::LatestAtVsync {
+                // Handle what queued up while the last swap waited for the vertical blank,
+                // so only the latest frame is rendered.
+                let since = *render_pending.get_or_insert_with(Instant::now);
+                if since.elapsed() < MAX_RENDER_DEFER {
+                    if let Ok(event) = rcv.try_recv() {
+                        next_event = Some(event);
+                        continue;
+                    }
+                }
+            }
             if do_render {
-                renderer.as_mut().map(|r| {
-                    r.render_views(&view_control);
-                });
-                // Swap back buffer
-                main_context
-                    .as_ref()
-                    .map(|c| c.swap_buffers().expect("Failed to swap back-buffer"));
+                render_pending = None;
+                let damage = renderer
+                    .as_mut()
+                    .map_or(Damage::Window, |r| r.render_views(&view_control));
+                log::trace!("Rendered with damage {:?}", damage);
+                // Swap back buffer, unless nothing changed.
+                if let Some(c) = main_context.as_ref() {
+                    match &damage {
+                        Damage::None => {}
+                        Damage::Views(rects) => c
+                            .swap_buffers_with_damage(rects, window_size)
+                            .expect("Failed to swap back-buffer"),
+                        Damage::Window => c.swap_buffers().expect("Failed to swap back-buffer"),
+                    }
+                }
+                // The age of the new frames, from the arrival of their samples.
+                for arrived in view_control.take_unpresented() {
+                    if present_ages.len() < MAX_PRESENT_AGES {
+                        present_ages.push(arrived.elapsed());
+                    }
+                }
+
+                // Hand out the offscreen frame when running headless, if it changed.
+                let sender = frame_sender.as_ref().filter(|_| damage != Damage::None);
+                if let (Some(r), Some(sender)) = (renderer.as_ref(), sender) {
+                    if let Some(frame) = r.read_frame() {
+                        if sender.send(frame).is_err() {
+                            log::debug!("Frame receiver is gone");
+                        }
+                    }
+                }
             }
         }
     }
 }
-#[derive(Debug)]
-struct JitterStats {
-    num_pushed: u64,
-    num_lost: u64,
-    num_late: u64,
-    num_duplicates: u64,
-    avg_jitter: u64,
-    rtx_count: u64,
-    rtx_success_count: u64,
-    rtx_per_packet: f64,
-    rtx_rtt: u64,
+// G toggles grey display, T grey clamping, [ ] change the gamma and , . the contrast.
+fn display_key(display: &mut DisplaySettings, key: Option<VirtualKeyCode>) -> bool {
+    match key {
+        Some(VirtualKeyCode::G) => display.toggle_mode(),
+        Some(VirtualKeyCode::T) => display.grey_clamp = !display.grey_clamp,
+        Some(VirtualKeyCode::LBracket) => display.adjust_gamma(-DISPLAY_STEP),
+        Some(VirtualKeyCode::RBracket) => display.adjust_gamma(DISPLAY_STEP),
+        Some(VirtualKeyCode::Comma) => display.adjust_contrast(-DISPLAY_STEP),
+        Some(VirtualKeyCode::Period) => display.adjust_contrast(DISPLAY_STEP),
+        _ => return false,
+    }
+    true
+}
+
+fn stream_ssrc(pad: &gst::Pad) -> Option<u32> {
+    // The webrtcbin src pad targets the rtpbin pad recv_rtp_src_{session}_{ssrc}_{pt}.
+    let target = pad.downcast_ref::<gst::GhostPad>()?.get_target()?;
+    target.get_name().rsplit('_').nth(1)?.parse().ok()
 }
 
-fn to_jitter_stats(stats: &gst::StructureRef) -> JitterStats {
-    JitterStats {
-        num_pushed: stats.get::<u64>("num-pushed").unwrap().unwrap(),
-        num_lost: stats.get::<u64>("num-lost").unwrap().unwrap(),
-        num_late: stats.get::<u64>("num-late").unwrap().unwrap(),
-        num_duplicates: stats.get::<u64>("num-duplicates").unwrap().unwrap(),
-        avg_jitter: stats.get::<u64>("avg-jitter").unwrap().unwrap(),
-        rtx_success_count: stats.get::<u64>("rtx-success-count").unwrap().unwrap(),
-        rtx_count: stats.get::<u64>("rtx-count").unwrap().unwrap(),
-        rtx_per_packet: stats.get::<f64>("rtx-per-packet").unwrap().unwrap(),
-        rtx_rtt: stats.get::<u64>("rtx-rtt").unwrap().unwrap(),
+fn request_key_frame(depay_sink: &gst::Pad) {
+    // rtpsession turns the event into a PLI/FIR to the sender.
+    let event = gst_video::UpstreamForceKeyUnitEvent::builder()
+        .all_headers(true)
+        .build();
+    if !depay_sink.push_event(event) {
+        log::debug!("Key frame request was not handled");
     }
 }
@@ -0,0 +1,306 @@
+use gst::prelude::*;
+use gstreamer as gst;
+use std::collections::HashMap;
+
+// Decoders that offload to the GPU, NVDEC/D3D11 and VA-API/V4L2 on Linux.
+const HARDWARE_DECODERS: &[&str] = &[
+    "nvh264dec",
+    "nvh265dec",
+    "nvvp9dec",
+    "nvav1dec",
+    "d3d11h264dec",
+    "d3d11h265dec",
+    "d3d11vp9dec",
+    "d3d11av1dec",
+    "vah264dec",
+    "vah265dec",
+    "vavp9dec",
+    "vaav1dec",
+    "vaapih264dec",
+    "vaapih265dec",
+    "vaapivp9dec",
+    "vaapiav1dec",
+    "v4l2slh264dec",
+    "v4l2slh265dec",
+    "v4l2slvp9dec",
+    "v4l2h264dec",
+    "v4l2h265dec",
+    "v4l2vp9dec",
+];
+// Software decoders that stop corrupt frames from reaching the renderer.
+const SOFTWARE_DECODERS: &[&str] = &["openh264dec", "libde265dec", "vp9dec", "dav1ddec", "av1dec"];
+// FFMpeg decoders, fast but they let corrupt frames through.
+const FAST_SOFTWARE_DECODERS: &[&str] = &["avdec_h264", "avdec_h265", "avdec_vp9", "avdec_av1"];
+
+/// Video codecs the client knows how to depayload, parse and decode.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Codec {
+    H264,
+    H265,
+    Vp9,
+    Av1,
+}
+
+impl Codec {
+    pub const ALL: [Codec; 4] = [Codec::H264, Codec::H265, Codec::Vp9, Codec::Av1];
+
+    pub fn from_encoding_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|codec| codec.encoding_name().eq_ignore_ascii_case(name))
+    }
+
+    /// The RTP encoding-name, as used in the caps and the SDP.
+    pub fn encoding_name(&self) -> &'static str {
+        match self {
+            Codec::H264 => "H264",
+            Codec::H265 => "H265",
+            Codec::Vp9 => "VP9",
+            Codec::Av1 => "AV1",
+        }
+    }
+
+    fn depayloader(&self) -> &'static str {
+        match self {
+            Codec::H264 => "rtph264depay",
+            Codec::H265 => "rtph265depay",
+            Codec::Vp9 => "rtpvp9depay",
+            Codec::Av1 => "rtpav1depay",
+        }
+    }
+
+    fn parser(&self) -> &'static str {
+        match self {
+            Codec::H264 => "h264parse",
+            Codec::H265 => "h265parse",
+            Codec::Vp9 => "vp9parse",
+            Codec::Av1 => "av1parse",
+        }
+    }
+
+    fn parser_options(&self) -> &'static str {
+        match self {
+            // Resend SPS/PPS with every IDR so that we can recover after loss.
+            Codec::H264 | Codec::H265 => "config-interval=-1",
+            Codec::Vp9 | Codec::Av1 => "",
+        }
+    }
+
+    // The caps out of the parser, used to match decoders.
+    fn caps(&self) -> gst::Caps {
+        let media_type = match self {
+            Codec::H264 => "video/x-h264",
+            Codec::H265 => "video/x-h265",
+            Codec::Vp9 => "video/x-vp9",
+            Codec::Av1 => "video/x-av1",
+        };
+        gst::Caps::new_simple(media_type, &[])
+    }
+
+    /// Check that the depayloader and parser for the codec are installed.
+    pub fn is_available(&self) -> bool {
+        [self.depayloader(), self.parser()]
+            .iter()
+            .all(|name| gst::ElementFactory::find(name).is_some())
+    }
+
+    /// The depay ! parse ! decode part of the decode bin, elements are named with {idx}.
+    /// The tee after the parser is where recordings are branched off.
+    pub fn decode_template(&self, decoder: &str) -> String {
+        format!(
+            "{depay} name=depay{{idx}} ! {parse} name=parse{{idx}} {parse_opts} ! tee name=tee{{idx}} ! {decoder} name=decoder{{idx}} qos=true",
+            depay = self.depayloader(),
+            parse = self.parser(),
+            parse_opts = self.parser_options(),
+            decoder = decoder,
+        )
+    }
+
+    /// A bin that muxes the parsed stream into a file, the location is set on recordsink.
+    pub fn record_template(&self, muxer: &str) -> String {
+        // A second parser converts to the stream format the muxer wants.
+        format!(
+            "queue ! {parse} ! {muxer} ! filesink name=recordsink",
+            parse = self.parser(),
+            muxer = muxer,
+        )
+    }
+}
+
+/// Ordered list of decoder elements to try, the first one that decodes a codec is used.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecoderChain(Vec<String>);
+
+impl DecoderChain {
+    pub fn new(decoders: Vec<String>) -> Self {
+        Self(decoders)
+    }
+
+    fn from_lists(lists: &[&[&str]]) -> Self {
+        Self(
+            lists
+                .iter()
+                .flat_map(|list| list.iter())
+                .map(|name| name.to_string())
+                .collect(),
+        )
+    }
+
+    /// Prefer the software decoders, then FFMpeg and hardware as a fallback.
+    pub fn software() -> Self {
+        Self::from_lists(&[SOFTWARE_DECODERS, FAST_SOFTWARE_DECODERS, HARDWARE_DECODERS])
+    }
+
+    pub fn hardware() -> Self {
+        Self::from_lists(&[HARDWARE_DECODERS, SOFTWARE_DECODERS, FAST_SOFTWARE_DECODERS])
+    }
+
+    pub fn fast_software() -> Self {
+        Self::from_lists(&[FAST_SOFTWARE_DECODERS, SOFTWARE_DECODERS, HARDWARE_DECODERS])
+    }
+
+    /// Decoders in the chain that are installed and accept the codec, in order.
+    pub fn candidates(&self, codec: Codec) -> Vec<String> {
+        let caps = codec.caps();
+        self.0
+            .iter()
+            .filter(|name| {
+                matches!(gst::ElementFactory::find(name), Some(f) if f.can_sink_any_caps(&caps))
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// The first candidate for the codec that can actually be brought up.
+    pub fn select(&self, codec: Codec) -> Option<String> {
+        self.candidates(codec)
+            .into_iter()
+            .find(|name| probe_decoder(name))
+    }
+}
+
+/// The decoder picked from the chain for each codec. Probing starts the decoders, which
+/// is slow for hardware ones, so the selection is handed on to the sessions that follow.
+#[derive(Debug, Clone)]
+pub struct DecoderSelection {
+    chain: DecoderChain,
+    selected: HashMap<Codec, String>,
+}
+
+impl DecoderSelection {
+    /// Probe the registry for the best working decoder of each codec.
+    pub fn probe(chain: DecoderChain) -> Self {
+        let selected = Codec::ALL
+            .iter()
+            .filter_map(|codec| match chain.select(*codec) {
+                Some(decoder) => {
+                    log::info!("Using decoder {} for {:?}", decoder, codec);
+                    Some((*codec, decoder))
+                }
+                None => {
+                    log::info!("No decoder found for {:?}", codec);
+                    None
+                }
+            })
+            .collect();
+        Self { chain, selected }
+    }
+
+    pub fn contains(&self, codec: Codec) -> bool {
+        self.selected.contains_key(&codec)
+    }
+
+    /// The selected decoder for the codec first, then the rest of the chain.
+    pub fn candidates(&self, codec: Codec) -> Vec<String> {
+        let mut candidates = self.chain.candidates(codec);
+        if let Some(selected) = self.selected.get(&codec) {
+            candidates.retain(|d| d != selected);
+            candidates.insert(0, selected.clone());
+        }
+        candidates
+    }
+
+    /// Keep the decoder that started for the codec, the codec is dropped if none did.
+    pub fn update(&mut self, codec: Codec, decoder: Option<&str>) {
+        match decoder {
+            Some(decoder) => {
+                self.selected.insert(codec, decoder.to_owned());
+            }
+            None => {
+                self.selected.remove(&codec);
+            }
+        }
+    }
+}
+
+fn probe_decoder(name: &str) -> bool {
+    // Some decoders are registered but fail to open their device, e.g. without a GPU.
+    match gst::ElementFactory::make(name, None) {
+        Ok(element) => {
+            let ready = element.set_state(gst::State::Ready).is_ok();
+            let _ = element.set_state(gst::State::Null);
+            if !ready {
+                log::debug!("Decoder {} failed to start", name);
+            }
+            ready
+        }
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_encoding_name() {
+        assert_eq!(Codec::from_encoding_name("H264"), Some(Codec::H264));
+        assert_eq!(Codec::from_encoding_name("h265"), Some(Codec::H265));
+        assert_eq!(Codec::from_encoding_name("VP9"), Some(Codec::Vp9));
+        assert_eq!(Codec::from_encoding_name("AV1"), Some(Codec::Av1));
+        assert_eq!(Codec::from_encoding_name("JPEG"), None);
+    }
+
+    #[test]
+    fn test_chain_order() {
+        let DecoderChain(decoders) = DecoderChain::hardware();
+        assert_eq!(decoders.first().map(String::as_str), Some("nvh264dec"));
+        let DecoderChain(decoders) = DecoderChain::fast_software();
+        assert_eq!(decoders.first().map(String::as_str), Some("avdec_h264"));
+        assert_eq!(
+            decoders.len(),
+            HARDWARE_DECODERS.len() + SOFTWARE_DECODERS.len() + FAST_SOFTWARE_DECODERS.len()
+        );
+    }
+
+    #[test]
+    fn test_selection_update() {
+        let mut selection = DecoderSelection {
+            chain: DecoderChain::software(),
+            selected: HashMap::new(),
+        };
+        selection.update(Codec::H264, Some("openh264dec"));
+        assert_eq!(
+            selection.selected.get(&Codec::H264).map(String::as_str),
+            Some("openh264dec")
+        );
+        // A failed decoder is replaced by the fallback that started.
+        selection.update(Codec::H264, Some("avdec_h264"));
+        assert_eq!(
+            selection.selected.get(&Codec::H264).map(String::as_str),
+            Some("avdec_h264")
+        );
+        selection.update(Codec::H264, None);
+        assert!(!selection.contains(Codec::H264));
+    }
+
+    #[test]
+    fn test_decode_template() {
+        let template = Codec::H265.decode_template("avdec_h265");
+        assert_eq!(
+            template,
+            "rtph265depay name=depay{idx} ! h265parse name=parse{idx} config-interval=-1 ! tee name=tee{idx} ! avdec_h265 name=decoder{idx} qos=true"
+        );
+    }
+}
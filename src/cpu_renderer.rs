@@ -0,0 +1,1057 @@
+use glyph_brush::{ab_glyph::Rect, GlyphBrush, GlyphBrushBuilder};
+use gstreamer as gst;
+use gstreamer_video as gst_video;
+
+use crate::{
+    display::DisplaySettings,
+    glvideo::Frame,
+    message::LayoutRect,
+    renderer::{
+        Damage, DamageTracker, Fill, PaneFrame, PaneOverlay, Renderer, ScalingFilter, ViewFrame,
+    },
+    text_renderer::{default_font, TextPartition, TextStyle},
+    vertex::Quad,
+    view_state::{ViewState, Zoom},
+};
+
+const PI: f32 = std::f32::consts::PI;
+const GLYPH_TEXTURE_SIZE: u32 = 256;
+// Measurements are drawn in yellow, like the GlRenderer.
+const LINE_COLOR: [u8; 4] = [255, 255, 0, 255];
+
+#[derive(Debug, Clone)]
+struct GlyphRect {
+    pixel: Rect,
+    tex: Rect,
+}
+
+/// Renders into system memory, without GL. Samples must be RGBA in system memory.
+/// Images are placed with the same Quad transforms, filtered and mapped like the scaling shader,
+/// so the output matches the GlRenderer for layout, zoom and pan.
+pub struct CpuRenderer {
+    quad: Quad,
+    state: ViewState,
+    target: Frame,
+    glyph_brush: GlyphBrush<GlyphRect>,
+    glyph_texture: Vec<u8>,
+    glyph_texture_size: u32,
+    glyphs: Vec<GlyphRect>,
+    filter: ScalingFilter,
+    display: DisplaySettings,
+    scale_factor: f32,
+    text_style: TextStyle,
+    damage: DamageTracker,
+}
+
+impl CpuRenderer {
+    pub fn new() -> Self {
+        let mut state = ViewState::new();
+        state.set_zoom_mode(Zoom::Pixel(1.0_f32));
+        Self {
+            quad: Quad::default(),
+            state,
+            target: Frame {
+                width: 0,
+                height: 0,
+                data: Vec::new(),
+            },
+            glyph_brush: GlyphBrushBuilder::using_font(default_font()).build(),
+            glyph_texture: vec![0; (GLYPH_TEXTURE_SIZE * GLYPH_TEXTURE_SIZE) as usize],
+            glyph_texture_size: GLYPH_TEXTURE_SIZE,
+            glyphs: Vec::new(),
+            filter: ScalingFilter::Lanczos,
+            display: DisplaySettings::default(),
+            scale_factor: 1.0,
+            text_style: TextStyle::default(),
+            damage: DamageTracker::default(),
+        }
+    }
+
+    /// The transform of the images in their views, zoom 1 and centered by default.
+    pub fn set_view_state(&mut self, state: ViewState) {
+        self.state = state;
+    }
+
+    fn clear(&mut self) {
+        for pixel in self.target.data.chunks_exact_mut(4) {
+            pixel.copy_from_slice(&[0, 0, 0, 255]);
+        }
+    }
+
+    // Clears a view that is redrawn, leaving the rest of the window.
+    fn clear_rect(&mut self, rect: &LayoutRect) {
+        for y in rect.y..rect.y + rect.height {
+            for x in rect.x..rect.x + rect.width {
+                if let Some(pixel) = self.target_pixel(x, y) {
+                    pixel.copy_from_slice(&[0, 0, 0, 255]);
+                }
+            }
+        }
+    }
+
+    fn draw_view(
+        &mut self,
+        rect: &LayoutRect,
+        image: Option<&Frame>,
+        panes: &[PaneFrame],
+        overlays: &[PaneOverlay],
+        fills: &[Fill],
+        text: Option<Vec<TextPartition>>,
+    ) {
+        match image {
+            Some(image) if panes.is_empty() => self.draw_image(rect, image),
+            Some(image) => {
+                for pane in panes {
+                    self.draw_pane(rect, image, pane);
+                }
+            }
+            None => {}
+        }
+        for overlay in overlays {
+            self.draw_overlay(rect, overlay);
+        }
+        for fill in fills {
+            self.draw_fill(rect, fill);
+        }
+        if let Some(text) = text {
+            self.draw_text(rect, text);
+        }
+    }
+
+    fn draw_image(&mut self, rect: &LayoutRect, image: &Frame) {
+        let pane = PaneFrame {
+            rect: LayoutRect {
+                x: 0,
+                y: 0,
+                width: rect.width,
+                height: rect.height,
+            },
+            prediction: None,
+        };
+        self.draw_pane(rect, image, &pane);
+    }
+
+    // Draws the part of the image under the pane, moved by its prediction.
+    fn draw_pane(&mut self, view: &LayoutRect, image: &Frame, pane: &PaneFrame) {
+        if image.width == 0 || image.height == 0 {
+            return;
+        }
+        let view_size = (view.width as f32, view.height as f32);
+        let rect = LayoutRect {
+            x: view.x + pane.rect.x,
+            y: view.y + pane.rect.y,
+            width: pane.rect.width,
+            height: pane.rect.height,
+        };
+        let pane_size = (rect.width as f32, rect.height as f32);
+        self.quad.set_viewport_size(pane_size);
+        // We assume that the texture has the same size as the frame, like the GlRenderer.
+        self.quad.map_texture_coords_with_offset(
+            pane_size,
+            view_size,
+            (
+                pane.rect.x as f32 / view_size.0,
+                pane.rect.y as f32 / view_size.1,
+            ),
+        );
+        self.quad.set_prediction(pane.prediction.as_ref());
+        let vertices = self.quad.get_vertex(&self.state);
+        self.quad.set_prediction(None);
+        // The quad is axis aligned, the first and the third vertex are opposite corners.
+        let (p0, t0) = (vertices[0].position(), vertices[0].tex_coords());
+        let (p2, t2) = (vertices[2].position(), vertices[2].tex_coords());
+
+        for y in 0..rect.height {
+            for x in 0..rect.width {
+                // Pixel centers in normalized device coordinates, y up.
+                let ndc_x = (x as f32 + 0.5) / pane_size.0 * 2.0 - 1.0;
+                let ndc_y = 1.0 - (y as f32 + 0.5) / pane_size.1 * 2.0;
+                let s = (ndc_x - p0[0]) / (p2[0] - p0[0]);
+                let t = (ndc_y - p0[1]) / (p2[1] - p0[1]);
+                if !(0.0..1.0).contains(&s) || !(0.0..1.0).contains(&t) {
+                    continue;
+                }
+                let coord = [t0[0] + s * (t2[0] - t0[0]), t0[1] + t * (t2[1] - t0[1])];
+                let color = self.display.apply(sample(self.filter, image, coord));
+                if let Some(pixel) = self.target_pixel(rect.x + x, rect.y + y) {
+                    for (dst, src) in pixel.iter_mut().zip(color.iter()) {
+                        *dst = to_unorm(*src);
+                    }
+                    pixel[3] = 255;
+                }
+            }
+        }
+    }
+
+    // Draws the lines of the overlay over its pane, one pixel wide.
+    fn draw_overlay(&mut self, view: &LayoutRect, overlay: &PaneOverlay) {
+        let pane = &overlay.rect;
+        // A square of the line width at each step, centered on the line.
+        let width = overlay.width.round().max(1.0);
+        let half = width / 2.0 - 0.5;
+        for [a, b] in overlay.lines.iter() {
+            let steps = (b.0 - a.0).abs().max((b.1 - a.1).abs()).ceil().max(1.0) as u32;
+            for i in 0..=steps {
+                let t = i as f32 / steps as f32;
+                let left = (a.0 + t * (b.0 - a.0) - half).floor();
+                let top = (a.1 + t * (b.1 - a.1) - half).floor();
+                for dy in 0..width as u32 {
+                    for dx in 0..width as u32 {
+                        let (x, y) = (left + dx as f32, top + dy as f32);
+                        // Clip to the pane.
+                        if x < 0.0 || y < 0.0 || x >= pane.width as f32 || y >= pane.height as f32 {
+                            continue;
+                        }
+                        let (x, y) = (view.x + pane.x + x as u32, view.y + pane.y + y as u32);
+                        if let Some(pixel) = self.target_pixel(x, y) {
+                            pixel.copy_from_slice(&LINE_COLOR);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Blends the colour over the part of the rectangle inside the view.
+    fn draw_fill(&mut self, view: &LayoutRect, fill: &Fill) {
+        let right = (fill.rect.x + fill.rect.width).min(view.width);
+        let bottom = (fill.rect.y + fill.rect.height).min(view.height);
+        let alpha = fill.color[3];
+        for y in fill.rect.y..bottom {
+            for x in fill.rect.x..right {
+                if let Some(pixel) = self.target_pixel(view.x + x, view.y + y) {
+                    for (p, c) in pixel.iter_mut().zip(fill.color.iter()).take(3) {
+                        *p = (c * 255.0 * alpha + *p as f32 * (1.0 - alpha)).round() as u8;
+                    }
+                }
+            }
+        }
+    }
+
+    fn draw_text(&mut self, rect: &LayoutRect, text: Vec<TextPartition>) {
+        for partition in text.iter() {
+            self.glyph_brush
+                .queue(partition.section(&self.text_style, self.scale_factor));
+        }
+        loop {
+            let size = self.glyph_texture_size as usize;
+            let texture = &mut self.glyph_texture;
+            let result = self.glyph_brush.process_queued(
+                |glyph_rect, glyph_data| {
+                    let width = glyph_rect.width() as usize;
+                    for (row, data) in glyph_data.chunks_exact(width.max(1)).enumerate() {
+                        let start =
+                            (glyph_rect.min[1] as usize + row) * size + glyph_rect.min[0] as usize;
+                        texture[start..start + width].copy_from_slice(data);
+                    }
+                },
+                |glyph_vertex| GlyphRect {
+                    pixel: glyph_vertex.pixel_coords,
+                    tex: glyph_vertex.tex_coords,
+                },
+            );
+            match result {
+                Ok(glyph_brush::BrushAction::Draw(glyphs)) => {
+                    self.glyphs = glyphs;
+                    break;
+                }
+                Ok(glyph_brush::BrushAction::ReDraw) => break,
+                Err(glyph_brush::BrushError::TextureTooSmall { suggested }) => {
+                    let dim = suggested.0.max(suggested.1).next_power_of_two();
+                    self.glyph_texture = vec![0; (dim * dim) as usize];
+                    self.glyph_texture_size = dim;
+                    self.glyph_brush.resize_texture(dim, dim);
+                }
+            }
+        }
+
+        // The outline or shadow first, then the text over it.
+        let glyphs = std::mem::take(&mut self.glyphs);
+        for offset in self.text_style.effect_offsets(self.scale_factor) {
+            self.blend_glyphs(rect, &glyphs, offset, self.text_style.effect_color);
+        }
+        self.blend_glyphs(rect, &glyphs, (0.0, 0.0), self.text_style.color);
+        self.glyphs = glyphs;
+    }
+
+    // Blends the colour with the glyph coverage as alpha, like the text shader.
+    fn blend_glyphs(
+        &mut self,
+        rect: &LayoutRect,
+        glyphs: &[GlyphRect],
+        offset: (f32, f32),
+        color: [f32; 4],
+    ) {
+        let size = self.glyph_texture_size as f32;
+        for glyph in glyphs.iter() {
+            let tex_x = (glyph.tex.min.x * size).round() as u32;
+            let tex_y = (glyph.tex.min.y * size).round() as u32;
+            let left = (glyph.pixel.min.x + offset.0).round() as i64;
+            let top = (glyph.pixel.min.y + offset.1).round() as i64;
+            let width = (glyph.tex.width() * size).round() as u32;
+            let height = (glyph.tex.height() * size).round() as u32;
+            for j in 0..height {
+                for i in 0..width {
+                    let (x, y) = (left + i as i64, top + j as i64);
+                    // Clip to the view, like the scissor box.
+                    if x < 0 || y < 0 || x >= rect.width as i64 || y >= rect.height as i64 {
+                        continue;
+                    }
+                    let coverage = self.glyph_texture
+                        [((tex_y + j) * self.glyph_texture_size + tex_x + i) as usize];
+                    if coverage == 0 {
+                        continue;
+                    }
+                    let alpha = color[3] * coverage as f32 / 255.0;
+                    if let Some(pixel) = self.target_pixel(rect.x + x as u32, rect.y + y as u32) {
+                        for (dst, c) in pixel.iter_mut().zip(color.iter()).take(3) {
+                            *dst = to_unorm(c * alpha + (1.0 - alpha) * (*dst as f32 / 255.0));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn target_pixel(&mut self, x: u32, y: u32) -> Option<&mut [u8]> {
+        if x >= self.target.width || y >= self.target.height {
+            return None;
+        }
+        let start = (y as usize * self.target.width as usize + x as usize) * 4;
+        self.target.data.get_mut(start..start + 4)
+    }
+}
+
+impl Default for CpuRenderer {
+    fn default() -> Self {
+        CpuRenderer::new()
+    }
+}
+
+impl Renderer for CpuRenderer {
+    fn set_window_size(&mut self, size: (u32, u32)) {
+        self.target = Frame {
+            width: size.0,
+            height: size.1,
+            data: vec![0; size.0 as usize * size.1 as usize * 4],
+        };
+        self.clear();
+        self.damage.invalidate();
+    }
+
+    fn set_scaling_filter(&mut self, filter: ScalingFilter) {
+        self.filter = filter;
+        self.damage.invalidate();
+    }
+
+    fn scaling_filter(&self) -> ScalingFilter {
+        self.filter
+    }
+
+    fn set_display(&mut self, display: DisplaySettings) {
+        self.display = display;
+        self.damage.invalidate();
+    }
+
+    fn display(&self) -> DisplaySettings {
+        self.display
+    }
+
+    fn set_scale_factor(&mut self, scale_factor: f32) {
+        self.scale_factor = scale_factor;
+        self.damage.invalidate();
+    }
+
+    fn set_text_style(&mut self, style: TextStyle) {
+        if style.font != self.text_style.font {
+            let font = style.load_font().unwrap_or_else(|e| {
+                log::error!("Failed to load font {:?}: {:?}", style.font, e);
+                default_font()
+            });
+            let size = self.glyph_texture_size;
+            self.glyph_brush = GlyphBrushBuilder::using_font(font)
+                .initial_cache_size((size, size))
+                .build();
+        }
+        self.text_style = style;
+        self.damage.invalidate();
+    }
+
+    fn render_frames(&mut self, frames: Vec<ViewFrame>) -> Damage {
+        let (damage, frames) = self.damage.update(frames);
+        if damage == Damage::Window {
+            self.clear();
+        }
+        for ViewFrame {
+            sample,
+            rect,
+            text,
+            panes,
+            overlays,
+            fills,
+            ..
+        } in frames
+        {
+            if damage != Damage::Window {
+                self.clear_rect(&rect);
+            }
+            let image = sample.and_then(|sample| sample_image(&sample.sample));
+            self.draw_view(&rect, image.as_ref(), &panes, &overlays, &fills, text);
+        }
+        damage
+    }
+
+    fn read_frame(&self) -> Option<Frame> {
+        Some(self.target.clone())
+    }
+}
+
+/// Copies an RGBA sample in system memory to a frame.
+fn sample_image(sample: &gst::Sample) -> Option<Frame> {
+    let buffer = sample.get_buffer_owned()?;
+    let info = sample
+        .get_caps()
+        .and_then(|caps| gst_video::VideoInfo::from_caps(caps).ok())?;
+    if info.format() != gst_video::VideoFormat::Rgba {
+        log::error!(
+            "The software renderer needs RGBA samples, got {:?}",
+            info.format()
+        );
+        return None;
+    }
+    let frame = gst_video::VideoFrame::from_buffer_readable(buffer, &info).ok()?;
+    let stride = frame.plane_stride()[0] as usize;
+    let row_len = frame.width() as usize * 4;
+    let data = frame
+        .plane_data(0)
+        .ok()?
+        .chunks(stride)
+        .take(frame.height() as usize)
+        .flat_map(|row| &row[..row_len])
+        .copied()
+        .collect();
+    Some(Frame {
+        width: frame.width(),
+        height: frame.height(),
+        data,
+    })
+}
+
+// GLSL fract
+fn fract(x: f32) -> f32 {
+    x - x.floor()
+}
+
+fn texel(image: &Frame, x: i64, y: i64) -> [f32; 3] {
+    // Clamp to edge, like the texture parameters of the GlRenderer.
+    let x = x.clamp(0, image.width as i64 - 1) as usize;
+    let y = y.clamp(0, image.height as i64 - 1) as usize;
+    let start = (y * image.width as usize + x) * 4;
+    let pixel = &image.data[start..start + 3];
+    [
+        pixel[0] as f32 / 255.0,
+        pixel[1] as f32 / 255.0,
+        pixel[2] as f32 / 255.0,
+    ]
+}
+
+fn nearest(image: &Frame, u: f32, v: f32) -> [f32; 3] {
+    let x = (u * image.width as f32).floor() as i64;
+    let y = (v * image.height as f32).floor() as i64;
+    texel(image, x, y)
+}
+
+fn mix(a: [f32; 3], b: [f32; 3], t: f32) -> [f32; 3] {
+    [
+        a[0] * (1.0 - t) + b[0] * t,
+        a[1] * (1.0 - t) + b[1] * t,
+        a[2] * (1.0 - t) + b[2] * t,
+    ]
+}
+
+// A linear texture lookup.
+fn bilinear(image: &Frame, u: f32, v: f32) -> [f32; 3] {
+    let x = u * image.width as f32 - 0.5;
+    let y = v * image.height as f32 - 0.5;
+    let (x0, y0) = (x.floor() as i64, y.floor() as i64);
+    let (fx, fy) = (fract(x), fract(y));
+    let top = mix(texel(image, x0, y0), texel(image, x0 + 1, y0), fx);
+    let bottom = mix(texel(image, x0, y0 + 1), texel(image, x0 + 1, y0 + 1), fx);
+    mix(top, bottom, fy)
+}
+
+fn cubic(v: f32) -> [f32; 4] {
+    let n = [1.0 - v, 2.0 - v, 3.0 - v, 4.0 - v];
+    let s = [n[0].powi(3), n[1].powi(3), n[2].powi(3), n[3].powi(3)];
+    let x = s[0];
+    let y = s[1] - 4.0 * s[0];
+    let z = s[2] - 4.0 * s[1] + 6.0 * s[0];
+    let w = 6.0 - x - y - z;
+    [x / 6.0, y / 6.0, z / 6.0, w / 6.0]
+}
+
+// textureBicubic in the scaling shader, four linear lookups.
+fn bicubic(image: &Frame, coord: [f32; 2]) -> [f32; 3] {
+    let size = [image.width as f32, image.height as f32];
+    let tex = [coord[0] * size[0] - 0.5, coord[1] * size[1] - 0.5];
+    let (fx, fy) = (fract(tex[0]), fract(tex[1]));
+    let tex = [tex[0] - fx, tex[1] - fy];
+    let xcubic = cubic(fx);
+    let ycubic = cubic(fy);
+
+    let s = [
+        xcubic[0] + xcubic[1],
+        xcubic[2] + xcubic[3],
+        ycubic[0] + ycubic[1],
+        ycubic[2] + ycubic[3],
+    ];
+    let offset = [
+        (tex[0] - 0.5 + xcubic[1] / s[0]) / size[0],
+        (tex[0] + 1.5 + xcubic[3] / s[1]) / size[0],
+        (tex[1] - 0.5 + ycubic[1] / s[2]) / size[1],
+        (tex[1] + 1.5 + ycubic[3] / s[3]) / size[1],
+    ];
+    let sample0 = bilinear(image, offset[0], offset[2]);
+    let sample1 = bilinear(image, offset[1], offset[2]);
+    let sample2 = bilinear(image, offset[0], offset[3]);
+    let sample3 = bilinear(image, offset[1], offset[3]);
+
+    let sx = s[0] / (s[0] + s[1]);
+    let sy = s[2] / (s[2] + s[3]);
+    mix(mix(sample3, sample2, sx), mix(sample1, sample0, sx), sy)
+}
+
+fn normalize(mut weights: [f32; 4]) -> [f32; 4] {
+    let sum: f32 = weights.iter().sum();
+    weights.iter_mut().for_each(|w| *w /= sum);
+    weights
+}
+
+fn lanczos_weights(x: f32) -> [f32; 4] {
+    let radius = 2.0;
+    let mut weights = [1.0 + x, x, 1.0 - x, 2.0 - x];
+    for w in weights.iter_mut() {
+        let s = (PI * *w).abs().max(1e-5);
+        *w = s.sin() * (s / radius).sin() / (s * s);
+    }
+    normalize(weights)
+}
+
+// weightbisharper in the scaling shader.
+fn sharp_weight(x: f32) -> f32 {
+    let (b, c) = (0.0, 0.75);
+    let ax = x.abs();
+    if ax < 1.0 {
+        (x * x * ((12.0 - 9.0 * b - 6.0 * c) * ax + (-18.0 + 12.0 * b + 6.0 * c)) + (6.0 - 2.0 * b))
+            / 6.0
+    } else if ax < 2.0 {
+        (x * x * ((-b - 6.0 * c) * ax + (6.0 * b + 30.0 * c))
+            + (-12.0 * b - 48.0 * c) * ax
+            + (8.0 * b + 24.0 * c))
+            / 6.0
+    } else {
+        0.0
+    }
+}
+
+fn sharp_weights(x: f32) -> [f32; 4] {
+    normalize([
+        sharp_weight(x + 1.0),
+        sharp_weight(x),
+        sharp_weight(1.0 - x),
+        sharp_weight(2.0 - x),
+    ])
+}
+
+// The 4x4 tap filters (lanc and bisharp) of the scaling shader.
+fn four_tap<W: Fn(f32) -> [f32; 4]>(image: &Frame, coord: [f32; 2], weights: W) -> [f32; 3] {
+    let step = [1.0 / image.width as f32, 1.0 / image.height as f32];
+    let pos = [coord[0] + step[0] * 0.5, coord[1] + step[1] * 0.5];
+    let f = [fract(pos[0] / step[0]), fract(pos[1] / step[1])];
+    let start = [
+        (-1.5 - f[0]) * step[0] + pos[0],
+        (-1.5 - f[1]) * step[1] + pos[1],
+    ];
+    let line_taps = weights(f[0]);
+    let column_taps = weights(f[1]);
+
+    let mut result = [0.0; 3];
+    for (j, column_tap) in column_taps.iter().enumerate() {
+        let v = start[1] + step[1] * j as f32;
+        for (i, line_tap) in line_taps.iter().enumerate() {
+            let u = start[0] + step[0] * i as f32;
+            let color = nearest(image, u, v);
+            for c in 0..3 {
+                result[c] += column_tap * line_tap * color[c];
+            }
+        }
+    }
+    result
+}
+
+fn sample(filter: ScalingFilter, image: &Frame, coord: [f32; 2]) -> [f32; 3] {
+    match filter {
+        ScalingFilter::Bilinear => bilinear(image, coord[0], coord[1]),
+        ScalingFilter::Bicubic => bicubic(image, coord),
+        ScalingFilter::Lanczos => four_tap(image, coord, lanczos_weights),
+        ScalingFilter::Sharp => four_tap(image, coord, sharp_weights),
+    }
+}
+
+fn to_unorm(x: f32) -> u8 {
+    (x.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        pane_style::{PaneBadges, PaneStyle},
+        text_renderer::Partition,
+        view_state::{Position, Prediction},
+        window_message::ViewSample,
+    };
+
+    const PALETTE: [(char, [u8; 3]); 6] = [
+        ('.', [0, 0, 0]),
+        ('R', [255, 0, 0]),
+        ('G', [0, 255, 0]),
+        ('B', [0, 0, 255]),
+        ('W', [255, 255, 255]),
+        ('Y', [255, 255, 0]),
+    ];
+
+    fn color(c: char) -> [u8; 3] {
+        PALETTE.iter().find(|(p, _)| *p == c).unwrap().1
+    }
+
+    // An image of blocks, one character per block.
+    fn block_image(rows: &[&str], block: u32) -> Frame {
+        let height = rows.len() as u32 * block;
+        let width = rows[0].len() as u32 * block;
+        let mut data = Vec::new();
+        for y in 0..height {
+            let row: Vec<char> = rows[(y / block) as usize].chars().collect();
+            for x in 0..width {
+                data.extend_from_slice(&color(row[(x / block) as usize]));
+                data.push(255);
+            }
+        }
+        Frame {
+            width,
+            height,
+            data,
+        }
+    }
+
+    // The rendered frame with a character per pixel, '?' for filtered edges.
+    fn golden(frame: &Frame) -> Vec<String> {
+        frame
+            .data
+            .chunks_exact(frame.width as usize * 4)
+            .map(|row| {
+                row.chunks_exact(4)
+                    .map(|pixel| {
+                        PALETTE
+                            .iter()
+                            .find(|(_, c)| {
+                                c.iter()
+                                    .zip(pixel.iter())
+                                    .all(|(a, b)| (*a as i32 - *b as i32).abs() <= 8)
+                            })
+                            .map_or('?', |(p, _)| *p)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn render(state: ViewState, window: (u32, u32), views: &[(LayoutRect, Frame)]) -> Vec<String> {
+        let mut renderer = CpuRenderer::new();
+        renderer.set_view_state(state);
+        renderer.set_window_size(window);
+        for (rect, image) in views {
+            renderer.draw_view(rect, Some(image), &[], &[], &[], None);
+        }
+        golden(&renderer.read_frame().unwrap())
+    }
+
+    fn rect(x: u32, y: u32, width: u32, height: u32) -> LayoutRect {
+        LayoutRect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    fn pixel_state(mag: f32, pos: (f32, f32)) -> ViewState {
+        let mut state = ViewState::new();
+        state.set_zoom_mode(Zoom::Pixel(mag));
+        state.pos = Position::Relative(pos);
+        state
+    }
+
+    #[test]
+    fn test_layout() {
+        let views = [
+            (rect(1, 1, 6, 4), block_image(&["RR", "RR"], 3)),
+            (rect(9, 2, 4, 3), block_image(&["G"], 4)),
+        ];
+        let expected = vec![
+            "..............",
+            ".RRRRRR.......",
+            ".RRRRRR..GGGG.",
+            ".RRRRRR..GGGG.",
+            ".RRRRRR..GGGG.",
+            "..............",
+        ];
+        assert_eq!(
+            render(pixel_state(1.0, (0.0, 0.0)), (14, 6), &views),
+            expected
+        );
+    }
+
+    fn blocks() -> [(LayoutRect, Frame); 1] {
+        let image = block_image(&["RGBW", "GBWR", "BWRG", "WRGB"], 4);
+        [(rect(0, 0, 16, 16), image)]
+    }
+
+    #[test]
+    fn test_pixel_zoom() {
+        let expected = vec![
+            "RRR?GGGGBBBB?WWW",
+            "RRR?GGGGBBBB?WWW",
+            "RRR?GGGGBBBB?WWW",
+            "????????????????",
+            "GGG?BBBBWWWW?RRR",
+            "GGG?BBBBWWWW?RRR",
+            "GGG?BBBBWWWW?RRR",
+            "GGG?BBB??WWW?RRR",
+            "BBB?WWW??RRR?GGG",
+            "BBB?WWWWRRRR?GGG",
+            "BBB?WWWWRRRR?GGG",
+            "BBB?WWWWRRRR?GGG",
+            "????????????????",
+            "WWW?RRRRGGGG?BBB",
+            "WWW?RRRRGGGG?BBB",
+            "WWW?RRRRGGGG?BBB",
+        ];
+        assert_eq!(
+            render(pixel_state(1.0, (0.0, 0.0)), (16, 16), &blocks()),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_zoom() {
+        // Zooms in on the center of the image.
+        let expected = vec![
+            "BBBBBBB??WWWWWWW",
+            "BBBBBBB??WWWWWWW",
+            "BBBBBBB??WWWWWWW",
+            "BBBBBBB??WWWWWWW",
+            "BBBBBBB??WWWWWWW",
+            "BBBBBBB??WWWWWWW",
+            "BBBBBBB??WWWWWWW",
+            "????????????????",
+            "????????????????",
+            "WWWWWWW??RRRRRRR",
+            "WWWWWWW??RRRRRRR",
+            "WWWWWWW??RRRRRRR",
+            "WWWWWWW??RRRRRRR",
+            "WWWWWWW??RRRRRRR",
+            "WWWWWWW??RRRRRRR",
+            "WWWWWWW??RRRRRRR",
+        ];
+        assert_eq!(
+            render(pixel_state(2.0, (0.0, 0.0)), (16, 16), &blocks()),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_pan() {
+        let expected = vec![
+            "....RRR?GGGGBBBB",
+            "....RRR?GGGGBBBB",
+            "....RRR?GGGGBBBB",
+            "....????????????",
+            "....GGG?BBBBWWWW",
+            "....GGG?BBBBWWWW",
+            "....GGG?BBBBWWWW",
+            "....GGG?BBB??WWW",
+            "....BBB?WWW??RRR",
+            "....BBB?WWWWRRRR",
+            "....BBB?WWWWRRRR",
+            "....BBB?WWWWRRRR",
+            "....????????????",
+            "....WWW?RRRRGGGG",
+            "....WWW?RRRRGGGG",
+            "....WWW?RRRRGGGG",
+        ];
+        assert_eq!(
+            render(pixel_state(1.0, (4.0, 0.0)), (16, 16), &blocks()),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_predicted_pan() {
+        // Two panes, the right one is panned until its frame arrives.
+        let mut renderer = CpuRenderer::new();
+        renderer.set_view_state(pixel_state(1.0, (0.0, 0.0)));
+        renderer.set_window_size((16, 8));
+        let panes = [
+            PaneFrame {
+                rect: rect(0, 0, 8, 8),
+                prediction: None,
+            },
+            PaneFrame {
+                rect: rect(8, 0, 8, 8),
+                prediction: Some(Prediction {
+                    scale: 1.0,
+                    offset: (2.0, 0.0),
+                }),
+            },
+        ];
+        let image = block_image(&["RRGB", "RRBG"], 4);
+        renderer.draw_view(&rect(0, 0, 16, 8), Some(&image), &panes, &[], &[], None);
+        let expected = vec![
+            "RRRRRRRR..GGG??B",
+            "RRRRRRRR..GGG??B",
+            "RRRRRRRR..GGG??B",
+            "RRRRRRRR..??????",
+            "RRRRRRRR..??????",
+            "RRRRRRRR..BBB??G",
+            "RRRRRRRR..BBB??G",
+            "RRRRRRRR..BBB??G",
+        ];
+        assert_eq!(golden(&renderer.read_frame().unwrap()), expected);
+    }
+
+    #[test]
+    fn test_measurement_overlay() {
+        // A ruler and a rect in the second pane, clipped to it.
+        let mut renderer = CpuRenderer::new();
+        renderer.set_window_size((12, 6));
+        let overlay = PaneOverlay {
+            rect: rect(6, 0, 6, 6),
+            lines: vec![
+                [(0.5, 0.5), (4.5, 0.5)],
+                [(1.5, 2.5), (1.5, 4.5)],
+                [(4.5, 4.5), (9.5, 4.5)],
+            ],
+            width: 1.0,
+        };
+        renderer.draw_view(&rect(0, 0, 12, 6), None, &[], &[overlay], &[], None);
+        let expected = vec![
+            "......YYYYY.",
+            "............",
+            ".......Y....",
+            ".......Y....",
+            ".......Y..YY",
+            "............",
+        ];
+        assert_eq!(golden(&renderer.read_frame().unwrap()), expected);
+    }
+
+    #[test]
+    fn test_measurement_line_width() {
+        // A line 3 pixels wide, as on a display with scale factor 3.
+        let mut renderer = CpuRenderer::new();
+        renderer.set_window_size((8, 5));
+        let overlay = PaneOverlay {
+            rect: rect(0, 0, 8, 5),
+            lines: vec![[(2.5, 2.5), (5.5, 2.5)]],
+            width: 3.0,
+        };
+        renderer.draw_view(&rect(0, 0, 8, 5), None, &[], &[overlay], &[], None);
+        let expected = vec!["........", ".YYYYYY.", ".YYYYYY.", ".YYYYYY.", "........"];
+        assert_eq!(golden(&renderer.read_frame().unwrap()), expected);
+    }
+
+    #[test]
+    fn test_pane_fills() {
+        // Two panes side by side in the second view, the right one has focus and sync.
+        let mut renderer = CpuRenderer::new();
+        renderer.set_window_size((14, 6));
+        let style = PaneStyle {
+            separator_color: [1.0, 1.0, 1.0, 1.0],
+            focus_color: [1.0, 0.0, 0.0, 1.0],
+            focus_width: 1,
+            sync_color: [0.0, 0.0, 1.0, 1.0],
+            badge_size: 2,
+            ..PaneStyle::default()
+        };
+        let sync = PaneBadges {
+            synchronized: true,
+            cine: false,
+        };
+        let panes = [
+            (rect(0, 0, 6, 6), PaneBadges::default()),
+            (rect(6, 0, 6, 6), sync),
+        ];
+        let fills = style.fills(&panes, Some(1));
+        renderer.draw_view(&rect(2, 0, 12, 6), None, &[], &[], &fills, None);
+        // The focus outline covers the separator inside the pane.
+        let expected = vec![
+            ".......WRRRRRR",
+            ".......WR.BB.R",
+            ".......WR.BB.R",
+            ".......WR....R",
+            ".......WR....R",
+            ".......WRRRRRR",
+        ];
+        assert_eq!(golden(&renderer.read_frame().unwrap()), expected);
+    }
+
+    #[test]
+    fn test_scaling_filters() {
+        // A blue and a white pixel upscaled to the view, the red channel goes from 0 to 255.
+        let rect = rect(0, 0, 12, 1);
+        let image = block_image(&["BW"], 1);
+        let expected = [
+            (
+                ScalingFilter::Bilinear,
+                [0, 0, 0, 30, 69, 108, 147, 186, 225, 255, 255, 255],
+            ),
+            // The B-spline smooths the pixels themselves.
+            (
+                ScalingFilter::Bicubic,
+                [12, 23, 38, 59, 85, 113, 142, 170, 196, 217, 232, 243],
+            ),
+            (
+                ScalingFilter::Lanczos,
+                [0, 0, 0, 23, 61, 105, 150, 194, 232, 255, 255, 255],
+            ),
+            (
+                ScalingFilter::Sharp,
+                [0, 0, 0, 25, 63, 106, 149, 192, 230, 255, 255, 255],
+            ),
+        ];
+        for (filter, red) in expected.iter() {
+            let mut renderer = CpuRenderer::new();
+            renderer.set_scaling_filter(*filter);
+            renderer.set_window_size((12, 1));
+            renderer.draw_view(&rect, Some(&image), &[], &[], &[], None);
+            let frame = renderer.read_frame().unwrap();
+            let profile: Vec<u8> = frame.data.chunks_exact(4).map(|pixel| pixel[0]).collect();
+            assert_eq!(&profile[..], &red[..], "{:?}", filter);
+        }
+    }
+
+    #[test]
+    fn test_render_rgba_sample() {
+        gst::init().unwrap();
+        let info = gst_video::VideoInfo::builder(gst_video::VideoFormat::Rgba, 4, 4)
+            .build()
+            .unwrap();
+        let buffer = gst::Buffer::from_mut_slice(block_image(&["G"], 4).data);
+        let sample = gst::Sample::builder()
+            .buffer(&buffer)
+            .caps(&info.to_caps().unwrap())
+            .build();
+        let frame = ViewFrame {
+            sample: Some(ViewSample {
+                sample,
+                id: 0,
+                timer: std::time::Instant::now(),
+                rtp_time: None,
+            }),
+            rect: rect(1, 0, 4, 4),
+            text: None,
+            debug_text: None,
+            panes: Vec::new(),
+            overlays: Vec::new(),
+            fills: Vec::new(),
+        };
+        let mut renderer = CpuRenderer::new();
+        renderer.set_window_size((6, 4));
+        renderer.render_frames(vec![frame]);
+        let expected = vec![".GGGG.", ".GGGG.", ".GGGG.", ".GGGG."];
+        assert_eq!(golden(&renderer.read_frame().unwrap()), expected);
+    }
+
+    #[test]
+    fn test_redraws_changed_views() {
+        let fill = |color| Fill {
+            rect: rect(0, 0, 2, 2),
+            color,
+        };
+        let frame = |x, color| ViewFrame {
+            sample: None,
+            rect: rect(x, 0, 2, 2),
+            text: None,
+            debug_text: None,
+            panes: Vec::new(),
+            overlays: Vec::new(),
+            fills: vec![fill(color)],
+        };
+        let red = [1.0, 0.0, 0.0, 1.0];
+        let green = [0.0, 1.0, 0.0, 1.0];
+        let mut renderer = CpuRenderer::new();
+        renderer.set_window_size((4, 2));
+        let damage = renderer.render_frames(vec![frame(0, red), frame(2, red)]);
+        assert_eq!(damage, Damage::Window);
+        assert_eq!(
+            golden(&renderer.read_frame().unwrap()),
+            vec!["RRRR", "RRRR"]
+        );
+
+        // Only the second view is drawn again, the first is left as it is.
+        renderer.target.data[..4].copy_from_slice(&[0, 0, 255, 255]);
+        let damage = renderer.render_frames(vec![frame(0, red), frame(2, green)]);
+        assert_eq!(damage, Damage::Views(vec![rect(2, 0, 2, 2)]));
+        assert_eq!(
+            golden(&renderer.read_frame().unwrap()),
+            vec!["BRGG", "RRGG"]
+        );
+        let damage = renderer.render_frames(vec![frame(0, red), frame(2, green)]);
+        assert_eq!(damage, Damage::None);
+
+        // A new layout redraws the window.
+        let damage = renderer.render_frames(vec![frame(0, green)]);
+        assert_eq!(damage, Damage::Window);
+        assert_eq!(
+            golden(&renderer.read_frame().unwrap()),
+            vec!["GG..", "GG.."]
+        );
+    }
+
+    #[test]
+    fn test_text_is_clipped_to_view() {
+        let mut renderer = CpuRenderer::new();
+        renderer.set_window_size((200, 100));
+        let view = rect(0, 0, 100, 100);
+        let mut partition = TextPartition::new(Partition::TL, (100.0, 100.0));
+        partition.add_text(vec!["Recovering..."]);
+        renderer.draw_view(&view, None, &[], &[], &[], Some(vec![partition]));
+        let frame = golden(&renderer.read_frame().unwrap());
+        assert!(frame.iter().any(|row| row[..100].contains('W')));
+        assert!(frame.iter().all(|row| row[100..].chars().all(|c| c == '.')));
+    }
+
+    #[test]
+    fn test_text_shadow() {
+        let mut renderer = CpuRenderer::new();
+        renderer.set_window_size((100, 50));
+        renderer.set_text_style(TextStyle {
+            color: [0.0, 0.0, 1.0, 1.0],
+            effect_color: [1.0, 0.0, 0.0, 1.0],
+            ..TextStyle::shadow()
+        });
+        let view = rect(0, 0, 100, 50);
+        let mut partition = TextPartition::new(Partition::TL, (100.0, 50.0));
+        partition.add_text(vec!["I"]);
+        renderer.draw_view(&view, None, &[], &[], &[], Some(vec![partition]));
+        let frame = golden(&renderer.read_frame().unwrap());
+        // The shadow shows below and to the right of the blue text.
+        let first = |c| frame.iter().position(|row| row.contains(c)).unwrap();
+        let last = |c| frame.iter().rposition(|row| row.contains(c)).unwrap();
+        assert_eq!(first('R'), first('B') + 2);
+        assert_eq!(last('R'), last('B') + 2);
+        let column = |c| frame[first('B') + 4].find(c).unwrap();
+        assert_eq!(column('R'), column('B') + 2);
+    }
+}
@@ -0,0 +1,182 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+// BT.709, as the rgb_to_yuv matrix in the scaling shader.
+const LUMA: [f32; 3] = [0.2126, 0.7152, 0.0722];
+const GAMMA_LIMITS: (f32, f32) = (0.2, 5.0);
+const CONTRAST_LIMITS: (f32, f32) = (0.1, 10.0);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DisplayMode {
+    Color,
+    // Only the luma is shown.
+    Grey,
+}
+
+/// How the decoded colours are mapped for display, set as uniforms of the scaling shader.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DisplaySettings {
+    pub mode: DisplayMode,
+    // Snap near-grey pixels to their luma, the encoder leaves a little chroma in greys.
+    pub grey_clamp: bool,
+    // Largest chroma, in 8 bit steps, that is still clamped.
+    pub chroma_threshold: f32,
+    pub gamma: f32,
+    // Scales the distance from mid grey.
+    pub contrast: f32,
+}
+
+impl Default for DisplaySettings {
+    fn default() -> Self {
+        Self {
+            mode: DisplayMode::Color,
+            grey_clamp: true,
+            chroma_threshold: 1.5,
+            gamma: 1.0,
+            contrast: 1.0,
+        }
+    }
+}
+
+impl DisplaySettings {
+    /// Keeps all colours, for colour overlays such as PET fusion.
+    pub fn fusion() -> Self {
+        Self {
+            grey_clamp: false,
+            ..Self::default()
+        }
+    }
+
+    pub fn grey() -> Self {
+        Self {
+            mode: DisplayMode::Grey,
+            ..Self::default()
+        }
+    }
+
+    /// Parses a preset name or a path to a JSON file.
+    pub fn from_arg(arg: &str) -> Result<Self> {
+        match arg {
+            "default" | "color" => Ok(Self::default()),
+            "fusion" => Ok(Self::fusion()),
+            "grey" | "gray" => Ok(Self::grey()),
+            path => Self::from_json(&std::fs::read_to_string(path)?),
+        }
+    }
+
+    // Values the shader can not map are rejected, gamma and contrast are clamped
+    // to the range of the keys.
+    fn from_json(json: &str) -> Result<Self> {
+        let settings: Self = serde_json::from_str(json)?;
+        let values = [settings.chroma_threshold, settings.gamma, settings.contrast];
+        if values.iter().any(|v| !v.is_finite()) || settings.chroma_threshold < 0.0 {
+            bail!("Invalid display settings {:?}", settings);
+        }
+        Ok(Self {
+            gamma: settings.gamma.clamp(GAMMA_LIMITS.0, GAMMA_LIMITS.1),
+            contrast: settings
+                .contrast
+                .clamp(CONTRAST_LIMITS.0, CONTRAST_LIMITS.1),
+            ..settings
+        })
+    }
+
+    pub fn toggle_mode(&mut self) {
+        self.mode = match self.mode {
+            DisplayMode::Color => DisplayMode::Grey,
+            DisplayMode::Grey => DisplayMode::Color,
+        };
+    }
+
+    pub fn adjust_gamma(&mut self, delta: f32) {
+        self.gamma = (self.gamma + delta).clamp(GAMMA_LIMITS.0, GAMMA_LIMITS.1);
+    }
+
+    pub fn adjust_contrast(&mut self, delta: f32) {
+        self.contrast = (self.contrast + delta).clamp(CONTRAST_LIMITS.0, CONTRAST_LIMITS.1);
+    }
+
+    /// The display mapping of the shader, for the CPU renderer.
+    pub fn apply(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let luma = rgb[0] * LUMA[0] + rgb[1] * LUMA[1] + rgb[2] * LUMA[2];
+        let rgb = match self.mode {
+            DisplayMode::Grey => [luma; 3],
+            DisplayMode::Color if self.grey_clamp && self.is_grey(rgb) => [luma; 3],
+            DisplayMode::Color => rgb,
+        };
+        let mut out = [0.0; 3];
+        for (o, c) in out.iter_mut().zip(rgb.iter()) {
+            let c = ((c - 0.5) * self.contrast + 0.5).clamp(0.0, 1.0);
+            *o = c.powf(1.0 / self.gamma);
+        }
+        out
+    }
+
+    fn is_grey(&self, rgb: [f32; 3]) -> bool {
+        // The chroma rows of the rgb_to_yuv matrix in the shader.
+        let u = -0.114572 * rgb[0] - 0.385428 * rgb[1] + 0.5 * rgb[2];
+        let v = 0.5 * rgb[0] - 0.454153 * rgb[1] - 0.045847 * rgb[2];
+        u.abs().max(v.abs()) * 255.0 <= self.chroma_threshold
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: [f32; 3], b: [f32; 3]) -> bool {
+        a.iter().zip(b.iter()).all(|(a, b)| (a - b).abs() < 1e-5)
+    }
+
+    #[test]
+    fn test_grey_clamp() {
+        // A grey with a little chroma from the encoder.
+        let greyish = [0.5, 0.502, 0.5];
+        let clamped = DisplaySettings::default().apply(greyish);
+        assert!(close(clamped, [clamped[0]; 3]));
+        assert!(close(DisplaySettings::fusion().apply(greyish), greyish));
+        // A colour is kept, unless only the luma is shown.
+        let red = [1.0, 0.0, 0.0];
+        assert!(close(DisplaySettings::default().apply(red), red));
+        assert!(close(DisplaySettings::grey().apply(red), [LUMA[0]; 3]));
+    }
+
+    #[test]
+    fn test_gamma_and_contrast() {
+        let mut settings = DisplaySettings::fusion();
+        settings.contrast = 2.0;
+        assert!(close(settings.apply([0.25, 0.5, 1.0]), [0.0, 0.5, 1.0]));
+        settings.contrast = 1.0;
+        settings.gamma = 2.0;
+        assert!(close(settings.apply([0.25, 0.0, 1.0]), [0.5, 0.0, 1.0]));
+        settings.adjust_gamma(10.0);
+        assert_eq!(settings.gamma, GAMMA_LIMITS.1);
+    }
+
+    #[test]
+    fn test_from_arg() {
+        assert_eq!(
+            DisplaySettings::from_arg("grey").unwrap().mode,
+            DisplayMode::Grey
+        );
+        assert!(!DisplaySettings::from_arg("fusion").unwrap().grey_clamp);
+        let json = r#"{"chroma_threshold": 3.0, "gamma": 1.2}"#;
+        let settings: DisplaySettings = serde_json::from_str(json).unwrap();
+        assert!(settings.grey_clamp);
+        assert_eq!(settings.chroma_threshold, 3.0);
+        assert!(DisplaySettings::from_arg("no-such-file.json").is_err());
+    }
+
+    #[test]
+    fn test_from_json_limits() {
+        let settings = DisplaySettings::from_json(r#"{"gamma": 0.0, "contrast": -1.0}"#).unwrap();
+        assert_eq!(settings.gamma, GAMMA_LIMITS.0);
+        assert_eq!(settings.contrast, CONTRAST_LIMITS.0);
+        assert!(settings.apply([0.5; 3]).iter().all(|c| c.is_finite()));
+        assert!(DisplaySettings::from_json(r#"{"chroma_threshold": -1.0}"#).is_err());
+        // JSON has no NaN, but a huge number overflows to infinity.
+        assert!(DisplaySettings::from_json(r#"{"gamma": 1e999}"#).is_err());
+    }
+}
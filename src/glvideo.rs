@@ -1,3 +1,4 @@
+use anyhow::Result;
 use gst_gl::VideoFrameGLExt;
 use gstreamer as gst;
 use gstreamer_gl as gst_gl;
@@ -6,9 +7,14 @@ use gstreamer_video as gst_video;
 use view_state::Zoom;
 
 use crate::{
-    text_renderer::{Partition, TextPartition, TextRenderer},
+    display::{DisplayMode, DisplaySettings},
+    message::LayoutRect,
+    renderer::{
+        Damage, DamageTracker, Fill, Line, PaneFrame, PaneOverlay, Renderer, ScalingFilter,
+        ViewFrame,
+    },
+    text_renderer::{TextPartition, TextRenderer, TextStyle},
     vertex::{self, Quad},
-    view::ViewControl,
     view_state::{self, ViewState},
 };
 
@@ -19,6 +25,35 @@ use std::{
     mem, ptr,
 };
 
+// Locations of the uniforms in the scaling shader.
+const FILTER_MODE_LOCATION: i32 = 0;
+const GREY_CLAMP_LOCATION: i32 = 1;
+const CHROMA_THRESHOLD_LOCATION: i32 = 2;
+const GAMMA_LOCATION: i32 = 3;
+const CONTRAST_LOCATION: i32 = 4;
+// Location of the colour in the line shader.
+const LINE_COLOR_LOCATION: i32 = 0;
+// Locations of the colour and offset in the text shaders.
+const TEXT_COLOR_LOCATION: i32 = 0;
+const TEXT_OFFSET_LOCATION: i32 = 1;
+// Measurements are drawn in yellow.
+const LINE_COLOR: [f32; 4] = [1.0, 1.0, 0.0, 1.0];
+
+/// A rendered frame read back from the offscreen framebuffer.
+/// The pixels are tightly packed RGBA, top row first.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct OffscreenTarget {
+    framebuffer: u32,
+    color_buffer: u32,
+}
+
 pub struct GlRenderer {
     bindings: gl::Gl,
     image_vao: u32,
@@ -27,6 +62,7 @@ pub struct GlRenderer {
     program_argb: u32,
     program_grey: u32,
     program_text: u32,
+    program_line: u32,
     quad: Quad,
     state: ViewState,
     own_ctx: gst_gl::GLContext,
@@ -36,7 +72,14 @@ pub struct GlRenderer {
     text_vertex_buffer: u32,
     text_index_buffer: u32,
     text_vertex_buffer_len: usize,
+    text_index_buffer_len: usize,
     text_renderer: TextRenderer,
+    offscreen: Option<OffscreenTarget>,
+    // Set when the offscreen target keeps the window and is copied to the back buffer.
+    retained: bool,
+    damage: DamageTracker,
+    filter: ScalingFilter,
+    display: DisplaySettings,
 }
 
 impl GlRenderer {
@@ -68,17 +111,27 @@ impl GlRenderer {
             // include_str!("shaders/glfrag_argb.glsl"),
         );
 
-        // This program is not used anymore!
+        // The grey display mode, the scaling shader showing only the luma.
         let program_grey = Self::compile_program(
             &bindings,
             include_str!("shaders/glvert.glsl"),
-            include_str!("shaders/glfrag_argb_grey.glsl"),
+            &include_str!("shaders/glfrag_argb_scaling.glsl").replacen(
+                "#version 450",
+                "#version 450\n#define GREY_DISPLAY",
+                1,
+            ),
         );
         let program_text = Self::compile_program(
             &bindings,
             include_str!("shaders/glvert_text.glsl"),
             include_str!("shaders/glfrag_text.glsl"),
         );
+        // Lines share the vertex layout of the text.
+        let program_line = Self::compile_program(
+            &bindings,
+            include_str!("shaders/glvert_text.glsl"),
+            include_str!("shaders/glfrag_line.glsl"),
+        );
         let (image_vao, image_vertex_buffer, image_index_buffer) =
             Self::create_vao(&bindings, true);
         // We need dynamic sizes of the vertex-/index-buffers.
@@ -101,14 +154,168 @@ impl GlRenderer {
             pipe_ctx,
             window_size: (0, 0),
             program_text,
+            program_line,
             text_vao,
             text_vertex_buffer,
             text_vertex_buffer_len: 0,
+            text_index_buffer_len: 0,
             text_index_buffer,
             text_renderer,
+            offscreen: None,
+            retained: false,
+            damage: DamageTracker::default(),
+            filter: ScalingFilter::Lanczos,
+            display: DisplaySettings::default(),
+        }
+    }
+
+    unsafe fn create_offscreen_target(bindings: &gl::Gl) -> OffscreenTarget {
+        let mut framebuffer = mem::MaybeUninit::uninit();
+        bindings.GenFramebuffers(1, framebuffer.as_mut_ptr());
+        let framebuffer = framebuffer.assume_init();
+
+        let mut color_buffer = mem::MaybeUninit::uninit();
+        bindings.GenRenderbuffers(1, color_buffer.as_mut_ptr());
+        let color_buffer = color_buffer.assume_init();
+
+        OffscreenTarget {
+            framebuffer,
+            color_buffer,
         }
     }
 
+    unsafe fn delete_offscreen_target(&self, target: OffscreenTarget) {
+        self.bindings.DeleteFramebuffers(1, &target.framebuffer);
+        self.bindings.DeleteRenderbuffers(1, &target.color_buffer);
+    }
+
+    unsafe fn allocate_offscreen_target(&self, target: &OffscreenTarget) -> Result<()> {
+        // (Re)allocate the color buffer to match the window size.
+        self.bindings
+            .BindRenderbuffer(gl::RENDERBUFFER, target.color_buffer);
+        self.bindings.RenderbufferStorage(
+            gl::RENDERBUFFER,
+            gl::RGBA8,
+            self.window_size.0.max(1) as _,
+            self.window_size.1.max(1) as _,
+        );
+        self.bindings.BindRenderbuffer(gl::RENDERBUFFER, 0);
+
+        self.bindings
+            .BindFramebuffer(gl::FRAMEBUFFER, target.framebuffer);
+        self.bindings.FramebufferRenderbuffer(
+            gl::FRAMEBUFFER,
+            gl::COLOR_ATTACHMENT0,
+            gl::RENDERBUFFER,
+            target.color_buffer,
+        );
+        let status = self.bindings.CheckFramebufferStatus(gl::FRAMEBUFFER);
+        self.bindings.BindFramebuffer(gl::FRAMEBUFFER, 0);
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            anyhow::bail!("Offscreen framebuffer is incomplete: {:#x}", status);
+        }
+        Ok(())
+    }
+
+    pub fn enable_offscreen(&mut self) -> Result<()> {
+        // Render into a framebuffer object instead of the default framebuffer,
+        // used when there is no window.
+        if self.offscreen.is_none() {
+            unsafe {
+                let target = Self::create_offscreen_target(&self.bindings);
+                if let Err(e) = self.allocate_offscreen_target(&target) {
+                    self.delete_offscreen_target(target);
+                    return Err(e);
+                }
+                self.offscreen = Some(target);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn enable_retained(&mut self) -> Result<()> {
+        // Keep the window in a framebuffer object and copy it to the back buffer after
+        // each render. Views that did not change are left as they are, whatever the
+        // buffer age of the back buffer.
+        self.enable_offscreen()?;
+        self.retained = true;
+        Ok(())
+    }
+
+    // Copies the retained window to the back buffer.
+    unsafe fn blit_to_window(&self, target: &OffscreenTarget) {
+        let (width, height) = (self.window_size.0 as i32, self.window_size.1 as i32);
+        self.bindings
+            .BindFramebuffer(gl::READ_FRAMEBUFFER, target.framebuffer);
+        self.bindings.BindFramebuffer(gl::DRAW_FRAMEBUFFER, 0);
+        self.bindings.BlitFramebuffer(
+            0,
+            0,
+            width,
+            height,
+            0,
+            0,
+            width,
+            height,
+            gl::COLOR_BUFFER_BIT,
+            gl::NEAREST,
+        );
+        self.bindings.BindFramebuffer(gl::FRAMEBUFFER, 0);
+    }
+
+    /// Reads the video frame of a sample back from its texture, at the size it was decoded.
+    /// The pixels are RGBA, top row first.
+    pub fn read_sample(&self, sample: &gst::Sample) -> Option<Frame> {
+        let buffer = sample.get_buffer_owned()?;
+        let info = sample
+            .get_caps()
+            .and_then(|caps| gst_video::VideoInfo::from_caps(caps).ok())?;
+        if let Some(sync_meta) = buffer.get_meta::<gst_gl::GLSyncMeta>() {
+            sync_meta.set_sync_point(&self.pipe_ctx);
+        }
+        let frame = gst_video::VideoFrame::from_buffer_readable_gl(buffer, &info).ok()?;
+        if let Some(sync_meta) = frame.buffer().get_meta::<gst_gl::GLSyncMeta>() {
+            sync_meta.wait(&self.own_ctx);
+        }
+        let texture = frame.get_texture_id(0)?;
+
+        let (width, height) = (info.width(), info.height());
+        let mut data = vec![0_u8; width as usize * height as usize * 4];
+        unsafe {
+            // Attach the texture to a framebuffer to read it.
+            let mut framebuffer = mem::MaybeUninit::uninit();
+            self.bindings.GenFramebuffers(1, framebuffer.as_mut_ptr());
+            let framebuffer = framebuffer.assume_init();
+            self.bindings
+                .BindFramebuffer(gl::READ_FRAMEBUFFER, framebuffer);
+            self.bindings.FramebufferTexture2D(
+                gl::READ_FRAMEBUFFER,
+                gl::COLOR_ATTACHMENT0,
+                gl::TEXTURE_2D,
+                texture,
+                0,
+            );
+            self.bindings.PixelStorei(gl::PACK_ALIGNMENT, 1);
+            self.bindings.ReadPixels(
+                0,
+                0,
+                width as _,
+                height as _,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                data.as_mut_ptr() as _,
+            );
+            self.bindings.BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+            self.bindings.DeleteFramebuffers(1, &framebuffer);
+        }
+        // Unlike the window, the texture rows are in the order of the video frame.
+        Some(Frame {
+            width,
+            height,
+            data,
+        })
+    }
+
     unsafe fn compile_program(bindings: &gl::Gl, vs_src: &str, fs_src: &str) -> u32 {
         let vs = Self::compile_shader(bindings, vs_src, gl::VERTEX_SHADER);
         let fs = Self::compile_shader(bindings, fs_src, gl::FRAGMENT_SHADER);
@@ -313,7 +520,9 @@ impl GlRenderer {
     }
 
     unsafe fn update_text_vertex_buffer(&mut self, vertices: &[vertex::Vertex], indicies: &[u16]) {
-        if vertices.len() > self.text_vertex_buffer_len {
+        if vertices.len() > self.text_vertex_buffer_len
+            || indicies.len() > self.text_index_buffer_len
+        {
             // Need to allocate a new buffer.
             self.bindings
                 .BindBuffer(gl::ARRAY_BUFFER, self.text_vertex_buffer);
@@ -336,6 +545,7 @@ impl GlRenderer {
                 gl::STREAM_DRAW,
             );
             self.bindings.BindBuffer(gl::ELEMENT_ARRAY_BUFFER, 0);
+            self.text_index_buffer_len = indicies.len();
         } else {
             // We have enough space in the existing buffers.
             self.bindings
@@ -365,12 +575,20 @@ impl GlRenderer {
         self.update_image_vertex_buffer(vertices);
 
         if use_grey {
-            // Use a shader that ensures real greys!
-            log::warn!("Using a forced grey shader");
             self.bindings.UseProgram(self.program_grey);
         } else {
             self.bindings.UseProgram(self.program_argb);
+            // Grey clamping is only done on colours.
+            self.bindings
+                .Uniform1i(GREY_CLAMP_LOCATION, self.display.grey_clamp as i32);
+            self.bindings
+                .Uniform1f(CHROMA_THRESHOLD_LOCATION, self.display.chroma_threshold);
         }
+        self.bindings
+            .Uniform1i(FILTER_MODE_LOCATION, self.filter as i32);
+        self.bindings.Uniform1f(GAMMA_LOCATION, self.display.gamma);
+        self.bindings
+            .Uniform1f(CONTRAST_LOCATION, self.display.contrast);
         self.bindings.BindVertexArray(self.image_vao);
 
         // Activate and bind the textures
@@ -383,11 +601,16 @@ impl GlRenderer {
         self.bindings
             .TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as _);
 
-            // Use nearest since we do custom sampling in the shader.
+        // Use nearest when we do custom sampling in the shader,
+        // bilinear and bicubic are built on linear texture lookups.
+        let texture_filter = match self.filter {
+            ScalingFilter::Bilinear | ScalingFilter::Bicubic => gl::LINEAR,
+            ScalingFilter::Lanczos | ScalingFilter::Sharp => gl::NEAREST,
+        };
         self.bindings
-            .TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as _);
+            .TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, texture_filter as _);
         self.bindings
-            .TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as _);
+            .TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, texture_filter as _);
 
 
         self.bindings
@@ -434,11 +657,9 @@ impl GlRenderer {
             .expect("Failed to get viewport size from partition");
 
         // Get the dynamic content from the text renderer
-        let (texture_id, vertices, indicies) = self.text_renderer.draw(
-            &self.bindings,
-            text.iter().map(|partition| partition.section()).collect(),
-            viewport_size,
-        );
+        let (texture_id, vertices, indicies) =
+            self.text_renderer
+                .draw(&self.bindings, &text, viewport_size);
 
         // Update the vertex and index buffers.
         self.update_text_vertex_buffer(&vertices, &indicies);
@@ -458,6 +679,79 @@ impl GlRenderer {
         self.bindings.ActiveTexture(gl::TEXTURE0); // Activate texture unit 0
         self.bindings.BindTexture(gl::TEXTURE_2D, texture_id);
 
+        // The outline or shadow first, then the text over it.
+        let style = self.text_renderer.style().clone();
+        let passes = self
+            .text_renderer
+            .effect_offsets()
+            .into_iter()
+            .map(|offset| (offset, style.effect_color))
+            .chain(std::iter::once(((0.0, 0.0), style.color)));
+        for ((dx, dy), color) in passes {
+            // Pixels to normalized device coordinates.
+            self.bindings.Uniform2f(
+                TEXT_OFFSET_LOCATION,
+                dx * 2.0 / viewport_size.0,
+                -dy * 2.0 / viewport_size.1,
+            );
+            self.bindings
+                .Uniform4f(TEXT_COLOR_LOCATION, color[0], color[1], color[2], color[3]);
+            self.bindings.DrawElements(
+                gl::TRIANGLES,
+                indicies.len() as _,
+                gl::UNSIGNED_SHORT,
+                ptr::null(),
+            );
+        }
+
+        // Unbind resources
+        self.bindings.BindVertexArray(0);
+        self.bindings.ActiveTexture(gl::TEXTURE0); // Activate texture unit 0
+        self.bindings.BindTexture(gl::TEXTURE_2D, 0);
+        self.bindings.UseProgram(0);
+        self.bindings.Disable(gl::BLEND);
+    }
+
+    // Draws the lines of the overlay over its pane.
+    unsafe fn draw_overlay(&mut self, view: &LayoutRect, overlay: &PaneOverlay) {
+        if overlay.lines.is_empty() {
+            return;
+        }
+        let rect = LayoutRect {
+            x: view.x + overlay.rect.x,
+            y: view.y + overlay.rect.y,
+            width: overlay.rect.width,
+            height: overlay.rect.height,
+        };
+        self.set_view_rect(&rect);
+        // GL lines are only 1 pixel wide, draw each line as a quad of the width.
+        // Pane pixels to normalized device coordinates.
+        let size = (rect.width as f32, rect.height as f32);
+        let vertices: Vec<_> = overlay
+            .lines
+            .iter()
+            .flat_map(|line| line_quad(line, overlay.width))
+            .map(|p| {
+                vertex::Vertex::new(
+                    [p.0 / size.0 * 2.0 - 1.0, 1.0 - p.1 / size.1 * 2.0],
+                    [0.0, 0.0],
+                )
+            })
+            .collect();
+        let indicies: Vec<u16> = (0..overlay.lines.len() as u16)
+            .flat_map(|line| Quad::INDICES.iter().map(move |i| line * 4 + i))
+            .collect();
+        self.update_text_vertex_buffer(&vertices, &indicies);
+
+        self.bindings.UseProgram(self.program_line);
+        self.bindings.Uniform4f(
+            LINE_COLOR_LOCATION,
+            LINE_COLOR[0],
+            LINE_COLOR[1],
+            LINE_COLOR[2],
+            LINE_COLOR[3],
+        );
+        self.bindings.BindVertexArray(self.text_vao);
         self.bindings.DrawElements(
             gl::TRIANGLES,
             indicies.len() as _,
@@ -467,8 +761,47 @@ impl GlRenderer {
 
         // Unbind resources
         self.bindings.BindVertexArray(0);
-        self.bindings.ActiveTexture(gl::TEXTURE0); // Activate texture unit 0
-        self.bindings.BindTexture(gl::TEXTURE_2D, 0);
+        self.bindings.UseProgram(0);
+    }
+
+    // Draws the pane separators, focus outline and badges over the view.
+    unsafe fn draw_fills(&mut self, view: &LayoutRect, fills: &[Fill]) {
+        if fills.is_empty() {
+            return;
+        }
+        self.set_view_rect(view);
+        let size = (view.width as f32, view.height as f32);
+        let ndc = |x: u32, y: u32| [x as f32 / size.0 * 2.0 - 1.0, 1.0 - y as f32 / size.1 * 2.0];
+
+        self.bindings
+            .BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        self.bindings.Enable(gl::BLEND);
+        self.bindings.UseProgram(self.program_line);
+        for Fill { rect, color } in fills {
+            let (right, bottom) = (rect.x + rect.width, rect.y + rect.height);
+            let vertices: Vec<_> = [
+                ndc(rect.x, rect.y),
+                ndc(rect.x, bottom),
+                ndc(right, bottom),
+                ndc(right, rect.y),
+            ]
+            .iter()
+            .map(|p| vertex::Vertex::new(*p, [0.0, 0.0]))
+            .collect();
+            self.update_text_vertex_buffer(&vertices, &Quad::INDICES);
+            self.bindings
+                .Uniform4f(LINE_COLOR_LOCATION, color[0], color[1], color[2], color[3]);
+            self.bindings.BindVertexArray(self.text_vao);
+            self.bindings.DrawElements(
+                gl::TRIANGLES,
+                Quad::INDICES.len() as _,
+                gl::UNSIGNED_SHORT,
+                ptr::null(),
+            );
+        }
+
+        // Unbind resources
+        self.bindings.BindVertexArray(0);
         self.bindings.UseProgram(0);
         self.bindings.Disable(gl::BLEND);
     }
@@ -508,11 +841,58 @@ impl GlRenderer {
         self.quad.map_texture_coords(size, size);
     }
 
+    // Draws to the rect of the window, the image covers it.
+    unsafe fn set_view_rect(&mut self, rect: &LayoutRect) {
+        let view_size = (rect.width as f32, rect.height as f32);
+        self.set_viewport_size(view_size);
+        self.set_frame_size(view_size);
+
+        // Translate to GL coordinates. This can be negative if the window
+        // is smaller than the views.
+        let gl_y = self.window_size.1 as i32 - (rect.y + rect.height) as i32;
+        // Set transformation
+        self.bindings
+            .Viewport(rect.x as _, gl_y as _, rect.width as _, rect.height as _);
+        // Set scissor box
+        self.bindings
+            .Scissor(rect.x as _, gl_y as _, rect.width as _, rect.height as _);
+    }
+
+    // Draws the part of the image under the pane, moved by its prediction.
+    unsafe fn draw_pane(
+        &mut self,
+        view: &LayoutRect,
+        pane: &PaneFrame,
+        image_texture: u32,
+        use_grey: bool,
+    ) {
+        self.set_view_rect(&LayoutRect {
+            x: view.x + pane.rect.x,
+            y: view.y + pane.rect.y,
+            width: pane.rect.width,
+            height: pane.rect.height,
+        });
+        let view_size = (view.width as f32, view.height as f32);
+        self.quad.map_texture_coords_with_offset(
+            (pane.rect.width as f32, pane.rect.height as f32),
+            view_size,
+            (
+                pane.rect.x as f32 / view_size.0,
+                pane.rect.y as f32 / view_size.1,
+            ),
+        );
+        self.quad.set_prediction(pane.prediction.as_ref());
+        let image_vertices = self.quad.get_vertex(&self.state);
+        self.quad.set_prediction(None);
+        self.draw_image(&image_vertices, image_texture, use_grey);
+    }
+
     pub fn render(
         &mut self,
         sample: gst::Sample,
+        rect: &LayoutRect,
+        panes: &[PaneFrame],
         use_grey: bool,
-        text: Option<Vec<TextPartition>>,
     ) {
         // Get the texture id from the sample.
 
@@ -542,80 +922,204 @@ impl GlRenderer {
             if let Some(image_texture) = frame.get_texture_id(0) {
                 log::trace!("Got frame texture with id {}", image_texture);
 
-                // Compute the vertices to use
-                let image_vertices = self.quad.get_vertex(&self.state);
-                self.draw(image_vertices, image_texture, use_grey, text);
+                if panes.is_empty() {
+                    // Compute the vertices to use
+                    let image_vertices = self.quad.get_vertex(&self.state);
+                    self.draw(image_vertices, image_texture, use_grey, None);
+                } else {
+                    for pane in panes {
+                        unsafe { self.draw_pane(rect, pane, image_texture, use_grey) };
+                    }
+                }
             }
         }
     }
 
-    pub fn render_views(&mut self, control: &ViewControl) {
-        // Clear the window back-buffer before setting the scissor box.
-        // This ensures that the entire view is cleared.
-        self.clear();
+    pub fn set_pipe_context(&mut self, pipe_ctx: gst_gl::GLContext) {
+        // The pipeline was rebuilt, sync against the context of the new pipeline.
+        self.pipe_ctx = pipe_ctx;
+        self.damage.invalidate();
+    }
+}
 
-        unsafe {
-            self.bindings.Enable(gl::SCISSOR_TEST);
+// The corners of a line widened to a rectangle, with square ends.
+fn line_quad(line: &Line, width: f32) -> [(f32, f32); 4] {
+    let [a, b] = *line;
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let len = (dx * dx + dy * dy).sqrt();
+    // A point is drawn as a square.
+    let (dx, dy) = if len > 0.0 {
+        (dx / len, dy / len)
+    } else {
+        (1.0, 0.0)
+    };
+    let half = width.max(1.0) / 2.0;
+    let (ex, ey) = (dx * half, dy * half);
+    let (nx, ny) = (-ey, ex);
+    [
+        (a.0 - ex + nx, a.1 - ey + ny),
+        (a.0 - ex - nx, a.1 - ey - ny),
+        (b.0 + ex - nx, b.1 + ey - ny),
+        (b.0 + ex + nx, b.1 + ey + ny),
+    ]
+}
+
+impl Drop for GlRenderer {
+    fn drop(&mut self) {
+        // Dropped on the thread the main context is current on.
+        if let Some(target) = self.offscreen.take() {
+            unsafe { self.delete_offscreen_target(target) };
         }
+    }
+}
 
-        // Get the position of the ViewControl
-        let control_layout = control.get_layout();
+impl Renderer for GlRenderer {
+    fn set_window_size(&mut self, size: (u32, u32)) {
+        self.window_size = size;
+        if let Some(target) = self.offscreen.take() {
+            match unsafe { self.allocate_offscreen_target(&target) } {
+                Ok(()) => self.offscreen = Some(target),
+                Err(e) => {
+                    // A window is drawn directly from now on, headless there are no frames.
+                    log::error!("Failed to resize the offscreen target: {:?}", e);
+                    unsafe { self.delete_offscreen_target(target) };
+                    self.retained = false;
+                }
+            }
+        }
+        self.damage.invalidate();
+    }
 
-        let view_samples = control.active_map(|view| {
-            (
-                view.get_current_sample(),
-                view.get_layout(),
-                view.get_timestamp(),
-            )
-        });
+    fn set_scaling_filter(&mut self, filter: ScalingFilter) {
+        self.filter = filter;
+        self.damage.invalidate();
+    }
 
-        for (sample, view_layout, timestamp) in view_samples {
-            // Check if we have a sample
+    fn scaling_filter(&self) -> ScalingFilter {
+        self.filter
+    }
 
-            let view_size = (view_layout.width as f32, view_layout.height as f32);
-            self.set_viewport_size(view_size);
-            self.set_frame_size(view_size);
+    fn set_display(&mut self, display: DisplaySettings) {
+        self.display = display;
+        self.damage.invalidate();
+    }
 
-            let text = if log::log_enabled!(log::Level::Debug) {
-                let mut text = TextPartition::new(Partition::BR, view_size);
-                text.add_text(vec![&format!("C: {}", timestamp), "_"]);
-                Some(vec![text])
-            } else {
-                None
-            };
+    fn display(&self) -> DisplaySettings {
+        self.display
+    }
 
-            // Compute the postion for the view
-            let top = control_layout.y + view_layout.y;
-            let left = control_layout.x + view_layout.x;
+    fn set_scale_factor(&mut self, scale_factor: f32) {
+        self.text_renderer.set_scale_factor(scale_factor);
+        self.damage.invalidate();
+    }
+
+    fn set_text_style(&mut self, style: TextStyle) {
+        self.text_renderer.set_style(style);
+        self.damage.invalidate();
+    }
+
+    fn render_frames(&mut self, frames: Vec<ViewFrame>) -> Damage {
+        // Without a retained window the back buffer has to be drawn in full.
+        if self.offscreen.is_none() {
+            self.damage.invalidate();
+        }
+        let (damage, frames) = self.damage.update(frames);
+        if damage == Damage::None {
+            return damage;
+        }
+        if let Some(target) = self.offscreen.as_ref() {
             unsafe {
-                // Translate to GL coordinates. This can be negative if the window
-                // is smaller than the views.
-                let gl_y = self.window_size.1 as i32 - (top + view_layout.height) as i32;
-                // Set transformation
-                self.bindings.Viewport(
-                    left as _,
-                    gl_y as _,
-                    view_layout.width as _,
-                    view_layout.height as _,
-                );
-                // Set scissor box
-                self.bindings.Scissor(
-                    left as _,
-                    gl_y as _,
-                    view_layout.width as _,
-                    view_layout.height as _,
-                );
+                self.bindings
+                    .BindFramebuffer(gl::FRAMEBUFFER, target.framebuffer);
+            }
+        }
+        if damage == Damage::Window {
+            // Clear the window back-buffer before setting the scissor box.
+            // This ensures that the entire view is cleared.
+            self.clear();
+        }
+
+        unsafe {
+            self.bindings.Enable(gl::SCISSOR_TEST);
+        }
+
+        for ViewFrame {
+            sample,
+            rect,
+            text,
+            panes,
+            overlays,
+            fills,
+            ..
+        } in frames
+        {
+            unsafe { self.set_view_rect(&rect) };
+            if damage != Damage::Window {
+                // Only the view is redrawn, clear it within the scissor box.
+                self.clear();
             }
 
             // Do the render, if there is a sample
-            sample.map(|sample| self.render(sample.sample, false, text));
+            if let Some(sample) = sample {
+                let use_grey = self.display.mode == DisplayMode::Grey;
+                self.render(sample.sample, &rect, &panes, use_grey);
+            }
+            unsafe {
+                for overlay in overlays.iter() {
+                    self.draw_overlay(&rect, overlay);
+                }
+                self.draw_fills(&rect, &fills);
+                // Still show the text over an empty view, e.g. while recovering.
+                if let Some(text) = text {
+                    // The text is placed in the whole view.
+                    self.set_view_rect(&rect);
+                    self.draw_text(text);
+                }
+            }
         }
         unsafe {
             self.bindings.Disable(gl::SCISSOR_TEST);
+            if let Some(target) = self.offscreen.as_ref() {
+                self.bindings.BindFramebuffer(gl::FRAMEBUFFER, 0);
+                if self.retained {
+                    self.blit_to_window(target);
+                }
+            }
         }
+        damage
     }
 
-    pub fn set_window_size(&mut self, size: (u32, u32)) {
-        self.window_size = size;
+    fn read_frame(&self) -> Option<Frame> {
+        let target = self.offscreen.as_ref()?;
+        let (width, height) = self.window_size;
+        let row_len = width as usize * 4;
+        let mut data = vec![0_u8; row_len * height as usize];
+        unsafe {
+            self.bindings
+                .BindFramebuffer(gl::READ_FRAMEBUFFER, target.framebuffer);
+            self.bindings.PixelStorei(gl::PACK_ALIGNMENT, 1);
+            self.bindings.ReadPixels(
+                0,
+                0,
+                width as _,
+                height as _,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                data.as_mut_ptr() as _,
+            );
+            self.bindings.BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+        }
+        // GL has the origin in the bottom left corner, flip to get the top row first.
+        let data = data
+            .chunks_exact(row_len.max(1))
+            .rev()
+            .flatten()
+            .copied()
+            .collect();
+        Some(Frame {
+            width,
+            height,
+            data,
+        })
     }
 }
@@ -0,0 +1,205 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    text_renderer::Partition,
+    view_state::{ViewState, Zoom},
+};
+
+// The lines of text in each corner of a pane.
+pub type Corners = Vec<(Partition, Vec<String>)>;
+
+/// Templates of the text in the four corners of each pane, one string per line.
+/// Placeholders: {case} {frame} {frames} {width} {center} {zoom} {variate} {sync}
+/// {cine} {bitrate} {fps}. A line is left out if one of its values is unknown.
+/// Hidden until toggled, unless `visible` is set.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InfoOverlay {
+    pub visible: bool,
+    pub top_left: Vec<String>,
+    pub top_right: Vec<String>,
+    pub bottom_left: Vec<String>,
+    pub bottom_right: Vec<String>,
+}
+
+impl Default for InfoOverlay {
+    fn default() -> Self {
+        let lines = |lines: &[&str]| lines.iter().map(|l| l.to_string()).collect();
+        Self {
+            visible: false,
+            top_left: lines(&["{case}", "Image: {frame}/{frames}"]),
+            top_right: lines(&[
+                "W: {width} L: {center}",
+                "Zoom: {zoom}",
+                "Variate: {variate}",
+            ]),
+            bottom_left: lines(&["{sync}", "{cine}"]),
+            bottom_right: lines(&["{bitrate}", "{fps} fps"]),
+        }
+    }
+}
+
+impl InfoOverlay {
+    /// Only the case and image, without stream details.
+    pub fn minimal() -> Self {
+        Self {
+            visible: true,
+            top_left: vec!["{case}".to_owned(), "{frame}/{frames}".to_owned()],
+            top_right: Vec::new(),
+            bottom_left: Vec::new(),
+            bottom_right: Vec::new(),
+        }
+    }
+
+    /// Parses a preset name or a path to a JSON file.
+    pub fn from_arg(arg: &str) -> Result<Self> {
+        match arg {
+            "default" | "none" | "hidden" => Ok(Self::default()),
+            "shown" => Ok(Self {
+                visible: true,
+                ..Self::default()
+            }),
+            "minimal" => Ok(Self::minimal()),
+            path => {
+                let json = std::fs::read_to_string(path)?;
+                Ok(serde_json::from_str(&json)?)
+            }
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    /// The expanded text of each corner, corners without lines are left out.
+    pub fn corners(&self, info: &PaneInfo) -> Corners {
+        let corners = vec![
+            (Partition::TL, &self.top_left),
+            (Partition::TR, &self.top_right),
+            (Partition::BL, &self.bottom_left),
+            (Partition::BR, &self.bottom_right),
+        ];
+        corners
+            .into_iter()
+            .map(|(partition, templates)| {
+                let lines: Vec<_> = templates.iter().filter_map(|t| info.expand(t)).collect();
+                (partition, lines)
+            })
+            .filter(|(_, lines)| !lines.is_empty())
+            .collect()
+    }
+}
+
+/// What is known about a pane, for the info overlay.
+#[derive(Debug, Clone, Default)]
+pub struct PaneInfo {
+    pub case: Option<String>,
+    pub frames: Option<usize>,
+    pub state: Option<ViewState>,
+    pub synchronized: bool,
+    // Frames per second of the cine loop, if it is running.
+    pub cine: Option<f32>,
+    // Received bitrate of the view in kbit/s.
+    pub bitrate: Option<f64>,
+    // Frames shown per second by the view.
+    pub fps: Option<f32>,
+}
+
+impl PaneInfo {
+    fn value(&self, name: &str) -> Option<String> {
+        match name {
+            "case" => self.case.clone(),
+            // Shown from 1, as in the image number.
+            "frame" => self
+                .state
+                .and_then(|s| s.frame)
+                .map(|f| (f + 1).to_string()),
+            "frames" => self.frames.map(|n| n.to_string()),
+            "width" => self.state.map(|s| format!("{:.2}", s.wl.width)),
+            "center" => self.state.map(|s| format!("{:.2}", s.wl.center)),
+            "zoom" => self.state.map(|s| match s.zoom {
+                Zoom::Fit(m) => format!("{:.2}x", m),
+                Zoom::Pixel(m) => format!("{:.2}x 1:1", m),
+            }),
+            "variate" => self
+                .state
+                .and_then(|s| s.variate)
+                .map(|v| format!("{:.2}", v)),
+            "sync" if self.synchronized => Some("Sync".to_owned()),
+            "cine" => self.cine.map(|fps| format!("Cine {:.0} fps", fps)),
+            "bitrate" => self.bitrate.map(|b| format!("{:.2} Mbit/s", b / 1000.0)),
+            "fps" => self.fps.map(|fps| format!("{:.0}", fps)),
+            _ => None,
+        }
+    }
+
+    /// Replaces the placeholders of a template line, None if a value is unknown.
+    pub fn expand(&self, template: &str) -> Option<String> {
+        let mut line = String::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            let end = start + rest[start..].find('}')?;
+            line.push_str(&rest[..start]);
+            line.push_str(&self.value(&rest[start + 1..end])?);
+            rest = &rest[end + 1..];
+        }
+        line.push_str(rest);
+        Some(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info() -> PaneInfo {
+        let mut state = ViewState::new();
+        state.frame = Some(11);
+        state.set_zoom_mode(Zoom::Fit(1.5));
+        PaneInfo {
+            case: Some("case1".to_owned()),
+            frames: Some(120),
+            state: Some(state),
+            bitrate: Some(2500.0),
+            ..PaneInfo::default()
+        }
+    }
+
+    #[test]
+    fn test_expand() {
+        let info = info();
+        assert_eq!(
+            info.expand("{case}: {frame}/{frames}").as_deref(),
+            Some("case1: 12/120")
+        );
+        assert_eq!(info.expand("Zoom {zoom}").as_deref(), Some("Zoom 1.50x"));
+        assert_eq!(info.expand("{bitrate}").as_deref(), Some("2.50 Mbit/s"));
+        // Unknown values and placeholders leave the line out.
+        assert_eq!(info.expand("{fps} fps"), None);
+        assert_eq!(info.expand("{sync}"), None);
+        assert_eq!(info.expand("{slice}"), None);
+        assert_eq!(info.expand("{case"), None);
+    }
+
+    #[test]
+    fn test_corners() {
+        let overlay = InfoOverlay::default();
+        let corners = overlay.corners(&info());
+        // Nothing is known for the bottom left corner.
+        assert_eq!(corners.len(), 3);
+        assert!(matches!(corners[0].0, Partition::TL));
+        assert_eq!(corners[0].1, vec!["case1", "Image: 12/120"]);
+        assert_eq!(corners[1].1, vec!["W: 1.00 L: 1.00", "Zoom: 1.50x"]);
+        assert_eq!(corners[2].1, vec!["2.50 Mbit/s"]);
+    }
+
+    #[test]
+    fn test_presets() {
+        // Opt in, existing users see no new text.
+        assert!(!InfoOverlay::from_arg("default").unwrap().visible);
+        assert!(InfoOverlay::from_arg("shown").unwrap().visible);
+        assert!(InfoOverlay::from_arg("minimal").unwrap().visible);
+        assert!(!InfoOverlay::from_arg("none").unwrap().visible);
+    }
+}
@@ -1,4 +1,8 @@
-use crate::view_state::ViewState;
+use crate::{
+    measure::{self, Measurements, Point},
+    vertex::ViewTransform,
+    view_state::ViewState,
+};
 use async_tungstenite::tungstenite::protocol::frame;
 use glutin::{
     dpi::PhysicalPosition,
@@ -13,6 +17,8 @@ pub enum InteractionMode {
     FastScroll,
     Wl,
     Variate,
+    // Places the points of a measurement.
+    Measure,
 }
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum SyncOperation {
@@ -42,6 +48,11 @@ pub struct InteractionState {
     cine: bool,
     cine_timer: Option<std::time::Instant>,
     cine_fps: f32,
+
+    measurements: Measurements,
+    // Size of the pane and of the image, to map the pointer to image pixels.
+    viewport: (f32, f32),
+    image_size: Option<(f32, f32)>,
 }
 
 impl InteractionState {
@@ -66,6 +77,9 @@ impl InteractionState {
             cine: false,
             cine_timer: None,
             cine_fps: Self::CINE_FPS,
+            measurements: Measurements::default(),
+            viewport: (0_f32, 0_f32),
+            image_size: None,
         }
     }
 
@@ -102,11 +116,52 @@ impl InteractionState {
         self.scroll_delta = Some(delta);
     }
 
+    pub fn set_viewport(&mut self, viewport: (f32, f32)) {
+        self.viewport = viewport;
+    }
+
+    pub fn set_image_size(&mut self, image_size: Option<(f32, f32)>) {
+        self.image_size = image_size;
+    }
+
+    /// Maps image pixels to pane pixels for the current state, None if the pane is empty.
+    pub fn image_to_pane(&self) -> Option<ViewTransform> {
+        if self.viewport.0 <= 0.0 || self.viewport.1 <= 0.0 {
+            return None;
+        }
+        // Without its size the image is assumed to fill the pane.
+        let image_size = self.image_size.unwrap_or(self.viewport);
+        Some(measure::image_to_pane(
+            &self.viewstate,
+            self.viewport,
+            image_size,
+        ))
+    }
+
+    fn pointer_in_image(&self) -> Option<Point> {
+        let position = self.mouse_position?;
+        let pane_to_image = self.image_to_pane()?.invert();
+        let p = pane_to_image.transform_vertex(&[position.x as f32, position.y as f32]);
+        Some((p[0], p[1]))
+    }
+
+    pub fn measurements(&self) -> &Measurements {
+        &self.measurements
+    }
+
+    pub fn measurements_mut(&mut self) -> &mut Measurements {
+        &mut self.measurements
+    }
+
     fn mode_from_state(&self) -> Option<InteractionMode> {
         if self.left_mouse {
             if self.right_mouse {
                 return Some(InteractionMode::FastScroll);
             }
+            // Left clicks place points while a tool is active, ctrl still zooms.
+            if self.measurements.tool().is_some() && !self.ctrl_pressed {
+                return Some(InteractionMode::Measure);
+            }
             // Pan/Zoom
             if self.ctrl_pressed {
                 return Some(InteractionMode::Zoom);
@@ -187,6 +242,16 @@ impl InteractionState {
             // Reset anchor
             self.anchor = None;
             mode_change = true;
+            // A point is placed when the button goes down.
+            if mode == Some(InteractionMode::Measure) {
+                if let Some(point) = self.pointer_in_image() {
+                    self.measurements.add_point(point);
+                }
+            }
+        }
+        if self.measurements.tool().is_some() {
+            let hover = self.pointer_in_image();
+            self.measurements.set_hover(hover);
         }
         let anchor = self.anchor.or(self.mouse_position);
         let movement = self.mouse_position.map(|p| {
@@ -263,6 +328,7 @@ impl InteractionState {
                         updated = true;
                     }
                 }
+                InteractionMode::Measure => {}
             }
         }
 
@@ -270,6 +336,8 @@ impl InteractionState {
         self.scroll_delta = None;
         // The state has been updated given the current mouse position
         self.anchor = self.mouse_position;
+        self.measurements
+            .set_image(self.viewstate.frame.unwrap_or(0));
         (updated || mode_change, sync_op)
     }
 
@@ -279,6 +347,8 @@ impl InteractionState {
 
     pub fn set_render_state(&mut self, state: ViewState) {
         self.viewstate = state;
+        self.measurements
+            .set_image(self.viewstate.frame.unwrap_or(0));
     }
 
     pub fn toggle_sync(&mut self) {
@@ -298,6 +368,15 @@ impl InteractionState {
         };
     }
 
+    /// The frames per second of the cine loop, if it is running.
+    pub fn cine_fps(&self) -> Option<f32> {
+        if self.cine {
+            Some(self.cine_fps)
+        } else {
+            None
+        }
+    }
+
     pub fn adjust_cine_speec(&mut self, direction: i32) {
         self.cine_fps += (direction as f32) * Self::CINE_ADJUST;
         println!("New cine FPS {}", self.cine_fps);
@@ -324,6 +403,35 @@ impl InteractionState {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{measure::Shape, view_state::Position};
+
+    fn click(state: &mut InteractionState, x: f64, y: f64) {
+        state.handle_move(PhysicalPosition::new(x, y), 0.01);
+        state.handle_mouse_input(MouseButton::Left, ElementState::Pressed);
+        state.update();
+        state.handle_mouse_input(MouseButton::Left, ElementState::Released);
+        state.update();
+    }
+
+    #[test]
+    fn test_measure_mode() {
+        let mut state = InteractionState::new();
+        // A 50x50 image fit to the pane is scaled by 2.
+        state.set_viewport((100.0, 100.0));
+        state.set_image_size(Some((50.0, 50.0)));
+        state.measurements_mut().cycle_tool();
+        click(&mut state, 10.0, 10.0);
+        click(&mut state, 40.0, 50.0);
+        assert_eq!(
+            state.measurements().on_image().collect::<Vec<_>>(),
+            vec![&Shape::Ruler((5.0, 5.0), (20.0, 25.0))]
+        );
+        // Dragging doesn't pan while measuring.
+        assert!(matches!(
+            state.get_render_state().pos,
+            Position::Relative(p) if p == (0.0, 0.0)
+        ));
+    }
     #[test]
     fn test() {
         let a: Option<InteractionMode> = Some(InteractionMode::Pan);
@@ -1,33 +1,50 @@
 use std::{
     convert::TryInto,
-    sync::{mpsc::Sender, Arc, Weak},
+    path::PathBuf,
+    sync::mpsc::{channel, Receiver, Sender},
+    time::{Duration, Instant},
 };
 
-use anyhow::Result;
-use app::{App, AppInner, Decoder};
+use anyhow::{Context, Result};
+use app::{App, SignalTarget};
 use async_std::task::JoinHandle;
 use async_tungstenite::{async_std::connect_async, tungstenite::Message};
+use codec::{DecoderChain, DecoderSelection};
 use futures::{
     channel::mpsc::{unbounded, UnboundedReceiver},
-    future, Sink, SinkExt, Stream, StreamExt, TryStreamExt,
+    future::{self, Either},
+    Sink, SinkExt, Stream, StreamExt, TryStreamExt,
 };
 use glutin::{
     dpi::PhysicalSize,
     event::{ElementState, Event, MouseButton, WindowEvent},
-    event_loop::{ControlFlow, EventLoop},
-    window::WindowBuilder,
-    NotCurrent, RawContext,
+    event_loop::{ControlFlow, EventLoop, EventLoopWindowTarget},
+    window::{Window, WindowBuilder},
+    NotCurrent,
 };
 use message::AppMessage;
-use util::bitrate::Schedule;
+use recording::{Container, RecordingConfig};
+use util::{backoff::Backoff, bitrate::Schedule};
 
 use crate::window_message::WindowMessage;
 
 mod app;
 mod bindings;
+mod codec;
+mod cpu_renderer;
+mod display;
 mod glvideo;
+mod info_overlay;
 mod interaction;
-mod message;
+mod main_context;
+mod measure;
+pub mod message;
+mod network;
+mod pane_style;
+mod recording;
+mod renderer;
+mod snapshot;
+mod stats;
 mod text_renderer;
 mod util;
 mod vertex;
@@ -35,6 +52,28 @@ mod view;
 mod view_state;
 mod window_message;
 
+pub use cpu_renderer::CpuRenderer;
+pub use display::{DisplayMode, DisplaySettings};
+pub use glvideo::Frame;
+pub use info_overlay::InfoOverlay;
+pub use main_context::MainContext;
+pub use network::{NetworkConfig, StreamNetworkConfig};
+pub use pane_style::PaneStyle;
+pub use renderer::{
+    Damage, DamageTracker, Fill, PaneFrame, PaneOverlay, PresentMode, Renderer, ScalingFilter,
+    ViewFrame,
+};
+pub use stats::{
+    CandidatePairStats, InboundRtpStats, JitterStats, LatencySummary, SessionStats, StatsHandle,
+    StreamStats,
+};
+pub use text_renderer::{Align, CornerAlign, Partition, TextEffect, TextPartition, TextStyle};
+pub use window_message::ViewSample;
+
+const RECONNECT_MIN_DELAY: Duration = Duration::from_millis(500);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+const RETRY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
 #[derive(Debug)]
 pub struct AppConfig {
     viewport_size: (u32, u32),
@@ -48,10 +87,24 @@ pub struct AppConfig {
     video_scaling: f32,
     narrow: bool,
     tcp: bool,
-    decoder: Decoder,
-    jitter: u32,
+    decoders: DecoderChain,
+    network: NetworkConfig,
     n_views: usize,
     schedule: Schedule,
+    stats_overlay: bool,
+    stats_csv: Option<String>,
+    // Bounds of the adaptive bitrate factor, None disables it.
+    adaptive_bitrate: Option<(f32, f32)>,
+    recording: RecordingConfig,
+    hold_corrupt: bool,
+    scaling_filter: ScalingFilter,
+    display: DisplaySettings,
+    // File the measurements of each case are saved to.
+    measurements: Option<PathBuf>,
+    info_overlay: InfoOverlay,
+    pane_style: PaneStyle,
+    present_mode: PresentMode,
+    text_style: TextStyle,
 }
 impl AppConfig {
     pub fn new(
@@ -68,23 +121,79 @@ impl AppConfig {
         tcp: bool,
         client_hw: bool,
         fast_sw_decode: bool,
+        decoders: Option<String>,
         jitter: u32,
         n_views: usize,
         scedule_string: String,
-    ) -> Self {
-        let decoder = if fast_sw_decode {
-            Decoder::FastSoftware
-        } else if client_hw {
-            Decoder::Hardware
-        } else {
-            Decoder::Software
+        stats_overlay: bool,
+        stats_csv: Option<String>,
+        adaptive_bitrate: Option<(f32, f32)>,
+        network: String,
+        recording_dir: String,
+        recording_format: String,
+        show_corrupt: bool,
+        scaling_filter: String,
+        display: String,
+        measurements: Option<String>,
+        info_overlay: String,
+        pane_style: String,
+        present_mode: String,
+        text_style: String,
+    ) -> Result<Self> {
+        if let Some((min, max)) = adaptive_bitrate {
+            if !(min > 0.0 && min <= max && max.is_finite()) {
+                anyhow::bail!("Invalid adaptive bitrate bounds {} - {}", min, max);
+            }
+        }
+        // An explicit list of decoders overrides the chain picked by the flags.
+        let decoders = match decoders {
+            Some(list) => {
+                let list: Vec<_> = list
+                    .split(',')
+                    .map(|d| d.trim().to_owned())
+                    .filter(|d| !d.is_empty())
+                    .collect();
+                if list.is_empty() {
+                    anyhow::bail!("Empty list of decoders");
+                }
+                DecoderChain::new(list)
+            }
+            None if fast_sw_decode => DecoderChain::fast_software(),
+            None if client_hw => DecoderChain::hardware(),
+            None => DecoderChain::software(),
         };
+        // A preset name or a JSON file, --jitter sets the latency of the default preset.
+        let network = NetworkConfig::from_arg(&network, jitter)
+            .with_context(|| format!("Invalid network config {}", network))?;
+        let container = Container::from_name(&recording_format)
+            .ok_or_else(|| anyhow::anyhow!("Unknown recording format {}", recording_format))?;
+        let recording = RecordingConfig {
+            dir: recording_dir.into(),
+            container,
+        };
+        let scaling_filter = ScalingFilter::from_name(&scaling_filter)
+            .ok_or_else(|| anyhow::anyhow!("Unknown scaling filter {}", scaling_filter))?;
+        // A preset name or a JSON file.
+        let display = DisplaySettings::from_arg(&display)
+            .with_context(|| format!("Invalid display settings {}", display))?;
+        let info_overlay = InfoOverlay::from_arg(&info_overlay)
+            .with_context(|| format!("Invalid info overlay {}", info_overlay))?;
+        let pane_style = PaneStyle::from_arg(&pane_style)
+            .with_context(|| format!("Invalid pane style {}", pane_style))?;
+        let present_mode = PresentMode::from_name(&present_mode)
+            .ok_or_else(|| anyhow::anyhow!("Unknown present mode {}", present_mode))?;
+        // A preset name or a JSON file, fail early on a font that can not be read.
+        let text_style = TextStyle::from_arg(&text_style)
+            .with_context(|| format!("Invalid text style {}", text_style))?;
+        text_style
+            .load_font()
+            .with_context(|| format!("Invalid font {:?}", text_style.font))?;
         let schedule = match &scedule_string[..] {
             "performance" => Schedule::Performance,
             "quality" => Schedule::Quality,
             _ => Schedule::Default,
         };
-        Self {
+        Ok(Self {
             ws_url,
             viewport_size,
             case_key,
@@ -96,26 +205,60 @@ impl AppConfig {
             video_scaling,
             narrow,
             tcp,
-            decoder,
-            jitter,
+            decoders,
+            network,
             n_views,
             schedule,
-        }
+            stats_overlay,
+            stats_csv,
+            adaptive_bitrate,
+            recording,
+            hold_corrupt: !show_corrupt,
+            scaling_filter,
+            display,
+            measurements: measurements.map(PathBuf::from),
+            info_overlay,
+            pane_style,
+            present_mode,
+            text_style,
+        })
     }
 }
 
-fn start_sender<S>(sink: S, rcv: UnboundedReceiver<AppMessage>) -> JoinHandle<()>
+// Why forwarding messages to the server stopped.
+enum Forwarded {
+    // The App dropped its signal sender, we are done.
+    AppDropped,
+    // The App closed the session, reconnect to start a new one.
+    SessionClosed,
+}
+
+async fn forward_messages<S>(
+    sink: S,
+    rcv: &mut UnboundedReceiver<(u64, AppMessage)>,
+    first_session: u64,
+) -> Result<Forwarded>
 where
-    S: Sink<Message, Error = anyhow::Error> + Send + 'static,
+    S: Sink<Message, Error = anyhow::Error>,
 {
-    let handle = async_std::task::spawn(async move {
-        let _ = rcv.map(|m| m.try_into()).forward(sink).await;
-        log::info!("Exiting sender task");
-    });
-    handle
+    // Completes with Err if the server can't be reached.
+    futures::pin_mut!(sink);
+    while let Some((session, msg)) = rcv.next().await {
+        if session < first_session {
+            // Queued by the App of an earlier connection.
+            log::debug!("Discarding stale message {:?}", msg);
+            continue;
+        }
+        let close = matches!(msg, AppMessage::Close);
+        sink.send(msg.try_into()?).await?;
+        if close {
+            return Ok(Forwarded::SessionClosed);
+        }
+    }
+    Ok(Forwarded::AppDropped)
 }
 
-fn start_receiver<S>(stream: S, weak_app: Weak<AppInner>) -> JoinHandle<()>
+fn start_receiver<S>(stream: S, target: SignalTarget) -> JoinHandle<()>
 where
     S: Stream<Item = Result<Message>> + Send + 'static,
 {
@@ -126,7 +269,7 @@ where
                     Message::Close(_) => Err(anyhow::anyhow!("Got close frame from server")),
                     _ => {
                         if let Ok(msg) = msg.try_into() {
-                            if let Some(app) = weak_app.upgrade().map(App) {
+                            if let Some(app) = target.upgrade() {
                                 if let Err(e) = app.handle_app_message(msg) {
                                     log::error!("Failed to handle app message: {:?}", e);
                                 }
@@ -146,31 +289,96 @@ where
     handle
 }
 
+async fn wait_for_retry(delay: Duration, target: &SignalTarget) -> bool {
+    // Sleep in small steps so that we don't hold up shutdown while waiting.
+    let deadline = Instant::now() + delay;
+    loop {
+        if target.upgrade().is_none() {
+            return false;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+        async_std::task::sleep(RETRY_POLL_INTERVAL.min(deadline - now)).await;
+    }
+}
+
 fn run_signalling(
     url: String,
-    weak_app: Weak<AppInner>,
-    rcv: UnboundedReceiver<AppMessage>,
+    target: SignalTarget,
+    mut rcv: UnboundedReceiver<(u64, AppMessage)>,
 ) -> std::thread::JoinHandle<()> {
     // Start a new thread that runs the async tasks used for web socket communication.
 
-    std::thread::spawn(|| {
+    std::thread::spawn(move || {
         async_std::task::block_on(async move {
-            let (ws, response) = connect_async(url)
-                .await
-                .expect("Failed to connect to server");
+            let mut backoff = Backoff::new(RECONNECT_MIN_DELAY, RECONNECT_MAX_DELAY);
+            let mut reconnecting = false;
+            // Messages of earlier sessions are not sent on a new connection.
+            let mut first_session = 0;
+            loop {
+                let ws = match connect_async(url.as_str()).await {
+                    Ok((ws, response)) => {
+                        log::debug!("Got respose from websocker server: {:?}", response);
+                        ws
+                    }
+                    Err(e) => {
+                        let delay = backoff.next_delay();
+                        log::warn!(
+                            "Failed to connect to {}: {:?}, retrying in {:?}",
+                            &url,
+                            e,
+                            delay
+                        );
+                        if !wait_for_retry(delay, &target).await {
+                            break;
+                        }
+                        continue;
+                    }
+                };
+                backoff.reset();
 
-            log::debug!("Got respose from websocker server: {:?}", response);
-            let (outgoing, incomming) = ws.split();
+                if reconnecting {
+                    log::info!("Reconnected to {}, resuming session", &url);
+                    first_session = target.session() + 1;
+                    // The App rebuilds its pipeline and sends a new Connect.
+                    match target.upgrade() {
+                        Some(app) => app.on_reconnected(),
+                        None => break,
+                    }
+                }
 
-            let send_handle = start_sender(outgoing.sink_map_err(|e| e.into()), rcv);
-            let receive_handle = start_receiver(incomming.map_err(|e| e.into()), weak_app);
+                let (outgoing, incomming) = ws.split();
+                let receive_handle =
+                    start_receiver(incomming.map_err(|e| e.into()), target.clone());
+                let sender =
+                    forward_messages(outgoing.sink_map_err(|e| e.into()), &mut rcv, first_session);
+                futures::pin_mut!(sender);
 
-            // Let this task run until either the server closses the connection or the signal sender (snd) is dropped.
-            // The signal sender will be dropped when the App is dropped, which means that the sender task will complete.
-            let (_, _, to_cancel) = future::select_all(vec![send_handle, receive_handle]).await;
+                // Run until either the server closses the connection or the signal sender (snd) is dropped.
+                // The signal sender will be dropped when the App is dropped, in which case we are done.
+                match future::select(sender, receive_handle).await {
+                    Either::Left((result, receive_handle)) => {
+                        receive_handle.cancel().await;
+                        match result {
+                            Ok(Forwarded::AppDropped) => break,
+                            Ok(Forwarded::SessionClosed) => {
+                                log::info!("Session closed by the client, reconnecting")
+                            }
+                            Err(e) => log::warn!("Failed to send to server: {:?}", e),
+                        }
+                    }
+                    Either::Right(_) => {
+                        log::warn!("Connection to server was closed");
+                    }
+                }
 
-            // Make sure all remaining tasks are canceled
-            future::join_all(to_cancel.into_iter().map(|x| x.cancel())).await;
+                reconnecting = true;
+                if !wait_for_retry(backoff.next_delay(), &target).await {
+                    break;
+                }
+            }
 
             log::debug!("Main task is complete");
         });
@@ -186,12 +394,20 @@ pub fn run(config: AppConfig) -> Result<()> {
     let main_context = glutin::ContextBuilder::new()
         .with_gl(glutin::GlRequest::Specific(glutin::Api::OpenGl, (4, 5)))
         .with_gl_profile(glutin::GlProfile::Core)
-        .with_vsync(false)
+        .with_vsync(config.present_mode.vsync())
         .build_windowed(window_builder, &event_loop)
         .expect("Failed to build GL main context");
     let (main_context, window) = unsafe { main_context.split() };
 
-    let app_control = start_app(config, main_context);
+    let app_control = start_app(
+        config,
+        MainContext::Window(main_context),
+        native_display(&window),
+    );
+    let size = window.inner_size();
+    app_control.send(
+        WindowMessage::ScaleFactor(window.scale_factor() as f32, (size.width, size.height)).into(),
+    );
 
     event_loop.run(move |event, _target, flow| {
         if *flow == ControlFlow::Exit {
@@ -211,35 +427,130 @@ pub fn run(config: AppConfig) -> Result<()> {
                 WindowEvent::CloseRequested => {
                     *flow = ControlFlow::Exit;
                 }
+                WindowEvent::ScaleFactorChanged {
+                    scale_factor,
+                    new_inner_size,
+                } => {
+                    // Can't be converted with to_static(), forward what the App needs.
+                    let size = (new_inner_size.width, new_inner_size.height);
+                    app_control.send(WindowMessage::ScaleFactor(*scale_factor as f32, size).into());
+                }
                 _ => {}
             },
             _ => {}
         };
-        // Convert messages with static lifetimes, ignore the one (ScaleFactorChanged) that cant be converted.
+        // Convert messages with static lifetimes, ScaleFactorChanged is forwarded above.
         event.to_static().map(|event| app_control.send(event));
     });
 }
 
-pub fn start_app(config: AppConfig, main_context: RawContext<NotCurrent>) -> AppControl {
+#[cfg(target_os = "linux")]
+fn native_display(window: &Window) -> Option<usize> {
+    // GLX context sharing needs the X connection of the window (None on Wayland).
+    use glutin::platform::unix::WindowExtUnix;
+    window.xlib_display().map(|display| display as usize)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn native_display(_window: &Window) -> Option<usize> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn event_loop_display<T>(event_loop: &EventLoopWindowTarget<T>) -> Option<usize> {
+    use glutin::platform::unix::EventLoopWindowTargetExtUnix;
+    event_loop
+        .xlib_xconnection()
+        .map(|connection| connection.display as usize)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn event_loop_display<T>(_event_loop: &EventLoopWindowTarget<T>) -> Option<usize> {
+    None
+}
+
+/// Starts the App without a window. The views are rendered into an offscreen framebuffer
+/// of `config.viewport_size` and each rendered frame is sent on the returned receiver.
+/// The event loop is only used to create the GL context, it doesn't have to be run
+/// but it must outlive the returned AppControl.
+/// A display is still required: glutin creates the event loop and the context on the
+/// X11 or Wayland connection, so run under Xvfb on a machine without one.
+pub fn start_headless<T>(
+    config: AppConfig,
+    event_loop: &EventLoopWindowTarget<T>,
+) -> Result<(AppControl, Receiver<Frame>)> {
+    let size = PhysicalSize {
+        width: config.viewport_size.0,
+        height: config.viewport_size.1,
+    };
+    let context = glutin::ContextBuilder::new()
+        .with_gl(glutin::GlRequest::Specific(glutin::Api::OpenGl, (4, 5)))
+        .with_gl_profile(glutin::GlProfile::Core)
+        .build_headless(event_loop, size)?;
+
+    let (frame_snd, frame_rcv) = channel();
+    let app_control = start_app_with_frames(
+        config,
+        MainContext::Headless(context),
+        event_loop_display(event_loop),
+        Some(frame_snd),
+    );
+    Ok((app_control, frame_rcv))
+}
+
+/// Starts the App on the given (not current) GL context.
+/// `native_display` is the native display connection the context was created on,
+/// it is required when sharing a GLX context.
+pub fn start_app(
+    config: AppConfig,
+    main_context: MainContext<NotCurrent>,
+    native_display: Option<usize>,
+) -> AppControl {
+    start_app_with_frames(config, main_context, native_display, None)
+}
+
+fn start_app_with_frames(
+    config: AppConfig,
+    main_context: MainContext<NotCurrent>,
+    native_display: Option<usize>,
+    frame_sender: Option<Sender<Frame>>,
+) -> AppControl {
     // Init GStreamer
     gstreamer::init().expect("Failed to initialize GStreamer");
 
     // Create the views that we want connected.
-    let (snd, rcv) = unbounded::<AppMessage>();
-    let app = App::new(snd, config.tcp, config.decoder, config.jitter);
+    let (snd, rcv) = unbounded::<(u64, AppMessage)>();
+    let target = SignalTarget::default();
+    let app = App::new(
+        snd,
+        target.clone(),
+        config.tcp,
+        DecoderSelection::probe(config.decoders.clone()),
+        config.network.clone(),
+        config.recording.clone(),
+        config.hold_corrupt,
+    );
 
-    let signal_thread = run_signalling(config.ws_url.clone(), Arc::downgrade(&app.0), rcv);
+    let signal_thread = run_signalling(config.ws_url.clone(), target, rcv);
 
     // Build the window and gl-context
-    let (snd, message_thread) = app.start(config, main_context);
+    let stats = StatsHandle::default();
+    let (snd, message_thread) = app.start(
+        config,
+        main_context,
+        native_display,
+        frame_sender,
+        stats.clone(),
+    );
 
-    AppControl::new(signal_thread, message_thread, snd)
+    AppControl::new(signal_thread, message_thread, snd, stats)
 }
 
 pub struct AppControl {
     _signal_thread: Option<std::thread::JoinHandle<()>>,
     _message_thread: Option<std::thread::JoinHandle<()>>,
     message_sender: Option<Sender<Event<'static, WindowMessage>>>,
+    stats: StatsHandle,
 }
 
 impl AppControl {
@@ -247,14 +558,42 @@ impl AppControl {
         signal_thread: std::thread::JoinHandle<()>,
         message_thread: std::thread::JoinHandle<()>,
         message_sender: Sender<Event<'static, WindowMessage>>,
+        stats: StatsHandle,
     ) -> Self {
         Self {
             _signal_thread: Some(signal_thread),
             _message_thread: Some(message_thread),
             message_sender: Some(message_sender),
+            stats,
         }
     }
 
+    /// Changes the jitterbuffer latency (ms) of a stream, or of all streams if `stream` is None.
+    pub fn set_latency(&self, stream: Option<usize>, latency: u32) {
+        self.send(WindowMessage::SetLatency(stream, latency).into());
+    }
+
+    /// Starts or stops recording the received streams, one file per view.
+    pub fn set_recording(&self, enabled: bool) {
+        self.send(WindowMessage::SetRecording(enabled).into());
+    }
+
+    /// Changes how colours are displayed, e.g. to keep the colours of a fusion overlay.
+    pub fn set_display(&self, display: DisplaySettings) {
+        self.send(WindowMessage::SetDisplay(display).into());
+    }
+
+    /// Saves a lossless snapshot of a view as PNG, cropped to `pane` if given.
+    /// The file is written next to the recordings.
+    pub fn snapshot(&self, view: usize, pane: Option<usize>) {
+        self.send(WindowMessage::Snapshot(view, pane).into());
+    }
+
+    /// The statistics of all streams, collected once a second.
+    pub fn stats(&self) -> Option<SessionStats> {
+        self.stats.latest()
+    }
+
     pub fn send(&self, message: Event<'static, WindowMessage>) {
         let result = self
             .message_sender
@@ -270,6 +609,8 @@ impl AppControl {
 
 impl Drop for AppControl {
     fn drop(&mut self) {
+        // Stop the main loop, the timers keep their own senders so dropping ours is not enough.
+        self.send(Event::LoopDestroyed);
         self.message_sender.take().map(|s| drop(s));
         self._message_thread.take().map(|t| t.join());
         self._signal_thread.take().map(|t| t.join());
@@ -32,12 +32,72 @@ struct Opt {
     client_hw: bool,
     #[structopt(long)]
     fast_sw: bool,
+    /// Comma separated list of decoder elements to try in order, e.g. vah264dec,avdec_h264
+    #[structopt(long)]
+    decoders: Option<String>,
     #[structopt(long, default_value = "200")]
     jitter: u32,
     #[structopt(long, default_value = "1")]
     views: usize,
     #[structopt(long, default_value = "default")]
     rate_schedule: String,
+    /// Show the stream statistics overlay on start, toggled with H.
+    #[structopt(long)]
+    stats: bool,
+    /// Append stream statistics to this CSV file every second.
+    #[structopt(long)]
+    stats_csv: Option<String>,
+    /// Adapt the bitrate of each view to packet loss and jitter.
+    #[structopt(long)]
+    adaptive_bitrate: bool,
+    /// Lower bound of the adaptive bitrate, relative to the scheduled bitrate.
+    #[structopt(long, default_value = "0.25")]
+    abr_min: f32,
+    /// Upper bound of the adaptive bitrate, relative to the scheduled bitrate.
+    #[structopt(long, default_value = "1.0")]
+    abr_max: f32,
+    /// Jitterbuffer settings: default, loss-tolerant (udp), low-latency (lan) or a JSON file.
+    #[structopt(long, default_value = "default")]
+    network: String,
+    /// Directory that recordings (toggled with R) and snapshots (P) are written to.
+    #[structopt(long, default_value = ".")]
+    record_dir: String,
+    /// Container of the recordings, mkv or mp4.
+    #[structopt(long, default_value = "mkv")]
+    record_format: String,
+    /// Show frames decoded after unrecovered packet loss instead of holding the last good frame.
+    #[structopt(long)]
+    show_corrupt: bool,
+    /// Filter used when the video is scaled to the view: bilinear, bicubic, lanczos or sharp.
+    /// F cycles through them while running.
+    #[structopt(long, default_value = "lanczos")]
+    scaling_filter: String,
+    /// Display settings: "default", "fusion" (no grey clamping, for colour overlays), "grey"
+    /// (only the luma) or a JSON file with mode, grey_clamp, chroma_threshold, gamma and contrast.
+    #[structopt(long, default_value = "default")]
+    display: String,
+    /// JSON file the measurements (M) of each case are saved to and loaded from.
+    #[structopt(long)]
+    measurements: Option<String>,
+    /// Text in the corners of each pane: "default" (hidden until O), "shown", "minimal" or a JSON
+    /// file with visible and top_left, top_right, bottom_left and bottom_right templates.
+    /// O toggles it.
+    #[structopt(long, default_value = "default")]
+    info_overlay: String,
+    /// Pane separators, focus outline and sync/cine badges: "default", "none" or a JSON file
+    /// with separator_color, separator_width, focus_color, focus_width, sync_color, cine_color
+    /// and badge_size. Colours are RGBA in 0-1, sizes in pixels.
+    #[structopt(long, default_value = "default")]
+    pane_style: String,
+    /// When frames are presented: "immediate" (may tear), "vsync" (every frame waits for the
+    /// vertical blank) or "latest" (waits for the vertical blank and only shows the latest frame).
+    #[structopt(long, default_value = "immediate")]
+    present: String,
+    /// Font and look of the text: "default" (outlined), "plain", "shadow" or a JSON file with
+    /// font (a TTF/OTF path), size, color, effect ("none", "outline" or "shadow"), effect_color,
+    /// effect_width and per corner align ("left", "center" or "right").
+    #[structopt(long, default_value = "default")]
+    text_style: String,
 }
 
 fn main() -> Result<()> {
@@ -58,10 +118,29 @@ fn main() -> Result<()> {
         opt.tcp,
         opt.client_hw,
         opt.fast_sw,
+        opt.decoders,
         opt.jitter,
         opt.views,
         opt.rate_schedule,
-    );
+        opt.stats,
+        opt.stats_csv,
+        if opt.adaptive_bitrate {
+            Some((opt.abr_min, opt.abr_max))
+        } else {
+            None
+        },
+        opt.network,
+        opt.record_dir,
+        opt.record_format,
+        opt.show_corrupt,
+        opt.scaling_filter,
+        opt.display,
+        opt.measurements,
+        opt.info_overlay,
+        opt.pane_style,
+        opt.present,
+        opt.text_style,
+    )?;
     log::info!("Running with config: {:?}", &config);
     wsclient::run(config)
 }
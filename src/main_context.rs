@@ -0,0 +1,118 @@
+use std::ops::Deref;
+
+use glutin::{
+    dpi::PhysicalSize, Context, ContextCurrentState, ContextError, NotCurrent, PixelFormat,
+    PossiblyCurrent, RawContext, Rect,
+};
+
+use crate::message::LayoutRect;
+
+/// The GL context used by the renderer. It is either bound to a window, or
+/// a headless context where the views are rendered into an offscreen framebuffer.
+#[derive(Debug)]
+pub enum MainContext<T: ContextCurrentState> {
+    Window(RawContext<T>),
+    Headless(Context<T>),
+}
+
+impl<T: ContextCurrentState> MainContext<T> {
+    /// # Safety
+    /// Same requirements as `Context::make_current`.
+    pub unsafe fn make_current(self) -> Result<MainContext<PossiblyCurrent>, (Self, ContextError)> {
+        match self {
+            MainContext::Window(ctx) => ctx
+                .make_current()
+                .map(MainContext::Window)
+                .map_err(|(ctx, e)| (MainContext::Window(ctx), e)),
+            MainContext::Headless(ctx) => ctx
+                .make_current()
+                .map(MainContext::Headless)
+                .map_err(|(ctx, e)| (MainContext::Headless(ctx), e)),
+        }
+    }
+
+    /// # Safety
+    /// Same requirements as `Context::make_not_current`.
+    pub unsafe fn make_not_current(self) -> Result<MainContext<NotCurrent>, (Self, ContextError)> {
+        match self {
+            MainContext::Window(ctx) => ctx
+                .make_not_current()
+                .map(MainContext::Window)
+                .map_err(|(ctx, e)| (MainContext::Window(ctx), e)),
+            MainContext::Headless(ctx) => ctx
+                .make_not_current()
+                .map(MainContext::Headless)
+                .map_err(|(ctx, e)| (MainContext::Headless(ctx), e)),
+        }
+    }
+
+    pub fn is_headless(&self) -> bool {
+        matches!(self, MainContext::Headless(_))
+    }
+}
+
+impl MainContext<PossiblyCurrent> {
+    pub fn swap_buffers(&self) -> Result<(), ContextError> {
+        match self {
+            MainContext::Window(ctx) => ctx.swap_buffers(),
+            // Nothing to present, frames are read back from the offscreen framebuffer.
+            MainContext::Headless(_) => Ok(()),
+        }
+    }
+
+    /// Swaps and tells the compositor that only the rects changed, if the platform
+    /// supports buffer damage. The rects are in window pixels from the top left.
+    pub fn swap_buffers_with_damage(
+        &self,
+        rects: &[LayoutRect],
+        window_size: (u32, u32),
+    ) -> Result<(), ContextError> {
+        match self {
+            MainContext::Window(ctx) if ctx.swap_buffers_with_damage_supported() => {
+                // Clipped to the window, with the origin in the bottom left corner.
+                let rects: Vec<_> = rects
+                    .iter()
+                    .filter_map(|r| {
+                        let right = (r.x + r.width).min(window_size.0);
+                        let bottom = (r.y + r.height).min(window_size.1);
+                        if r.x >= right || r.y >= bottom {
+                            return None;
+                        }
+                        Some(Rect {
+                            x: r.x,
+                            y: window_size.1 - bottom,
+                            width: right - r.x,
+                            height: bottom - r.y,
+                        })
+                    })
+                    .collect();
+                ctx.swap_buffers_with_damage(&rects)
+            }
+            _ => self.swap_buffers(),
+        }
+    }
+
+    // Headless contexts don't expose their pixel format.
+    pub fn pixel_format(&self) -> Option<PixelFormat> {
+        match self {
+            MainContext::Window(ctx) => Some(ctx.get_pixel_format()),
+            MainContext::Headless(_) => None,
+        }
+    }
+
+    pub fn resize(&self, size: PhysicalSize<u32>) {
+        if let MainContext::Window(ctx) = self {
+            ctx.resize(size);
+        }
+    }
+}
+
+impl<T: ContextCurrentState> Deref for MainContext<T> {
+    type Target = Context<T>;
+    fn deref(&self) -> &Self::Target {
+        match self {
+            MainContext::Window(ctx) => ctx,
+            MainContext::Headless(ctx) => ctx,
+        }
+    }
+}
@@ -0,0 +1,456 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    renderer::Line,
+    vertex::{Quad, ViewTransform},
+    view_state::ViewState,
+};
+
+// A point in image pixels, or in pane pixels once placed.
+pub type Point = (f32, f32);
+
+const ELLIPSE_SEGMENTS: usize = 32;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Tool {
+    Ruler,
+    Angle,
+    Rect,
+    Ellipse,
+}
+
+impl Tool {
+    /// The next tool, None turns measuring off.
+    pub fn next(tool: Option<Tool>) -> Option<Tool> {
+        match tool {
+            None => Some(Tool::Ruler),
+            Some(Tool::Ruler) => Some(Tool::Angle),
+            Some(Tool::Angle) => Some(Tool::Rect),
+            Some(Tool::Rect) => Some(Tool::Ellipse),
+            Some(Tool::Ellipse) => None,
+        }
+    }
+
+    fn points(&self) -> usize {
+        match self {
+            Tool::Angle => 3,
+            _ => 2,
+        }
+    }
+}
+
+/// The outline of a measurement, anchored in image pixels.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Shape {
+    Ruler(Point, Point),
+    // The angle is at the second point.
+    Angle(Point, Point, Point),
+    // Opposite corners.
+    Rect(Point, Point),
+    // Opposite corners of the bounding box.
+    Ellipse(Point, Point),
+}
+
+impl Shape {
+    fn from_points(tool: Tool, points: &[Point]) -> Option<Self> {
+        match (tool, points) {
+            (Tool::Ruler, [a, b]) => Some(Shape::Ruler(*a, *b)),
+            (Tool::Angle, [a, b, c]) => Some(Shape::Angle(*a, *b, *c)),
+            (Tool::Rect, [a, b]) => Some(Shape::Rect(*a, *b)),
+            (Tool::Ellipse, [a, b]) => Some(Shape::Ellipse(*a, *b)),
+            _ => None,
+        }
+    }
+
+    /// The outline as line segments.
+    pub fn lines(&self) -> Vec<Line> {
+        match *self {
+            Shape::Ruler(a, b) => vec![[a, b]],
+            Shape::Angle(a, b, c) => vec![[a, b], [b, c]],
+            Shape::Rect(a, b) => {
+                let corners = [a, (b.0, a.1), b, (a.0, b.1)];
+                (0..4).map(|i| [corners[i], corners[(i + 1) % 4]]).collect()
+            }
+            Shape::Ellipse(a, b) => {
+                let center = ((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0);
+                let radius = ((b.0 - a.0) / 2.0, (b.1 - a.1) / 2.0);
+                let point = |i: usize| {
+                    let t = i as f32 / ELLIPSE_SEGMENTS as f32 * 2.0 * std::f32::consts::PI;
+                    (center.0 + radius.0 * t.cos(), center.1 + radius.1 * t.sin())
+                };
+                (0..ELLIPSE_SEGMENTS)
+                    .map(|i| [point(i), point(i + 1)])
+                    .collect()
+            }
+        }
+    }
+
+    /// Where the label is placed.
+    pub fn anchor(&self) -> Point {
+        match *self {
+            Shape::Ruler(_, b) => b,
+            Shape::Angle(_, b, _) => b,
+            Shape::Rect(a, b) | Shape::Ellipse(a, b) => (a.0.max(b.0), a.1.max(b.1)),
+        }
+    }
+
+    /// Length, angle or area. In millimetres if the pixel spacing (x, y) is known.
+    pub fn label(&self, spacing: Option<(f32, f32)>) -> String {
+        let (sx, sy) = spacing.unwrap_or((1.0, 1.0));
+        let unit = if spacing.is_some() { "mm" } else { "px" };
+        let scaled = |a: Point, b: Point| ((b.0 - a.0) * sx, (b.1 - a.1) * sy);
+        match *self {
+            Shape::Ruler(a, b) => {
+                let (dx, dy) = scaled(a, b);
+                format!("{:.1} {}", dx.hypot(dy), unit)
+            }
+            Shape::Angle(a, b, c) => {
+                let (ux, uy) = scaled(b, a);
+                let (vx, vy) = scaled(b, c);
+                let angle = (ux * vy - uy * vx).atan2(ux * vx + uy * vy).abs();
+                format!("{:.1}°", angle.to_degrees())
+            }
+            Shape::Rect(a, b) => {
+                let (dx, dy) = scaled(a, b);
+                format!("{:.1} {}²", (dx * dy).abs(), unit)
+            }
+            Shape::Ellipse(a, b) => {
+                let (dx, dy) = scaled(a, b);
+                let area = std::f32::consts::PI / 4.0 * dx * dy;
+                format!("{:.1} {}²", area.abs(), unit)
+            }
+        }
+    }
+}
+
+/// A shape on one image of a case.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Measurement {
+    pub image: u32,
+    pub shape: Shape,
+}
+
+/// An edit of the measurements of a case, applied to the shared list in the store.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Add(Measurement),
+    Remove(Measurement),
+}
+
+/// The measurements of the case in a pane and the one being placed with the active tool.
+#[derive(Debug, Default)]
+pub struct Measurements {
+    tool: Option<Tool>,
+    done: Vec<Measurement>,
+    // The image on screen, measurements are placed on and drawn over it.
+    image: u32,
+    points: Vec<Point>,
+    hover: Option<Point>,
+    // Set when the overlay has to be redrawn.
+    redraw: bool,
+    // Edits that have to be stored.
+    changes: Vec<Change>,
+}
+
+impl Measurements {
+    pub fn tool(&self) -> Option<Tool> {
+        self.tool
+    }
+
+    pub fn cycle_tool(&mut self) {
+        self.tool = Tool::next(self.tool);
+        self.points.clear();
+        self.redraw = true;
+    }
+
+    /// Moving to another image drops the measurement being placed.
+    pub fn set_image(&mut self, image: u32) {
+        if self.image != image {
+            self.image = image;
+            self.points.clear();
+            self.redraw = true;
+        }
+    }
+
+    pub fn add_point(&mut self, point: Point) {
+        let tool = match self.tool {
+            Some(tool) => tool,
+            None => return,
+        };
+        self.points.push(point);
+        if self.points.len() == tool.points() {
+            if let Some(shape) = Shape::from_points(tool, &self.points) {
+                let measurement = Measurement {
+                    image: self.image,
+                    shape,
+                };
+                self.done.push(measurement.clone());
+                self.changes.push(Change::Add(measurement));
+            }
+            self.points.clear();
+        }
+        self.redraw = true;
+    }
+
+    /// The pointer, previews the measurement being placed.
+    pub fn set_hover(&mut self, point: Option<Point>) {
+        if self.hover != point {
+            self.hover = point;
+            self.redraw = self.redraw || !self.points.is_empty();
+        }
+    }
+
+    /// Removes the measurement being placed, or the last one on the image.
+    pub fn undo(&mut self) {
+        if self.points.is_empty() {
+            let image = self.image;
+            if let Some(idx) = self.done.iter().rposition(|m| m.image == image) {
+                self.changes.push(Change::Remove(self.done.remove(idx)));
+            }
+        } else {
+            self.points.clear();
+        }
+        self.redraw = true;
+    }
+
+    /// Removes the measurements on the image.
+    pub fn clear(&mut self) {
+        self.points.clear();
+        let image = self.image;
+        let (removed, kept) = self.done.drain(..).partition(|m| m.image == image);
+        self.done = kept;
+        self.changes.extend(removed.into_iter().map(Change::Remove));
+        self.redraw = true;
+    }
+
+    /// The measurements on the image on screen.
+    pub fn on_image(&self) -> impl Iterator<Item = &Shape> {
+        let image = self.image;
+        self.done
+            .iter()
+            .filter(move |m| m.image == image)
+            .map(|m| &m.shape)
+    }
+
+    /// The measurements of the case as stored, keeps the one being placed.
+    pub fn set_done(&mut self, done: Vec<Measurement>) {
+        self.done = done;
+        self.redraw = true;
+    }
+
+    /// The measurement being placed, ending at the pointer.
+    pub fn preview(&self) -> Option<Shape> {
+        let tool = self.tool?;
+        if self.points.is_empty() {
+            return None;
+        }
+        let mut points = self.points.clone();
+        points.push(self.hover?);
+        match Shape::from_points(tool, &points) {
+            Some(shape) => Some(shape),
+            // The first leg of an angle.
+            None => Some(Shape::Ruler(points[0], points[1])),
+        }
+    }
+
+    /// Returns the redraw flag and the edits to store, and resets them.
+    pub fn take_changes(&mut self) -> (bool, Vec<Change>) {
+        let redraw = self.redraw;
+        self.redraw = false;
+        (redraw, std::mem::take(&mut self.changes))
+    }
+}
+
+/// Maps image pixels to pane pixels, as the server places the image for the state.
+pub fn image_to_pane(state: &ViewState, viewport: (f32, f32), image: (f32, f32)) -> ViewTransform {
+    let mut quad = Quad::new();
+    quad.set_viewport_size(viewport);
+    quad.map_texture_coords(image, image);
+    quad.image_to_viewport(state)
+}
+
+/// The measurements of each case, saved to a JSON file if a path is given.
+/// Panes showing the same case share its list.
+#[derive(Debug, Default)]
+pub struct MeasurementStore {
+    path: Option<PathBuf>,
+    cases: HashMap<String, Vec<Measurement>>,
+    // Bumped on every edit, panes reload their lists when it changes.
+    revision: u64,
+}
+
+impl MeasurementStore {
+    pub fn open(path: Option<PathBuf>) -> Self {
+        let cases = match path.as_deref().filter(|p| p.exists()).map(Self::load) {
+            Some(Ok(cases)) => cases,
+            Some(Err(e)) => {
+                log::error!("Failed to load measurements: {:?}", e);
+                HashMap::new()
+            }
+            None => HashMap::new(),
+        };
+        Self {
+            path,
+            cases,
+            revision: 0,
+        }
+    }
+
+    fn load(path: &Path) -> Result<HashMap<String, Vec<Measurement>>> {
+        let json = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    pub fn get(&self, case: &str) -> Vec<Measurement> {
+        self.cases.get(case).cloned().unwrap_or_default()
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Applies the edits of a pane to the list of the case, keeping those of other panes.
+    pub fn apply(&mut self, case: &str, changes: Vec<Change>) {
+        let measurements = self.cases.entry(case.to_owned()).or_default();
+        for change in changes {
+            match change {
+                Change::Add(measurement) => measurements.push(measurement),
+                Change::Remove(measurement) => {
+                    if let Some(idx) = measurements.iter().rposition(|m| *m == measurement) {
+                        measurements.remove(idx);
+                    }
+                }
+            }
+        }
+        self.revision += 1;
+        if let Err(e) = self.save() {
+            log::error!("Failed to save measurements: {:?}", e);
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        if let Some(path) = &self.path {
+            std::fs::write(path, serde_json::to_string_pretty(&self.cases)?)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::view_state::Zoom;
+
+    #[test]
+    fn test_labels() {
+        let ruler = Shape::Ruler((0.0, 0.0), (30.0, 40.0));
+        assert_eq!(ruler.label(None), "50.0 px");
+        assert_eq!(ruler.label(Some((0.5, 0.5))), "25.0 mm");
+        let angle = Shape::Angle((10.0, 0.0), (0.0, 0.0), (0.0, 10.0));
+        assert_eq!(angle.label(None), "90.0°");
+        // Anisotropic pixels change the angle.
+        let angle = Shape::Angle((10.0, 0.0), (0.0, 0.0), (10.0, 10.0));
+        assert_eq!(angle.label(Some((1.0, 2.0))), "63.4°");
+        let rect = Shape::Rect((10.0, 10.0), (0.0, 5.0));
+        assert_eq!(rect.label(Some((0.5, 1.0))), "25.0 mm²");
+        assert_eq!(Shape::Ellipse((0.0, 0.0), (2.0, 2.0)).lines().len(), 32);
+    }
+
+    #[test]
+    fn test_place_angle() {
+        let mut measurements = Measurements::default();
+        measurements.add_point((0.0, 0.0));
+        assert!(measurements.done.is_empty());
+        while measurements.tool() != Some(Tool::Angle) {
+            measurements.cycle_tool();
+        }
+        measurements.add_point((0.0, 0.0));
+        measurements.set_hover(Some((5.0, 0.0)));
+        assert_eq!(
+            measurements.preview(),
+            Some(Shape::Ruler((0.0, 0.0), (5.0, 0.0)))
+        );
+        measurements.add_point((5.0, 0.0));
+        measurements.add_point((5.0, 5.0));
+        let angle = Measurement {
+            image: 0,
+            shape: Shape::Angle((0.0, 0.0), (5.0, 0.0), (5.0, 5.0)),
+        };
+        assert_eq!(
+            measurements.take_changes(),
+            (true, vec![Change::Add(angle.clone())])
+        );
+        assert_eq!(measurements.preview(), None);
+        assert_eq!(measurements.done.len(), 1);
+        measurements.undo();
+        assert!(measurements.done.is_empty());
+        assert_eq!(
+            measurements.take_changes(),
+            (true, vec![Change::Remove(angle)])
+        );
+    }
+
+    #[test]
+    fn test_per_image() {
+        let mut measurements = Measurements::default();
+        measurements.cycle_tool();
+        measurements.add_point((0.0, 0.0));
+        measurements.add_point((1.0, 0.0));
+        // The point on the first image is dropped when scrolling.
+        measurements.add_point((2.0, 0.0));
+        measurements.set_image(3);
+        measurements.add_point((0.0, 1.0));
+        measurements.add_point((1.0, 1.0));
+        assert_eq!(measurements.done.len(), 2);
+        assert_eq!(
+            measurements.on_image().collect::<Vec<_>>(),
+            vec![&Shape::Ruler((0.0, 1.0), (1.0, 1.0))]
+        );
+        // Only the measurements on the image are removed.
+        measurements.clear();
+        assert_eq!(measurements.done[0].image, 0);
+        measurements.set_image(0);
+        assert_eq!(measurements.on_image().count(), 1);
+    }
+
+    #[test]
+    fn test_anchored_in_image() {
+        // A 100x50 image fit to a 200x200 pane is scaled by 2 and centered.
+        let mut state = ViewState::new();
+        let transform = image_to_pane(&state, (200.0, 200.0), (100.0, 50.0));
+        assert_eq!(transform.transform_vertex(&[0.0, 0.0]), [0.0, 50.0]);
+        // Zooming and panning moves the image and the point with it.
+        state.set_zoom_mode(Zoom::Fit(2.0));
+        state.set_position((10.0, 0.0));
+        let transform = image_to_pane(&state, (200.0, 200.0), (100.0, 50.0));
+        assert_eq!(transform.transform_vertex(&[50.0, 25.0]), [110.0, 100.0]);
+        let back = transform.invert().transform_vertex(&[110.0, 100.0]);
+        assert_eq!(back, [50.0, 25.0]);
+    }
+
+    #[test]
+    fn test_store() {
+        let path = std::env::temp_dir().join(format!("measurements-{}.json", std::process::id()));
+        let mut store = MeasurementStore::open(Some(path.clone()));
+        let ruler = |image| Measurement {
+            image,
+            shape: Shape::Ruler((1.0, 2.0), (3.0, 4.0)),
+        };
+        // Two panes on the case edit the same list.
+        store.apply("case0", vec![Change::Add(ruler(0))]);
+        store.apply("case0", vec![Change::Add(ruler(1))]);
+        store.apply("case0", vec![Change::Remove(ruler(0))]);
+        assert_eq!(store.revision(), 3);
+        let store = MeasurementStore::open(Some(path.clone()));
+        assert_eq!(store.get("case0"), vec![ruler(1)]);
+        assert!(store.get("case1").is_empty());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -23,6 +23,9 @@ pub struct ClientConfig {
     pub bitrate: f32,
     pub preset: String,
     pub fullrange: bool,
+    // RTP encoding-names the client can decode.
+    #[serde(default)]
+    pub codecs: Vec<String>,
 }
 impl Default for ClientConfig {
     fn default() -> Self {
@@ -38,6 +41,7 @@ impl Default for ClientConfig {
             bitrate: 4_f32,
             preset: "default".to_owned(),
             fullrange: true,
+            codecs: vec!["H264".to_owned()],
         }
     }
 }
@@ -69,6 +73,12 @@ pub struct CaseMeta {
     pub key: String,
     pub number_of_images: usize,
     pub number_of_variates: Option<usize>,
+    // Millimetres per pixel along x and y, for measurements.
+    #[serde(default)]
+    pub pixel_spacing: Option<(f32, f32)>,
+    // Columns and rows of the images, measurements are anchored in image pixels.
+    #[serde(default)]
+    pub image_size: Option<(u32, u32)>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -108,7 +118,7 @@ impl TryFrom<AppMessage> for Message {
     }
 }
 
-#[derive(Debug, Copy, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize, Default)]
 #[serde(rename_all = "lowercase")]
 pub struct LayoutRect {
     pub x: u32,
@@ -167,6 +177,9 @@ impl Default for RenderState {
 pub enum DataMessage {
     NewState(RenderState),
     Eof(u64),
+    // Sent by the server with the seq of a state and the RTP timestamp of the first
+    // frame rendered from it.
+    Rendered { seq: u64, rtp_time: u32 },
 }
 
 impl TryFrom<String> for DataMessage {
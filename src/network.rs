@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use gst::prelude::*;
+use gstreamer as gst;
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_LATENCY: u32 = 200;
+
+/// Jitterbuffer and retransmission settings for rtpbin.
+/// Settings left as None keep the GStreamer defaults.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkConfig {
+    // Jitterbuffer latency in ms.
+    pub latency: u32,
+    pub buffer_mode: String,
+    pub do_retransmission: Option<bool>,
+    pub drop_on_latency: Option<bool>,
+    pub do_lost: Option<bool>,
+    // Overrides for single streams, keyed on the mline index.
+    pub streams: HashMap<usize, StreamNetworkConfig>,
+}
+
+/// Settings of a single stream, applied to its jitterbuffer.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StreamNetworkConfig {
+    pub latency: Option<u32>,
+    pub do_retransmission: Option<bool>,
+    pub drop_on_latency: Option<bool>,
+    pub do_lost: Option<bool>,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            latency: DEFAULT_LATENCY,
+            buffer_mode: "synced".to_owned(),
+            do_retransmission: None,
+            drop_on_latency: None,
+            do_lost: None,
+            streams: HashMap::new(),
+        }
+    }
+}
+
+impl NetworkConfig {
+    /// A large buffer that waits for retransmissions, for lossy UDP links (e.g. Wi-Fi).
+    pub fn loss_tolerant() -> Self {
+        Self {
+            latency: 500,
+            do_retransmission: Some(true),
+            drop_on_latency: Some(false),
+            do_lost: Some(true),
+            ..Self::default()
+        }
+    }
+
+    /// A small buffer that drops what is late, for a wired LAN.
+    pub fn low_latency() -> Self {
+        Self {
+            latency: 50,
+            do_retransmission: Some(false),
+            drop_on_latency: Some(true),
+            do_lost: Some(true),
+            ..Self::default()
+        }
+    }
+
+    /// Makes the jitterbuffers report lost packets, also for streams that turn it off.
+    /// Without do-lost they send no GstRTPPacketLost events.
+    pub fn report_loss(&mut self) {
+        if self.do_lost == Some(false) {
+            log::warn!("Overriding do_lost, loss is needed to hold back corrupt frames");
+        }
+        self.do_lost = Some(true);
+        for stream in self.streams.values_mut() {
+            stream.do_lost = stream.do_lost.map(|_| true);
+        }
+    }
+
+    /// Parses a preset name or a path to a JSON file. `jitter` is the latency of the default preset.
+    pub fn from_arg(arg: &str, jitter: u32) -> Result<Self> {
+        match arg {
+            "default" => Ok(Self {
+                latency: jitter,
+                ..Self::default()
+            }),
+            "loss-tolerant" | "udp" => Ok(Self::loss_tolerant()),
+            "low-latency" | "lan" => Ok(Self::low_latency()),
+            path => {
+                let json = std::fs::read_to_string(path)?;
+                Ok(serde_json::from_str(&json)?)
+            }
+        }
+    }
+
+    pub fn apply(&self, rtpbin: &gst::Element) {
+        // rtpbin hands these on to the jitterbuffers it creates.
+        log::debug!("Setting '{}' rtpjitterbuffer mode", self.buffer_mode);
+        rtpbin.set_property_from_str("buffer-mode", &self.buffer_mode);
+        log::debug!("Setting jitter buffer latency to {}", self.latency);
+        rtpbin
+            .set_property("latency", &self.latency)
+            .expect("Failed to set jitter buffer latency");
+        set_flags(
+            rtpbin,
+            self.do_retransmission,
+            self.drop_on_latency,
+            self.do_lost,
+        );
+    }
+
+    /// The settings for a stream, with its overrides.
+    pub fn stream(&self, idx: usize) -> StreamNetworkConfig {
+        let stream = self.streams.get(&idx).cloned().unwrap_or_default();
+        StreamNetworkConfig {
+            latency: stream.latency.or(Some(self.latency)),
+            do_retransmission: stream.do_retransmission.or(self.do_retransmission),
+            drop_on_latency: stream.drop_on_latency.or(self.drop_on_latency),
+            do_lost: stream.do_lost.or(self.do_lost),
+        }
+    }
+}
+
+impl StreamNetworkConfig {
+    pub fn apply(&self, jitter_buffer: &gst::Element) {
+        if let Some(latency) = self.latency {
+            jitter_buffer
+                .set_property("latency", &latency)
+                .expect("Failed to set jitter buffer latency");
+        }
+        set_flags(
+            jitter_buffer,
+            self.do_retransmission,
+            self.drop_on_latency,
+            self.do_lost,
+        );
+    }
+}
+
+fn set_flags(
+    element: &gst::Element,
+    do_retransmission: Option<bool>,
+    drop_on_latency: Option<bool>,
+    do_lost: Option<bool>,
+) {
+    let flags = [
+        ("do-retransmission", do_retransmission),
+        ("drop-on-latency", drop_on_latency),
+        ("do-lost", do_lost),
+    ];
+    for (name, value) in flags.iter() {
+        if let Some(value) = value {
+            let name: &str = name;
+            log::debug!("Setting {} to {} on {}", name, value, element.get_name());
+            element
+                .set_property(name, value)
+                .unwrap_or_else(|_| panic!("Failed to set {}", name));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_overrides() {
+        let json = r#"{"latency": 300, "do_lost": true, "streams": {"1": {"latency": 80, "do_lost": false}}}"#;
+        let config: NetworkConfig = serde_json::from_str(json).unwrap();
+        assert_eq!(config.buffer_mode, "synced");
+
+        let stream = config.stream(0);
+        assert_eq!(stream.latency, Some(300));
+        assert_eq!(stream.do_lost, Some(true));
+        let stream = config.stream(1);
+        assert_eq!(stream.latency, Some(80));
+        assert_eq!(stream.do_lost, Some(false));
+        assert_eq!(stream.do_retransmission, None);
+    }
+
+    #[test]
+    fn test_report_loss() {
+        // The default leaves do-lost to GStreamer, which doesn't report loss.
+        let mut config = NetworkConfig::from_arg("default", 200).unwrap();
+        config.streams.entry(1).or_default().do_lost = Some(false);
+        assert_eq!(config.stream(0).do_lost, None);
+        config.report_loss();
+        assert_eq!(config.stream(0).do_lost, Some(true));
+        assert_eq!(config.stream(1).do_lost, Some(true));
+    }
+
+    #[test]
+    fn test_presets() {
+        assert_eq!(
+            NetworkConfig::from_arg("default", 120).unwrap().latency,
+            120
+        );
+        assert_eq!(NetworkConfig::from_arg("udp", 120).unwrap().latency, 500);
+        assert_eq!(NetworkConfig::from_arg("lan", 120).unwrap().latency, 50);
+        assert!(NetworkConfig::from_arg("no-such-file.json", 120).is_err());
+    }
+}
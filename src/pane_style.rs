@@ -0,0 +1,211 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{message::LayoutRect, renderer::Fill};
+
+/// Colours (RGBA) and sizes in logical pixels of the pane separators, the outline of the
+/// focused pane and the sync and cine badges. A size of 0 leaves the part out.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PaneStyle {
+    pub separator_color: [f32; 4],
+    pub separator_width: u32,
+    pub focus_color: [f32; 4],
+    pub focus_width: u32,
+    pub sync_color: [f32; 4],
+    pub cine_color: [f32; 4],
+    pub badge_size: u32,
+}
+
+impl Default for PaneStyle {
+    fn default() -> Self {
+        Self {
+            separator_color: [0.3, 0.3, 0.3, 1.0],
+            separator_width: 2,
+            focus_color: [1.0, 0.6, 0.0, 1.0],
+            focus_width: 2,
+            sync_color: [0.0, 0.8, 1.0, 1.0],
+            cine_color: [0.2, 1.0, 0.2, 1.0],
+            badge_size: 10,
+        }
+    }
+}
+
+/// What the badges of a pane show.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct PaneBadges {
+    pub synchronized: bool,
+    pub cine: bool,
+}
+
+impl PaneStyle {
+    pub fn none() -> Self {
+        Self {
+            separator_width: 0,
+            focus_width: 0,
+            badge_size: 0,
+            ..Self::default()
+        }
+    }
+
+    /// The style with its sizes, given in logical pixels, in physical pixels.
+    pub fn scaled(&self, scale_factor: f32) -> Self {
+        let scale = |size: u32| (size as f32 * scale_factor).round() as u32;
+        Self {
+            separator_width: scale(self.separator_width),
+            focus_width: scale(self.focus_width),
+            badge_size: scale(self.badge_size),
+            ..*self
+        }
+    }
+
+    /// Parses a preset name or a path to a JSON file.
+    pub fn from_arg(arg: &str) -> Result<Self> {
+        match arg {
+            "default" => Ok(Self::default()),
+            "none" => Ok(Self::none()),
+            path => {
+                let json = std::fs::read_to_string(path)?;
+                Ok(serde_json::from_str(&json)?)
+            }
+        }
+    }
+
+    /// The rectangles drawn over the panes of a view, placed in the view.
+    pub fn fills(&self, panes: &[(LayoutRect, PaneBadges)], focus: Option<usize>) -> Vec<Fill> {
+        let mut fills = Vec::new();
+        // Separators on the edges shared with the panes to the left and above.
+        let half = self.separator_width / 2;
+        for (pane, _) in panes.iter().filter(|_| self.separator_width > 0) {
+            if pane.x > 0 {
+                fills.push(Fill {
+                    rect: rect(
+                        pane.x.saturating_sub(half),
+                        pane.y,
+                        self.separator_width,
+                        pane.height,
+                    ),
+                    color: self.separator_color,
+                });
+            }
+            if pane.y > 0 {
+                fills.push(Fill {
+                    rect: rect(
+                        pane.x,
+                        pane.y.saturating_sub(half),
+                        pane.width,
+                        self.separator_width,
+                    ),
+                    color: self.separator_color,
+                });
+            }
+        }
+        if let Some((pane, _)) = focus.and_then(|idx| panes.get(idx)) {
+            let width = self.focus_width.min(pane.width / 2).min(pane.height / 2);
+            if width > 0 {
+                let (x, y, w, h) = (pane.x, pane.y, pane.width, pane.height);
+                for edge in [
+                    rect(x, y, w, width),
+                    rect(x, y + h - width, w, width),
+                    rect(x, y, width, h),
+                    rect(x + w - width, y, width, h),
+                ]
+                .iter()
+                {
+                    fills.push(Fill {
+                        rect: *edge,
+                        color: self.focus_color,
+                    });
+                }
+            }
+        }
+        // Badges in a row at the top center, half a badge apart.
+        let size = self.badge_size;
+        for (pane, badges) in panes.iter().filter(|_| size > 0) {
+            let colors: Vec<_> = [
+                (badges.synchronized, self.sync_color),
+                (badges.cine, self.cine_color),
+            ]
+            .iter()
+            .filter(|(on, _)| *on)
+            .map(|(_, color)| *color)
+            .collect();
+            let row = colors.len() as u32 * size + colors.len().saturating_sub(1) as u32 * size / 2;
+            if colors.is_empty() || row > pane.width || 2 * size > pane.height {
+                continue;
+            }
+            let left = pane.x + (pane.width - row) / 2;
+            for (i, color) in colors.into_iter().enumerate() {
+                fills.push(Fill {
+                    rect: rect(
+                        left + i as u32 * (size + size / 2),
+                        pane.y + size / 2,
+                        size,
+                        size,
+                    ),
+                    color,
+                });
+            }
+        }
+        fills
+    }
+}
+
+fn rect(x: u32, y: u32, width: u32, height: u32) -> LayoutRect {
+    LayoutRect {
+        x,
+        y,
+        width,
+        height,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_separators_and_focus() {
+        let style = PaneStyle::default();
+        let panes = [
+            (rect(0, 0, 50, 40), PaneBadges::default()),
+            (rect(50, 0, 50, 40), PaneBadges::default()),
+        ];
+        let fills = style.fills(&panes, None);
+        assert_eq!(fills.len(), 1);
+        assert_eq!(fills[0].rect, rect(49, 0, 2, 40));
+
+        let fills = style.fills(&panes, Some(1));
+        let outline: Vec<_> = fills.iter().skip(1).map(|f| f.rect).collect();
+        assert_eq!(
+            outline,
+            vec![
+                rect(50, 0, 50, 2),
+                rect(50, 38, 50, 2),
+                rect(50, 0, 2, 40),
+                rect(98, 0, 2, 40)
+            ]
+        );
+        assert!(PaneStyle::none().fills(&panes, Some(1)).is_empty());
+    }
+
+    #[test]
+    fn test_badges() {
+        let style = PaneStyle::default();
+        let both = PaneBadges {
+            synchronized: true,
+            cine: true,
+        };
+        let fills = style.fills(&[(rect(0, 100, 100, 80), both)], None);
+        // 10 px badges 5 px apart, centered.
+        let badges: Vec<_> = fills.iter().map(|f| (f.rect, f.color)).collect();
+        assert_eq!(
+            badges,
+            vec![
+                (rect(0, 99, 100, 2), style.separator_color),
+                (rect(37, 105, 10, 10), style.sync_color),
+                (rect(52, 105, 10, 10), style.cine_color),
+            ]
+        );
+    }
+}
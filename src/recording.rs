@@ -0,0 +1,258 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use gst::prelude::*;
+use gstreamer as gst;
+use gstreamer_video as gst_video;
+use serde::Serialize;
+
+use crate::{codec::Codec, message::RenderState};
+
+// How long the worker waits for the muxer to write out the file.
+const FINALIZE_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Container {
+    Matroska,
+    Mp4,
+}
+
+impl Container {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "mkv" | "matroska" => Some(Container::Matroska),
+            "mp4" => Some(Container::Mp4),
+            _ => None,
+        }
+    }
+
+    fn muxer(&self) -> &'static str {
+        match self {
+            Container::Matroska => "matroskamux",
+            Container::Mp4 => "mp4mux",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Container::Matroska => "mkv",
+            Container::Mp4 => "mp4",
+        }
+    }
+}
+
+/// Where and how the received streams are recorded.
+#[derive(Debug, Clone)]
+pub struct RecordingConfig {
+    pub dir: PathBuf,
+    pub container: Container,
+}
+
+impl RecordingConfig {
+    /// A new file name for a recording of the stream, without extension.
+    fn base_path(&self, idx: usize) -> PathBuf {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis());
+        self.dir.join(format!("view{}-{}", idx, started))
+    }
+}
+
+/// A branch off the tee of a decode bin that muxes the parsed stream into a file,
+/// the video is written as received without re-encoding.
+#[derive(Debug)]
+pub struct Recording {
+    path: PathBuf,
+    tee_pad: gst::Pad,
+    branch: gst::Bin,
+    eos: Receiver<()>,
+}
+
+impl Recording {
+    pub fn start(
+        decodebin: &gst::Bin,
+        idx: usize,
+        codec: Codec,
+        config: &RecordingConfig,
+    ) -> Result<Self> {
+        let path = config
+            .base_path(idx)
+            .with_extension(config.container.extension());
+        let branch = gst::parse_bin_from_description(
+            &codec.record_template(config.container.muxer()),
+            true,
+        )?;
+        let filesink = branch
+            .get_by_name("recordsink")
+            .expect("Failed to get recording sink");
+        filesink.set_property("location", &path.to_string_lossy().to_string())?;
+
+        // Tell when the muxer has finished the file.
+        let (eos_snd, eos) = channel();
+        filesink
+            .get_static_pad("sink")
+            .expect("Failed to get recording sink pad")
+            .add_probe(gst::PadProbeType::EVENT_DOWNSTREAM, move |_pad, info| {
+                if let Some(gst::PadProbeData::Event(ref event)) = info.data {
+                    if event.get_type() == gst::EventType::Eos {
+                        let _ = eos_snd.send(());
+                    }
+                }
+                gst::PadProbeReturn::Ok
+            });
+
+        decodebin.add(&branch)?;
+        branch.sync_state_with_parent()?;
+        let tee = decodebin
+            .get_by_name(&format!("tee{}", idx))
+            .ok_or_else(|| anyhow::anyhow!("No tee in decode bin {}", idx))?;
+        let tee_pad = tee
+            .get_request_pad("src_%u")
+            .ok_or_else(|| anyhow::anyhow!("Failed to request tee pad"))?;
+        let sinkpad = branch
+            .get_static_pad("sink")
+            .expect("Failed to get recording bin sink pad");
+        tee_pad.link(&sinkpad)?;
+
+        // Ask the server for a key frame so that the file can be played from the start.
+        let event = gst_video::UpstreamForceKeyUnitEvent::builder()
+            .all_headers(true)
+            .build();
+        tee_pad.send_event(event);
+
+        log::info!("Recording stream {} to {}", idx, path.display());
+        Ok(Self {
+            path,
+            tee_pad,
+            branch,
+            eos,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Detaches the branch, the muxer finalizes the file on a worker thread. The handle
+    /// finishes once the file is written, the decode bin can be stopped meanwhile.
+    pub fn stop(self, decodebin: &gst::Bin) -> JoinHandle<()> {
+        let sinkpad = self
+            .branch
+            .get_static_pad("sink")
+            .expect("Failed to get recording bin sink pad");
+        // Unlink once no buffer is on its way through the tee pad, then end the file.
+        // The branch leaves the decode bin, so stopping that doesn't cut the file short.
+        let branch = self.branch.clone();
+        let parent = decodebin.clone();
+        self.tee_pad
+            .add_probe(gst::PadProbeType::IDLE, move |tee_pad, _info| {
+                if tee_pad.unlink(&sinkpad).is_ok() {
+                    let _ = parent.remove(&branch);
+                    sinkpad.send_event(gst::event::Eos::new());
+                }
+                gst::PadProbeReturn::Remove
+            });
+
+        let decodebin = decodebin.clone();
+        thread::spawn(move || {
+            if self.eos.recv_timeout(FINALIZE_TIMEOUT).is_err() {
+                log::warn!("Recording {} was not finalized", self.path.display());
+            }
+            if let Some(tee) = self.tee_pad.get_parent_element() {
+                tee.release_request_pad(&self.tee_pad);
+            }
+            let _ = self.branch.set_state(gst::State::Null);
+            if self.branch.get_parent().is_some() {
+                let _ = decodebin.remove(&self.branch);
+            }
+            log::info!("Stopped recording {}", self.path.display());
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct LoggedState<'a> {
+    // Seconds since the log was started.
+    time: f64,
+    state: &'a RenderState,
+}
+
+/// Logs the RenderStates sent for a view as a JSON array, next to its recording.
+#[derive(Debug)]
+pub struct StateLog {
+    file: BufWriter<File>,
+    started: Instant,
+    empty: bool,
+}
+
+impl StateLog {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        write!(file, "[")?;
+        Ok(Self {
+            file,
+            started: Instant::now(),
+            empty: true,
+        })
+    }
+
+    pub fn append(&mut self, state: &RenderState) -> io::Result<()> {
+        let entry = LoggedState {
+            time: self.started.elapsed().as_secs_f64(),
+            state,
+        };
+        let separator = if self.empty { "" } else { "," };
+        writeln!(self.file, "{}", separator)?;
+        serde_json::to_writer(&mut self.file, &entry)?;
+        self.empty = false;
+        Ok(())
+    }
+}
+
+impl Drop for StateLog {
+    fn drop(&mut self) {
+        // Close the array so the file is valid JSON.
+        if writeln!(self.file, "\n]")
+            .and_then(|_| self.file.flush())
+            .is_err()
+        {
+            log::error!("Failed to finish the state log");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_log() {
+        let path =
+            std::env::temp_dir().join(format!("wsclient-states-{}.json", std::process::id()));
+        {
+            let mut log = StateLog::create(&path).unwrap();
+            log.append(&RenderState::with_seq(1)).unwrap();
+            log.append(&RenderState::with_seq(2)).unwrap();
+        }
+        let json = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let entries: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let entries = entries.as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1]["state"]["seq"], 2);
+    }
+
+    #[test]
+    fn test_container_from_name() {
+        assert_eq!(Container::from_name("mkv"), Some(Container::Matroska));
+        assert_eq!(Container::from_name("mp4"), Some(Container::Mp4));
+        assert_eq!(Container::from_name("avi"), None);
+    }
+}
@@ -0,0 +1,443 @@
+use std::time::Instant;
+
+use crate::{
+    display::DisplaySettings,
+    glvideo::Frame,
+    info_overlay::Corners,
+    message::LayoutRect,
+    text_renderer::{Partition, TextPartition, TextStyle},
+    view::ViewControl,
+    view_state::Prediction,
+    window_message::ViewSample,
+};
+
+/// What is drawn for a view, placed in the window.
+#[derive(Debug, Clone)]
+pub struct ViewFrame {
+    pub sample: Option<ViewSample>,
+    // Position and size of the view in the window.
+    pub rect: LayoutRect,
+    pub text: Option<Vec<TextPartition>>,
+    // The debug timestamp changes every frame, it is drawn with the text of views
+    // that are redrawn anyway and doesn't damage the view by itself.
+    pub debug_text: Option<TextPartition>,
+    // The panes are drawn one by one while the frame lags behind the sent state,
+    // empty if the view is drawn as a whole.
+    pub panes: Vec<PaneFrame>,
+    pub overlays: Vec<PaneOverlay>,
+    // Pane separators, focus outline and badges, drawn over the video.
+    pub fills: Vec<Fill>,
+}
+
+/// A filled rectangle, placed in the view.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fill {
+    pub rect: LayoutRect,
+    pub color: [f32; 4],
+}
+
+// Width of the measurement lines in logical pixels.
+const LINE_WIDTH: f32 = 1.0;
+
+// A line segment, from one point to another.
+pub type Line = [(f32, f32); 2];
+
+/// Lines drawn over a pane, e.g. measurements.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaneOverlay {
+    // Position and size of the pane in the view.
+    pub rect: LayoutRect,
+    // In pane pixels, clipped to the pane.
+    pub lines: Vec<Line>,
+    // Width of the lines in window pixels.
+    pub width: f32,
+}
+
+/// A pane of a view, moved to where it is predicted to be.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PaneFrame {
+    // Position and size of the pane in the view.
+    pub rect: LayoutRect,
+    pub prediction: Option<Prediction>,
+}
+
+/// What changed in the window since the last render.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Damage {
+    // Nothing had to be redrawn.
+    None,
+    // Only the views in these rects were redrawn, in window pixels.
+    Views(Vec<LayoutRect>),
+    // The whole window was cleared and redrawn.
+    Window,
+}
+
+// What a view was last drawn with, the sample by its stream and arrival.
+#[derive(Debug, Clone, PartialEq)]
+struct DrawnView {
+    sample: Option<(usize, Instant)>,
+    rect: LayoutRect,
+    text: Option<Vec<TextPartition>>,
+    panes: Vec<PaneFrame>,
+    overlays: Vec<PaneOverlay>,
+    fills: Vec<Fill>,
+}
+
+impl From<&ViewFrame> for DrawnView {
+    fn from(frame: &ViewFrame) -> Self {
+        Self {
+            sample: frame.sample.as_ref().map(|s| (s.id, s.timer)),
+            rect: frame.rect,
+            text: frame.text.clone(),
+            panes: frame.panes.clone(),
+            overlays: frame.overlays.clone(),
+            fills: frame.fills.clone(),
+        }
+    }
+}
+
+impl ViewFrame {
+    fn with_debug_text(mut self) -> Self {
+        if let Some(partition) = self.debug_text.take() {
+            self.text.get_or_insert_with(Vec::new).push(partition);
+        }
+        self
+    }
+}
+
+/// Remembers what the views were drawn with, so a render only redraws the views that changed.
+/// Renderers invalidate it when a setting changes how everything is drawn.
+#[derive(Debug, Default)]
+pub struct DamageTracker {
+    // None until the first render and after an invalidate.
+    drawn: Option<Vec<DrawnView>>,
+}
+
+impl DamageTracker {
+    pub fn invalidate(&mut self) {
+        self.drawn = None;
+    }
+
+    /// The frames of the views to redraw. If views were added, removed or moved,
+    /// all of them are redrawn over a cleared window.
+    pub fn update(&mut self, frames: Vec<ViewFrame>) -> (Damage, Vec<ViewFrame>) {
+        let drawn: Vec<_> = frames.iter().map(DrawnView::from).collect();
+        let previous = self.drawn.replace(drawn.clone());
+        match previous {
+            Some(previous)
+                if previous.len() == drawn.len()
+                    && previous.iter().zip(&drawn).all(|(p, d)| p.rect == d.rect) =>
+            {
+                let changed: Vec<_> = frames
+                    .into_iter()
+                    .zip(previous.iter().zip(&drawn))
+                    .filter(|(_, (p, d))| p != d)
+                    .map(|(frame, _)| frame.with_debug_text())
+                    .collect();
+                if changed.is_empty() {
+                    (Damage::None, changed)
+                } else {
+                    let rects = changed.iter().map(|frame| frame.rect).collect();
+                    (Damage::Views(rects), changed)
+                }
+            }
+            _ => (
+                Damage::Window,
+                frames.into_iter().map(ViewFrame::with_debug_text).collect(),
+            ),
+        }
+    }
+}
+
+/// How the video is interpolated when it is scaled to its view,
+/// the value selects the filter in the scaling shader.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ScalingFilter {
+    Bilinear = 0,
+    Bicubic = 1,
+    Lanczos = 2,
+    Sharp = 3,
+}
+
+impl ScalingFilter {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "bilinear" | "linear" => Some(ScalingFilter::Bilinear),
+            "bicubic" | "cubic" => Some(ScalingFilter::Bicubic),
+            "lanczos" => Some(ScalingFilter::Lanczos),
+            "sharp" | "bisharp" => Some(ScalingFilter::Sharp),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScalingFilter::Bilinear => "bilinear",
+            ScalingFilter::Bicubic => "bicubic",
+            ScalingFilter::Lanczos => "lanczos",
+            ScalingFilter::Sharp => "sharp",
+        }
+    }
+
+    /// The next filter, from smooth to sharp.
+    pub fn next(self) -> Self {
+        match self {
+            ScalingFilter::Bilinear => ScalingFilter::Bicubic,
+            ScalingFilter::Bicubic => ScalingFilter::Lanczos,
+            ScalingFilter::Lanczos => ScalingFilter::Sharp,
+            ScalingFilter::Sharp => ScalingFilter::Bilinear,
+        }
+    }
+}
+
+/// When rendered frames are presented in the window.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PresentMode {
+    // Swap as soon as a frame is rendered, may tear.
+    Immediate,
+    // Every rendered frame waits for the vertical blank.
+    Vsync,
+    // Waits for the vertical blank, frames that arrive meanwhile are skipped
+    // so only the latest one is rendered.
+    LatestAtVsync,
+}
+
+impl PresentMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "immediate" => Some(PresentMode::Immediate),
+            "vsync" => Some(PresentMode::Vsync),
+            "latest" | "latest-at-vsync" => Some(PresentMode::LatestAtVsync),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PresentMode::Immediate => "immediate",
+            PresentMode::Vsync => "vsync",
+            PresentMode::LatestAtVsync => "latest-at-vsync",
+        }
+    }
+
+    pub fn vsync(&self) -> bool {
+        !matches!(self, PresentMode::Immediate)
+    }
+}
+
+/// Draws the active views of a ViewControl into the window, or an offscreen target.
+pub trait Renderer {
+    fn set_window_size(&mut self, size: (u32, u32));
+
+    fn set_scaling_filter(&mut self, filter: ScalingFilter);
+
+    fn scaling_filter(&self) -> ScalingFilter;
+
+    fn set_display(&mut self, display: DisplaySettings);
+
+    fn display(&self) -> DisplaySettings;
+
+    /// Physical pixels per logical pixel, text is sized in logical pixels.
+    fn set_scale_factor(&mut self, scale_factor: f32);
+
+    fn set_text_style(&mut self, style: TextStyle);
+
+    /// Draws the views that changed since the last render with their text overlays,
+    /// clearing the window first if the layout changed.
+    fn render_frames(&mut self, frames: Vec<ViewFrame>) -> Damage;
+
+    /// The last rendered window, if the renderer can read it back.
+    fn read_frame(&self) -> Option<Frame>;
+
+    fn render_views(&mut self, control: &ViewControl) -> Damage {
+        let filter = self.scaling_filter();
+        self.render_frames(view_frames(control, filter))
+    }
+}
+
+/// Collects the samples and text overlays of the active views.
+pub fn view_frames(control: &ViewControl, filter: ScalingFilter) -> Vec<ViewFrame> {
+    // Get the position of the ViewControl
+    let control_layout = control.get_layout();
+    control.active_map(|view| {
+        let layout = view.get_layout();
+        let view_size = (layout.width as f32, layout.height as f32);
+        let stats = view.get_stats_overlay().map(|mut lines| {
+            lines.push(format!("Filter: {}", filter.name()));
+            lines
+        });
+        let line_width = (LINE_WIDTH * control.scale_factor()).round().max(1.0);
+        let (overlays, labels) = view.get_measurement_overlays(line_width);
+        let info = control
+            .get_info_overlay()
+            .map(|overlay| view.get_pane_info(overlay))
+            .unwrap_or_default();
+        let focus = control
+            .focused_pane()
+            .filter(|(video_id, _)| *video_id == view.video_id())
+            .map(|(_, pane)| pane);
+        let fills = control
+            .get_pane_style()
+            .scaled(control.scale_factor())
+            .fills(&view.get_pane_badges(), focus);
+        let text = overlay_text(
+            view_size,
+            view.is_recovering(),
+            stats,
+            labels,
+            info,
+            control.get_notice(),
+        );
+        ViewFrame {
+            sample: view.get_current_sample(),
+            rect: LayoutRect {
+                x: control_layout.x + layout.x,
+                y: control_layout.y + layout.y,
+                width: layout.width,
+                height: layout.height,
+            },
+            text,
+            debug_text: debug_text(view_size, view.get_timestamp()),
+            panes: view.get_predictions(),
+            overlays,
+            fills,
+        }
+    })
+}
+
+fn debug_text(view_size: (f32, f32), timestamp: f32) -> Option<TextPartition> {
+    if log::log_enabled!(log::Level::Debug) {
+        let mut partition = TextPartition::new(Partition::BR, view_size);
+        partition.add_text(vec![&format!("C: {}", timestamp), "_"]);
+        Some(partition)
+    } else {
+        None
+    }
+}
+
+fn overlay_text(
+    view_size: (f32, f32),
+    recovering: bool,
+    stats: Option<Vec<String>>,
+    labels: Vec<((f32, f32), String)>,
+    info: Vec<(LayoutRect, Corners)>,
+    notice: Option<&str>,
+) -> Option<Vec<TextPartition>> {
+    let mut text = Vec::new();
+    for (rect, corners) in info {
+        let origin = (rect.x as f32, rect.y as f32);
+        let size = (rect.width as f32, rect.height as f32);
+        for (corner, lines) in corners {
+            let mut partition = TextPartition::in_area(corner, view_size, origin, size);
+            partition.add_text(lines.iter().map(String::as_str).collect());
+            text.push(partition);
+        }
+    }
+    if recovering {
+        let mut partition = TextPartition::new(Partition::TL, view_size);
+        partition.add_text(vec!["Recovering..."]);
+        text.push(partition);
+    }
+    if let Some(lines) = stats {
+        let mut partition = TextPartition::new(Partition::TR, view_size);
+        partition.add_text(lines.iter().map(String::as_str).collect());
+        text.push(partition);
+    }
+    for ((x, y), label) in labels {
+        let mut partition = TextPartition::new(Partition::At(x, y), view_size);
+        partition.add_text(vec![&label]);
+        text.push(partition);
+    }
+    if let Some(notice) = notice {
+        let mut partition = TextPartition::new(Partition::Center, view_size);
+        partition.add_text(vec![notice]);
+        text.push(partition);
+    }
+    if text.is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scaling_filter_cycle() {
+        let mut filter = ScalingFilter::Lanczos;
+        for _ in 0..4 {
+            filter = filter.next();
+            assert_eq!(ScalingFilter::from_name(filter.name()), Some(filter));
+        }
+        assert_eq!(filter, ScalingFilter::Lanczos);
+        assert_eq!(ScalingFilter::from_name("nearest"), None);
+    }
+
+    #[test]
+    fn test_present_mode_names() {
+        for mode in [
+            PresentMode::Immediate,
+            PresentMode::Vsync,
+            PresentMode::LatestAtVsync,
+        ]
+        .iter()
+        {
+            assert_eq!(PresentMode::from_name(mode.name()), Some(*mode));
+        }
+        assert!(!PresentMode::Immediate.vsync());
+        assert!(PresentMode::LatestAtVsync.vsync());
+    }
+
+    fn frame(x: u32, fills: Vec<Fill>) -> ViewFrame {
+        ViewFrame {
+            sample: None,
+            rect: LayoutRect {
+                x,
+                y: 0,
+                width: 100,
+                height: 100,
+            },
+            text: None,
+            debug_text: None,
+            panes: Vec::new(),
+            overlays: Vec::new(),
+            fills,
+        }
+    }
+
+    #[test]
+    fn test_damage_tracker() {
+        let mut tracker = DamageTracker::default();
+        let frames = || vec![frame(0, Vec::new()), frame(100, Vec::new())];
+        assert_eq!(tracker.update(frames()).0, Damage::Window);
+        let (damage, redrawn) = tracker.update(frames());
+        assert_eq!(damage, Damage::None);
+        assert!(redrawn.is_empty());
+
+        // Only the view with a new fill is redrawn.
+        let fill = Fill {
+            rect: frame(0, Vec::new()).rect,
+            color: [1.0; 4],
+        };
+        let (damage, redrawn) = tracker.update(vec![frame(0, Vec::new()), frame(100, vec![fill])]);
+        assert_eq!(damage, Damage::Views(vec![frame(100, Vec::new()).rect]));
+        assert_eq!(redrawn.len(), 1);
+
+        // The debug timestamp alone doesn't redraw a view, it is drawn with the changed ones.
+        let timestamp = |x| ViewFrame {
+            debug_text: Some(TextPartition::new(Partition::BR, (100.0, 100.0))),
+            ..frame(x, vec![fill])
+        };
+        let (damage, redrawn) = tracker.update(vec![timestamp(0), timestamp(100)]);
+        assert_eq!(damage, Damage::Views(vec![frame(0, Vec::new()).rect]));
+        assert_eq!(redrawn[0].text.as_ref().map(Vec::len), Some(1));
+        assert!(redrawn[0].debug_text.is_none());
+
+        // A new layout, or settings, redraw the window.
+        assert_eq!(tracker.update(vec![frame(0, Vec::new())]).0, Damage::Window);
+        tracker.invalidate();
+        assert_eq!(tracker.update(vec![frame(0, Vec::new())]).0, Damage::Window);
+    }
+}
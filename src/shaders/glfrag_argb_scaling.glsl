@@ -8,6 +8,13 @@ in vec2 image_coord;
 out vec4 f_color;
 
 layout(binding=0) uniform sampler2D image_texture;
+// Selects the scaling filter, see ScalingFilter in renderer.rs.
+layout(location=0) uniform int filter_mode;
+// Display settings, see DisplaySettings in display.rs.
+layout(location=1) uniform int grey_clamp;
+layout(location=2) uniform float chroma_threshold;
+layout(location=3) uniform float gamma;
+layout(location=4) uniform float contrast;
 
 
 #define FIX(c) max(abs(c), 1e-5);
@@ -20,7 +27,6 @@ const mat3 rgb_to_yuv = mat3(
     -0.114572, -0.385428, 0.5, // Column 2
     0.5, -0.454153, -0.045847 // Column 3
 );
-const float CHROMA_THRESHOLD = 1.5;
 
 // from http://www.java-gaming.org/index.php?topic=35123.0
 vec4 cubic(float v){
@@ -246,20 +252,40 @@ vec4 bisharp(sampler2D sampler, vec2 texCoords) {
 
 
 void main() {
-    // f_color = texture(image_texture, image_coord);
-    // f_color = textureBicubic(image_texture, image_coord);
-    f_color = lanc(image_texture, image_coord);
-    // f_color = bisharp(image_texture, image_coord);
+    switch (filter_mode) {
+    case 0:
+        // Bilinear, the texture is set to linear filtering.
+        f_color = texture(image_texture, image_coord);
+        break;
+    case 1:
+        f_color = textureBicubic(image_texture, image_coord);
+        break;
+    case 3:
+        f_color = bisharp(image_texture, image_coord);
+        break;
+    default:
+        f_color = lanc(image_texture, image_coord);
+        break;
+    }
 
-    // Check if the color is close to a grey scale, if so
-    // clamp it to grey. We need accurate grey representation.
     // Multiply from left (to follow example in Python, otherwise transpose the matrix)
     vec3 yuv = f_color.rgb * rgb_to_yuv;
+#ifdef GREY_DISPLAY
+    // Grey display mode, only the luma is shown.
+    f_color = vec4(yuv.r, yuv.r, yuv.r, f_color.a);
+#else
+    // Check if the color is close to a grey scale, if so
+    // clamp it to grey. We need accurate grey representation.
     float max_chroma = max(abs(yuv.g), abs(yuv.b)) * 255.0;
-    if (max_chroma <= CHROMA_THRESHOLD) {
+    if (grey_clamp != 0 && max_chroma <= chroma_threshold) {
         // This seems like a grey, clampt it
         f_color = vec4(yuv.r, yuv.r, yuv.r, f_color.a);
     }
+#endif
+
+    // Contrast around mid grey, then gamma.
+    vec3 adjusted = clamp((f_color.rgb - 0.5) * contrast + 0.5, 0.0, 1.0);
+    f_color = vec4(pow(adjusted, vec3(1.0 / gamma)), f_color.a);
 
     // f_color = vec4(yuv.r, yuv.r, yuv.r, f_color.a);
     // float color_mean = (f_color.r + f_color.g + f_color.b) / 3.0;
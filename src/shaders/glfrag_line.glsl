@@ -0,0 +1,11 @@
+#version 450
+
+precision highp float;
+
+out vec4 f_color;
+
+layout(location=0) uniform vec4 line_color;
+
+void main() {
+    f_color = line_color;
+}
@@ -8,11 +8,12 @@ in vec2 image_coord;
 out vec4 f_color;
 
 layout(binding=0) uniform sampler2D image_texture;
+layout(location=0) uniform vec4 text_color;
 
 void main() {
     float alpha = texture(image_texture, image_coord).r;
     if (alpha <= 0.0) {
         discard;
     }
-    f_color = vec4(1.0, 1.0, 1.0, alpha);
+    f_color = vec4(text_color.rgb, text_color.a * alpha);
 }
\ No newline at end of file
@@ -5,10 +5,13 @@ layout(location=0) in vec2 vertex_pos;
 // layout(location=0) in vec3 vertex_pos;
 layout(location=1) in vec2 tex_coord;
 
+// Moves the text for the outline and shadow passes, zero by default.
+layout(location=1) uniform vec2 offset;
+
 out vec2 image_coord;
 
 void main() {
-    gl_Position = vec4(vertex_pos, 0.0,  1.0);
+    gl_Position = vec4(vertex_pos + offset, 0.0,  1.0);
     // gl_Position = vec4(vertex_pos, 1.0);
     image_coord = tex_coord;
 }
\ No newline at end of file
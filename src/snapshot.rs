@@ -0,0 +1,220 @@
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::{
+    glvideo::Frame,
+    message::LayoutRect,
+    view_state::{Wl, Zoom},
+    window_message::ViewSample,
+};
+
+// Give up, saving nothing, if no frame is rendered from the snapshot state in time.
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The settings of a pane when the snapshot was requested.
+#[derive(Debug, Clone, Serialize)]
+pub struct PaneSnapshot {
+    pub case: Option<String>,
+    pub frame: Option<u32>,
+    pub wl: Wl,
+    pub zoom: Zoom,
+    // Position of the pane in the view.
+    pub layout: LayoutRect,
+}
+
+/// Written as JSON next to the PNG.
+#[derive(Debug, Clone, Serialize)]
+pub struct SnapshotMeta {
+    pub view: usize,
+    pub seq: u64,
+    // Unix time in ms of the request.
+    pub time: u64,
+    pub width: u32,
+    pub height: u32,
+    // The part of the video frame that was saved, in frame pixels.
+    pub crop: Option<LayoutRect>,
+    pub panes: Vec<PaneSnapshot>,
+}
+
+/// A snapshot that waits for the frame rendered from its state.
+#[derive(Debug)]
+pub struct PendingSnapshot {
+    meta: SnapshotMeta,
+    // Crop to this pane of the view.
+    pane_layout: Option<LayoutRect>,
+    view_size: (u32, u32),
+    requested: Instant,
+    // The first frame rendered from the snapshot state.
+    sample: Option<ViewSample>,
+}
+
+impl PendingSnapshot {
+    pub fn new(
+        view: usize,
+        seq: u64,
+        panes: Vec<PaneSnapshot>,
+        pane_layout: Option<LayoutRect>,
+        view_size: (u32, u32),
+    ) -> Self {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        Self {
+            meta: SnapshotMeta {
+                view,
+                seq,
+                time,
+                width: 0,
+                height: 0,
+                crop: None,
+                panes,
+            },
+            pane_layout,
+            view_size,
+            requested: Instant::now(),
+            sample: None,
+        }
+    }
+
+    pub fn seq(&self) -> u64 {
+        self.meta.seq
+    }
+
+    /// True if the frame rendered from the state `displayed` shows the snapshot state.
+    pub fn matches(&self, displayed: Option<u64>) -> bool {
+        displayed == Some(self.meta.seq)
+    }
+
+    /// Keeps the first sample rendered from the snapshot state, `displayed` is the
+    /// echoed seq of its state.
+    pub fn offer(&mut self, displayed: Option<u64>, sample: &ViewSample) {
+        if self.sample.is_none() && self.matches(displayed) {
+            self.sample = Some(sample.clone());
+        }
+    }
+
+    pub fn take_sample(&mut self) -> Option<ViewSample> {
+        self.sample.take()
+    }
+
+    pub fn timed_out(&self) -> bool {
+        self.requested.elapsed() >= SNAPSHOT_TIMEOUT
+    }
+
+    /// Crops the frame read back from the sample, then writes the PNG and its JSON file.
+    pub fn save(mut self, frame: Frame, dir: &Path) -> Result<PathBuf> {
+        let crop = self
+            .pane_layout
+            .map(|pane| crop_rect(pane, self.view_size, (frame.width, frame.height)));
+        let image = match crop {
+            Some(rect) => crop_frame(&frame, rect),
+            None => frame,
+        };
+        self.meta.crop = crop;
+        self.meta.width = image.width;
+        self.meta.height = image.height;
+
+        let base = dir.join(format!(
+            "snapshot-view{}-{}",
+            self.meta.view, self.meta.time
+        ));
+        let path = base.with_extension("png");
+        write_png(&path, &image)?;
+        let json = File::create(base.with_extension("json"))?;
+        serde_json::to_writer_pretty(json, &self.meta)?;
+        Ok(path)
+    }
+}
+
+// The video frame can be scaled relative to the view, map the pane into frame pixels.
+fn crop_rect(pane: LayoutRect, view_size: (u32, u32), frame_size: (u32, u32)) -> LayoutRect {
+    let scale_x = frame_size.0 as f32 / view_size.0.max(1) as f32;
+    let scale_y = frame_size.1 as f32 / view_size.1.max(1) as f32;
+    let x = ((pane.x as f32 * scale_x).round() as u32).min(frame_size.0);
+    let y = ((pane.y as f32 * scale_y).round() as u32).min(frame_size.1);
+    LayoutRect {
+        x,
+        y,
+        width: ((pane.width as f32 * scale_x).round() as u32).min(frame_size.0 - x),
+        height: ((pane.height as f32 * scale_y).round() as u32).min(frame_size.1 - y),
+    }
+}
+
+fn crop_frame(frame: &Frame, rect: LayoutRect) -> Frame {
+    let row_len = frame.width as usize * 4;
+    let data = frame
+        .data
+        .chunks_exact(row_len.max(1))
+        .skip(rect.y as usize)
+        .take(rect.height as usize)
+        .flat_map(|row| &row[rect.x as usize * 4..(rect.x + rect.width) as usize * 4])
+        .copied()
+        .collect();
+    Frame {
+        width: rect.width,
+        height: rect.height,
+        data,
+    }
+}
+
+fn write_png(path: &Path, frame: &Frame) -> Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, frame.width, frame.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&frame.data)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_seq() {
+        let snapshot = PendingSnapshot::new(0, 7, Vec::new(), None, (100, 100));
+        // Frames of older or unknown states are lossy, later states have moved on.
+        assert!(!snapshot.matches(None));
+        assert!(!snapshot.matches(Some(6)));
+        assert!(snapshot.matches(Some(7)));
+        assert!(!snapshot.matches(Some(8)));
+    }
+
+    #[test]
+    fn test_crop_scaled_pane() {
+        let pane = LayoutRect {
+            x: 100,
+            y: 0,
+            width: 100,
+            height: 200,
+        };
+        // The video is decoded at half the view size.
+        let rect = crop_rect(pane, (200, 200), (100, 100));
+        assert_eq!((rect.x, rect.y, rect.width, rect.height), (50, 0, 50, 100));
+    }
+
+    #[test]
+    fn test_crop_frame() {
+        let frame = Frame {
+            width: 3,
+            height: 2,
+            data: (0..24).collect(),
+        };
+        let rect = LayoutRect {
+            x: 1,
+            y: 1,
+            width: 2,
+            height: 1,
+        };
+        let cropped = crop_frame(&frame, rect);
+        assert_eq!((cropped.width, cropped.height), (2, 1));
+        assert_eq!(cropped.data, (16..24).collect::<Vec<u8>>());
+    }
+}
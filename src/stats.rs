@@ -0,0 +1,535 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use gstreamer as gst;
+use gstreamer_webrtc as gst_webrtc;
+
+use crate::util::frame_guard::FrameGuard;
+
+/// Counters reported by an rtpjitterbuffer.
+#[derive(Debug, Clone, Default)]
+pub struct JitterStats {
+    pub num_pushed: u64,
+    pub num_lost: u64,
+    pub num_late: u64,
+    pub num_duplicates: u64,
+    // Average jitter in ns.
+    pub avg_jitter: u64,
+    pub rtx_count: u64,
+    pub rtx_success_count: u64,
+    pub rtx_per_packet: f64,
+    // Round trip time of retransmissions in ns.
+    pub rtx_rtt: u64,
+}
+
+impl JitterStats {
+    pub(crate) fn from_structure(s: &gst::StructureRef) -> Self {
+        // Missing fields are left at 0, the set of fields depends on the GStreamer version.
+        let count = |name| number(s, name).unwrap_or(0.0) as u64;
+        Self {
+            num_pushed: count("num-pushed"),
+            num_lost: count("num-lost"),
+            num_late: count("num-late"),
+            num_duplicates: count("num-duplicates"),
+            avg_jitter: count("avg-jitter"),
+            rtx_count: count("rtx-count"),
+            rtx_success_count: count("rtx-success-count"),
+            rtx_per_packet: number(s, "rtx-per-packet").unwrap_or(0.0),
+            rtx_rtt: count("rtx-rtt"),
+        }
+    }
+}
+
+/// The inbound-rtp statistics webrtcbin reports for a stream.
+#[derive(Debug, Clone, Default)]
+pub struct InboundRtpStats {
+    pub ssrc: u32,
+    pub packets_received: u64,
+    pub bytes_received: u64,
+    pub packets_lost: i64,
+    // Interarrival jitter in seconds.
+    pub jitter: f64,
+    pub nack_count: u64,
+    pub pli_count: u64,
+    pub fir_count: u64,
+}
+
+/// The ICE candidate pair in use, only reported by newer versions of webrtcbin.
+#[derive(Debug, Clone, Default)]
+pub struct CandidatePairStats {
+    pub local_type: Option<String>,
+    pub remote_type: Option<String>,
+    pub protocol: Option<String>,
+    // Round trip time in seconds.
+    pub round_trip_time: Option<f64>,
+}
+
+/// Statistics for one video stream, `stream` is the mline index (the video_id of the view).
+#[derive(Debug, Clone, Default)]
+pub struct StreamStats {
+    pub stream: usize,
+    pub decoder: Option<String>,
+    pub jitter_buffer: Option<JitterStats>,
+    pub inbound: Option<InboundRtpStats>,
+    // Received bitrate in kbit/s, computed between two collections.
+    pub bitrate: Option<f64>,
+    // Counted since the decode bin of the stream was created.
+    pub frames_decoded: u64,
+    pub frames_dropped: u64,
+    // Frames held back after unrecovered loss, and the key frames requested for it.
+    pub frames_held: u64,
+    pub key_frame_requests: u64,
+}
+
+impl StreamStats {
+    /// Short lines for the stats overlay of a view.
+    pub fn overlay_lines(&self, round_trip_time: Option<f64>) -> Vec<String> {
+        let mut lines = Vec::new();
+        if let Some(decoder) = &self.decoder {
+            lines.push(decoder.clone());
+        }
+        if let Some(bitrate) = self.bitrate {
+            lines.push(format!("{:.2} Mbit/s", bitrate / 1000.0));
+        }
+        if let Some(rtt) = round_trip_time {
+            lines.push(format!("RTT: {:.0} ms", rtt * 1000.0));
+        }
+        if let Some(inbound) = &self.inbound {
+            lines.push(format!("Jitter: {:.1} ms", inbound.jitter * 1000.0));
+            lines.push(format!("Lost: {}", inbound.packets_lost));
+        }
+        if let Some(jb) = &self.jitter_buffer {
+            lines.push(format!("Late: {}", jb.num_late));
+            lines.push(format!("RTX: {}/{}", jb.rtx_success_count, jb.rtx_count));
+        }
+        lines.push(format!("Frames: {}", self.frames_decoded));
+        lines.push(format!("Dropped: {}", self.frames_dropped));
+        lines.push(format!(
+            "Held: {} ({} key req)",
+            self.frames_held, self.key_frame_requests
+        ));
+        lines
+    }
+}
+
+/// Statistics for all streams of the session.
+#[derive(Debug, Clone, Default)]
+pub struct SessionStats {
+    // Time since the client was started.
+    pub elapsed: Duration,
+    // Round trip time in seconds, from ICE, RTCP or retransmissions, whichever is reported.
+    pub round_trip_time: Option<f64>,
+    pub candidate_pair: Option<CandidatePairStats>,
+    pub streams: Vec<StreamStats>,
+    // Age of the frames presented since the previous collection.
+    pub present_latency: Option<LatencySummary>,
+}
+
+impl SessionStats {
+    pub fn stream(&self, stream: usize) -> Option<&StreamStats> {
+        self.streams.iter().find(|s| s.stream == stream)
+    }
+}
+
+/// How old the frames were when they were presented, from the arrival of their
+/// samples, in ms.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LatencySummary {
+    pub frames: usize,
+    pub min: f64,
+    pub avg: f64,
+    pub p95: f64,
+}
+
+impl LatencySummary {
+    /// None if no frames were presented.
+    pub fn from_ages(ages: &[Duration]) -> Option<Self> {
+        if ages.is_empty() {
+            return None;
+        }
+        let mut ms: Vec<_> = ages.iter().map(|a| a.as_secs_f64() * 1000.0).collect();
+        ms.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let p95 = ((ms.len() as f64 * 0.95).ceil() as usize).max(1) - 1;
+        Some(Self {
+            frames: ms.len(),
+            min: ms[0],
+            avg: ms.iter().sum::<f64>() / ms.len() as f64,
+            p95: ms[p95],
+        })
+    }
+
+    pub fn overlay_line(&self) -> String {
+        format!(
+            "Present: {:.1}/{:.1}/{:.1} ms",
+            self.min, self.avg, self.p95
+        )
+    }
+}
+
+/// The latest SessionStats, shared between the App and the AppControl.
+#[derive(Debug, Clone, Default)]
+pub struct StatsHandle(Arc<Mutex<Option<SessionStats>>>);
+
+impl StatsHandle {
+    pub fn latest(&self) -> Option<SessionStats> {
+        self.0.lock().unwrap().clone()
+    }
+
+    pub(crate) fn publish(&self, stats: SessionStats) {
+        *self.0.lock().unwrap() = Some(stats);
+    }
+}
+
+/// Frame counts of a decode bin, updated from the streaming threads.
+#[derive(Debug, Default)]
+pub(crate) struct FrameCounters {
+    decoded: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl FrameCounters {
+    pub fn add_decoded(&self) {
+        self.decoded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_dropped(&self, dropped: u64) {
+        // QoS messages carry the total number of frames dropped by the element.
+        self.dropped.fetch_max(dropped, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> (u64, u64) {
+        (
+            self.decoded.load(Ordering::Relaxed),
+            self.dropped.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// What the App knows about a stream when stats are collected.
+pub(crate) struct StreamSource {
+    pub stream: usize,
+    pub ssrc: Option<u32>,
+    pub decoder: Option<String>,
+    pub jitter_buffer: Option<JitterStats>,
+    pub counters: Arc<FrameCounters>,
+    pub guard: Arc<FrameGuard>,
+}
+
+/// Builds SessionStats from the raw GStreamer statistics.
+/// Keeps the byte count from the previous collection to compute bitrates.
+#[derive(Debug)]
+pub(crate) struct StatsCollector {
+    start: Instant,
+    // ssrc, bytes received and time of the previous collection, per stream
+    previous: HashMap<usize, (u32, u64, Instant)>,
+}
+
+impl StatsCollector {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            previous: HashMap::new(),
+        }
+    }
+
+    pub fn collect(
+        &mut self,
+        sources: Vec<StreamSource>,
+        webrtc: Option<&gst::StructureRef>,
+    ) -> SessionStats {
+        let now = Instant::now();
+        let webrtc = webrtc.map(WebRtcStats::parse).unwrap_or_default();
+
+        let mut streams: Vec<_> = sources
+            .into_iter()
+            .map(|source| {
+                let inbound = source
+                    .ssrc
+                    .and_then(|ssrc| webrtc.inbound.get(&ssrc))
+                    .cloned();
+                let bitrate = inbound
+                    .as_ref()
+                    .and_then(|inbound| self.bitrate(source.stream, inbound, now));
+                let (frames_decoded, frames_dropped) = source.counters.get();
+                let (frames_held, key_frame_requests) = source.guard.counts();
+                StreamStats {
+                    stream: source.stream,
+                    decoder: source.decoder,
+                    jitter_buffer: source.jitter_buffer,
+                    inbound,
+                    bitrate,
+                    frames_decoded,
+                    frames_dropped,
+                    frames_held,
+                    key_frame_requests,
+                }
+            })
+            .collect();
+        streams.sort_by_key(|s| s.stream);
+        // Forget streams that went away.
+        self.previous
+            .retain(|stream, _| streams.iter().any(|s| s.stream == *stream));
+
+        // Prefer the ICE round trip, then RTCP and last the retransmission estimate.
+        let rtx_rtt = streams
+            .iter()
+            .filter_map(|s| s.jitter_buffer.as_ref())
+            .map(|jb| jb.rtx_rtt)
+            .filter(|rtt| *rtt > 0)
+            .max()
+            .map(|rtt| rtt as f64 / 1e9);
+        let round_trip_time = webrtc
+            .candidate_pair
+            .as_ref()
+            .and_then(|pair| pair.round_trip_time)
+            .or(webrtc.remote_round_trip_time)
+            .or(rtx_rtt);
+
+        SessionStats {
+            elapsed: now - self.start,
+            round_trip_time,
+            candidate_pair: webrtc.candidate_pair,
+            streams,
+            present_latency: None,
+        }
+    }
+
+    fn bitrate(&mut self, stream: usize, inbound: &InboundRtpStats, now: Instant) -> Option<f64> {
+        let previous = self
+            .previous
+            .insert(stream, (inbound.ssrc, inbound.bytes_received, now));
+        match previous {
+            // A new ssrc restarts the byte count.
+            Some((ssrc, bytes, time))
+                if ssrc == inbound.ssrc && inbound.bytes_received >= bytes =>
+            {
+                let seconds = (now - time).as_secs_f64();
+                if seconds > 0.0 {
+                    Some((inbound.bytes_received - bytes) as f64 * 8.0 / 1000.0 / seconds)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+// The parts of the webrtcbin get-stats reply we use.
+#[derive(Debug, Default)]
+struct WebRtcStats {
+    inbound: HashMap<u32, InboundRtpStats>,
+    candidate_pair: Option<CandidatePairStats>,
+    remote_round_trip_time: Option<f64>,
+}
+
+impl WebRtcStats {
+    fn parse(reply: &gst::StructureRef) -> Self {
+        // The reply has one structure per stats object, keyed on the stats id.
+        let entries: HashMap<&str, gst::Structure> = reply
+            .iter()
+            .filter_map(|(id, value)| {
+                value
+                    .get::<gst::Structure>()
+                    .ok()
+                    .flatten()
+                    .map(|s| (id, s))
+            })
+            .collect();
+
+        let mut stats = WebRtcStats::default();
+        for s in entries.values() {
+            match s.get_some::<gst_webrtc::WebRTCStatsType>("type") {
+                Ok(gst_webrtc::WebRTCStatsType::InboundRtp) => {
+                    let ssrc = match number(s, "ssrc") {
+                        Some(ssrc) => ssrc as u32,
+                        None => continue,
+                    };
+                    let count = |name| number(s, name).unwrap_or(0.0) as u64;
+                    stats.inbound.insert(
+                        ssrc,
+                        InboundRtpStats {
+                            ssrc,
+                            packets_received: count("packets-received"),
+                            bytes_received: count("bytes-received"),
+                            packets_lost: number(s, "packets-lost").unwrap_or(0.0) as i64,
+                            jitter: number(s, "jitter").unwrap_or(0.0),
+                            nack_count: count("nack-count"),
+                            pli_count: count("pli-count"),
+                            fir_count: count("fir-count"),
+                        },
+                    );
+                }
+                Ok(gst_webrtc::WebRTCStatsType::RemoteInboundRtp)
+                | Ok(gst_webrtc::WebRTCStatsType::RemoteOutboundRtp) => {
+                    if let Some(rtt) = number(s, "round-trip-time") {
+                        stats.remote_round_trip_time = Some(rtt);
+                    }
+                }
+                Ok(gst_webrtc::WebRTCStatsType::CandidatePair) => {
+                    let candidate = |field| {
+                        text(s, field)
+                            .and_then(|id| entries.get(id.as_str()))
+                            .map(|c| c.as_ref())
+                    };
+                    let local = candidate("local-candidate-id");
+                    let remote = candidate("remote-candidate-id");
+                    stats.candidate_pair = Some(CandidatePairStats {
+                        local_type: local.and_then(|c| text(c, "candidate-type")),
+                        remote_type: remote.and_then(|c| text(c, "candidate-type")),
+                        protocol: local.and_then(|c| text(c, "protocol")),
+                        round_trip_time: number(s, "current-round-trip-time"),
+                    });
+                }
+                _ => {}
+            }
+        }
+        stats
+    }
+}
+
+fn number(s: &gst::StructureRef, name: &str) -> Option<f64> {
+    // Counters are reported with different integer types across versions.
+    let value = s.get_value(name).ok()?;
+    value
+        .get_some::<u64>()
+        .map(|v| v as f64)
+        .or_else(|_| value.get_some::<i64>().map(|v| v as f64))
+        .or_else(|_| value.get_some::<u32>().map(f64::from))
+        .or_else(|_| value.get_some::<i32>().map(f64::from))
+        .or_else(|_| value.get_some::<f64>())
+        .ok()
+}
+
+fn text(s: &gst::StructureRef, name: &str) -> Option<String> {
+    // Enums, e.g. the candidate type, are converted to their name.
+    let value = s.get_value(name).ok()?;
+    value
+        .transform::<String>()
+        .and_then(|v| v.get::<String>().ok().flatten())
+}
+
+const CSV_HEADER: &str = "elapsed_s,stream,decoder,bitrate_kbps,packets_received,packets_lost,jitter_ms,rtt_ms,\
+jb_pushed,jb_lost,jb_late,jb_duplicates,jb_avg_jitter_ms,rtx_count,rtx_success_count,frames_decoded,frames_dropped,frames_held,key_frame_requests";
+
+/// Appends SessionStats to a CSV file, one row per stream.
+#[derive(Debug)]
+pub(crate) struct StatsCsv {
+    file: File,
+}
+
+impl StatsCsv {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        // Only write the header to a new file, so that runs can be appended.
+        if file.metadata()?.len() == 0 {
+            writeln!(file, "{}", CSV_HEADER)?;
+        }
+        Ok(Self { file })
+    }
+
+    pub fn append(&mut self, stats: &SessionStats) -> io::Result<()> {
+        for row in csv_rows(stats) {
+            writeln!(self.file, "{}", row)?;
+        }
+        self.file.flush()
+    }
+}
+
+fn csv_rows(stats: &SessionStats) -> Vec<String> {
+    let optional = |v: Option<f64>| v.map(|v| format!("{:.3}", v)).unwrap_or_default();
+    stats
+        .streams
+        .iter()
+        .map(|s| {
+            let jb = s.jitter_buffer.clone().unwrap_or_default();
+            let inbound = s.inbound.as_ref();
+            format!(
+                "{:.3},{},{},{},{},{},{},{},{},{},{},{},{:.3},{},{},{},{},{},{}",
+                stats.elapsed.as_secs_f64(),
+                s.stream,
+                s.decoder.as_deref().unwrap_or(""),
+                optional(s.bitrate),
+                inbound.map_or(0, |i| i.packets_received),
+                inbound.map_or(0, |i| i.packets_lost),
+                optional(inbound.map(|i| i.jitter * 1000.0)),
+                optional(stats.round_trip_time.map(|rtt| rtt * 1000.0)),
+                jb.num_pushed,
+                jb.num_lost,
+                jb.num_late,
+                jb.num_duplicates,
+                jb.avg_jitter as f64 / 1e6,
+                jb.rtx_count,
+                jb.rtx_success_count,
+                s.frames_decoded,
+                s.frames_dropped,
+                s.frames_held,
+                s.key_frame_requests,
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_row() {
+        let stats = SessionStats {
+            elapsed: Duration::from_millis(1500),
+            round_trip_time: Some(0.012),
+            candidate_pair: None,
+            streams: vec![StreamStats {
+                stream: 1,
+                decoder: Some("avdec_h264".to_owned()),
+                bitrate: Some(2500.0),
+                frames_decoded: 30,
+                frames_dropped: 2,
+                ..StreamStats::default()
+            }],
+            present_latency: None,
+        };
+        let rows = csv_rows(&stats);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(
+            rows[0],
+            "1.500,1,avdec_h264,2500.000,0,0,,12.000,0,0,0,0,0.000,0,0,30,2,0,0"
+        );
+        assert_eq!(rows[0].split(',').count(), CSV_HEADER.split(',').count());
+    }
+
+    #[test]
+    fn test_latency_summary() {
+        assert_eq!(LatencySummary::from_ages(&[]), None);
+        let ages: Vec<_> = (1..=20).rev().map(Duration::from_millis).collect();
+        let summary = LatencySummary::from_ages(&ages).unwrap();
+        assert_eq!(summary.frames, 20);
+        assert_eq!(summary.min, 1.0);
+        assert_eq!(summary.avg, 10.5);
+        assert_eq!(summary.p95, 19.0);
+        assert_eq!(summary.overlay_line(), "Present: 1.0/10.5/19.0 ms");
+    }
+
+    #[test]
+    fn test_jitter_stats_missing_fields() {
+        gst::init().unwrap();
+        let s = gst::Structure::builder("application/x-rtp-jitterbuffer-stats")
+            .field("num-pushed", &10u64)
+            .field("rtx-per-packet", &0.5f64)
+            .build();
+        let stats = JitterStats::from_structure(&s);
+        assert_eq!(stats.num_pushed, 10);
+        assert_eq!(stats.num_lost, 0);
+        assert_eq!(stats.rtx_per_packet, 0.5);
+    }
+}
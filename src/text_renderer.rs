@@ -1,12 +1,136 @@
-use std::{mem, ptr};
+use std::{mem, path::PathBuf, ptr};
 
+use anyhow::Result;
 use glyph_brush::{ab_glyph::FontArc, HorizontalAlign, Layout, Section, Text, VerticalAlign};
+use serde::{Deserialize, Serialize};
 
 use super::{
     bindings::gl,
     vertex::{Quad, Vertex},
     view_state::ViewState,
 };
+
+pub fn default_font() -> FontArc {
+    FontArc::try_from_slice(include_bytes!("../fonts/segoe-ui/Segoe UI.ttf"))
+        .expect("Failed to load font")
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TextEffect {
+    None,
+    // The glyphs are drawn around the text in the effect colour.
+    Outline,
+    // The glyphs are drawn below and to the right of the text in the effect colour.
+    Shadow,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+/// Horizontal alignment of the text in each corner, None keeps it to the side of the corner.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CornerAlign {
+    pub top_left: Option<Align>,
+    pub top_right: Option<Align>,
+    pub bottom_left: Option<Align>,
+    pub bottom_right: Option<Align>,
+}
+
+/// Font and look of the overlay text. Colours are RGBA in 0-1.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TextStyle {
+    // A TrueType or OpenType file, the embedded font if not set.
+    pub font: Option<PathBuf>,
+    // Scales the size of the text, which follows the size of its pane.
+    pub size: f32,
+    pub color: [f32; 4],
+    pub effect: TextEffect,
+    pub effect_color: [f32; 4],
+    // Width of the outline or offset of the shadow, in logical pixels.
+    pub effect_width: f32,
+    pub align: CornerAlign,
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        // Outlined, so the text can be read over bright images too.
+        Self {
+            font: None,
+            size: 1.0,
+            color: [1.0, 1.0, 1.0, 1.0],
+            effect: TextEffect::Outline,
+            effect_color: [0.0, 0.0, 0.0, 1.0],
+            effect_width: 1.0,
+            align: CornerAlign::default(),
+        }
+    }
+}
+
+impl TextStyle {
+    /// White text without outline.
+    pub fn plain() -> Self {
+        Self {
+            effect: TextEffect::None,
+            ..Self::default()
+        }
+    }
+
+    pub fn shadow() -> Self {
+        Self {
+            effect: TextEffect::Shadow,
+            effect_width: 2.0,
+            ..Self::default()
+        }
+    }
+
+    /// Parses a preset name or a path to a JSON file.
+    pub fn from_arg(arg: &str) -> Result<Self> {
+        match arg {
+            "default" | "outline" => Ok(Self::default()),
+            "plain" => Ok(Self::plain()),
+            "shadow" => Ok(Self::shadow()),
+            path => {
+                let json = std::fs::read_to_string(path)?;
+                Ok(serde_json::from_str(&json)?)
+            }
+        }
+    }
+
+    pub fn load_font(&self) -> Result<FontArc> {
+        match &self.font {
+            Some(path) => Ok(FontArc::try_from_vec(std::fs::read(path)?)?),
+            None => Ok(default_font()),
+        }
+    }
+
+    /// Where the effect is drawn, relative to the text in physical pixels.
+    pub fn effect_offsets(&self, scale_factor: f32) -> Vec<(f32, f32)> {
+        let w = (self.effect_width * scale_factor).max(1.0);
+        match self.effect {
+            TextEffect::None => Vec::new(),
+            TextEffect::Outline => vec![
+                (-w, -w),
+                (0.0, -w),
+                (w, -w),
+                (-w, 0.0),
+                (w, 0.0),
+                (-w, w),
+                (0.0, w),
+                (w, w),
+            ],
+            TextEffect::Shadow => vec![(w, w)],
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 struct GlyphQuad {
     vertices: Vec<Vertex>,
@@ -17,15 +141,16 @@ pub struct TextRenderer {
     glyph_texture_width: u32,
     glyph_texture_height: u32,
     glyph_brush: glyph_brush::GlyphBrush<GlyphQuad>,
+    // Physical pixels per logical pixel of the window.
+    scale_factor: f32,
+    style: TextStyle,
 }
 impl TextRenderer {
     pub fn new(bindings: &gl::Gl) -> Self {
         // let font =
         //     FontArc::try_from_slice(include_bytes!("../../fonts/open-sans/OpenSans-Regular.ttf"))
         //         .expect("Failed to load font");
-        let font = FontArc::try_from_slice(include_bytes!("../fonts/segoe-ui/Segoe UI.ttf"))
-            .expect("Failed to load font");
-        let glyph_brush = glyph_brush::GlyphBrushBuilder::using_font(font).build();
+        let glyph_brush = glyph_brush::GlyphBrushBuilder::using_font(default_font()).build();
         // Create the texture handle
         let glyph_texture = Self::create_glyph_texture(bindings);
         let glyph_texture_width = 256;
@@ -43,9 +168,38 @@ impl TextRenderer {
             glyph_texture_width,
             glyph_texture_height,
             cached_quads: Vec::default(),
+            scale_factor: 1.0,
+            style: TextStyle::default(),
         }
     }
 
+    pub fn set_scale_factor(&mut self, scale_factor: f32) {
+        self.scale_factor = scale_factor;
+    }
+
+    pub fn set_style(&mut self, style: TextStyle) {
+        if style.font != self.style.font {
+            let font = style.load_font().unwrap_or_else(|e| {
+                log::error!("Failed to load font {:?}: {:?}", style.font, e);
+                default_font()
+            });
+            // Glyphs are cached again in the current texture.
+            self.glyph_brush = glyph_brush::GlyphBrushBuilder::using_font(font)
+                .initial_cache_size((self.glyph_texture_width, self.glyph_texture_height))
+                .build();
+        }
+        self.style = style;
+    }
+
+    pub fn style(&self) -> &TextStyle {
+        &self.style
+    }
+
+    /// Offsets of the outline or shadow passes, drawn before the text.
+    pub fn effect_offsets(&self) -> Vec<(f32, f32)> {
+        self.style.effect_offsets(self.scale_factor)
+    }
+
     fn create_glyph_texture(bindings: &gl::Gl) -> u32 {
         unsafe {
             let mut texture_id = mem::MaybeUninit::uninit();
@@ -83,12 +237,13 @@ impl TextRenderer {
     pub fn draw(
         &mut self,
         bindings: &gl::Gl,
-        sections: Vec<Section>,
+        text: &[TextPartition],
         viewport_size: (f32, f32),
     ) -> (u32, Vec<Vertex>, Vec<u16>) {
         // Queue all text render operations
-        for s in sections {
-            self.glyph_brush.queue(s);
+        for partition in text {
+            self.glyph_brush
+                .queue(partition.section(&self.style, self.scale_factor));
         }
 
         loop {
@@ -195,21 +350,45 @@ impl TextRenderer {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Partition {
     TL,
     TR,
     BL,
     BR,
+    // Top left corner of the text, in view pixels.
+    At(f32, f32),
+    // Centered in the view.
+    Center,
 }
 
 impl Partition {
-    fn screen_position(&self, viewport_size: (f32, f32)) -> (f32, f32) {
-        match self {
+    fn screen_position(&self, viewport_size: (f32, f32), align: Option<Align>) -> (f32, f32) {
+        let (x, y) = match self {
             Partition::TL => (0_f32, 0_f32),
             Partition::TR => (viewport_size.0, 0_f32),
             Partition::BL => (0_f32, viewport_size.1),
             Partition::BR => (viewport_size.0, viewport_size.1),
+            Partition::At(x, y) => return (*x, *y),
+            Partition::Center => return (viewport_size.0 / 2_f32, viewport_size.1 / 2_f32),
+        };
+        // The text is aligned within its half of the viewport.
+        let half = viewport_size.0 / 2_f32;
+        let left = if x > 0_f32 { half } else { 0_f32 };
+        match align {
+            Some(Align::Left) => (left, y),
+            Some(Align::Center) => (left + half / 2_f32, y),
+            Some(Align::Right) => (left + half, y),
+            None => (x, y),
+        }
+    }
+    fn corner_align(&self, align: &CornerAlign) -> Option<Align> {
+        match self {
+            Partition::TL => align.top_left,
+            Partition::TR => align.top_right,
+            Partition::BL => align.bottom_left,
+            Partition::BR => align.bottom_right,
+            Partition::At(..) | Partition::Center => None,
         }
     }
     fn bounds(&self, viewport_size: (f32, f32)) -> (f32, f32) {
@@ -217,30 +396,47 @@ impl Partition {
     }
     fn horizontal_alignment(&self) -> HorizontalAlign {
         match self {
-            Partition::TL | Partition::BL => HorizontalAlign::Left,
+            Partition::TL | Partition::BL | Partition::At(..) => HorizontalAlign::Left,
             Partition::TR | Partition::BR => HorizontalAlign::Right,
+            Partition::Center => HorizontalAlign::Center,
         }
     }
     fn vertical_alignment(&self) -> VerticalAlign {
         match self {
-            Partition::TL | Partition::TR => VerticalAlign::Top,
+            Partition::TL | Partition::TR | Partition::At(..) => VerticalAlign::Top,
             Partition::BL | Partition::BR => VerticalAlign::Bottom,
+            Partition::Center => VerticalAlign::Center,
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct TextPartition {
     partition: Partition,
     viewport_size: (f32, f32),
+    // Top left corner and size of the area the partition is placed in, in view pixels.
+    origin: (f32, f32),
+    area_size: (f32, f32),
     text: Option<String>,
 }
 
 impl TextPartition {
     pub fn new(partition: Partition, viewport_size: (f32, f32)) -> Self {
+        Self::in_area(partition, viewport_size, (0_f32, 0_f32), viewport_size)
+    }
+
+    /// A partition of a part of the viewport, e.g. a pane.
+    pub fn in_area(
+        partition: Partition,
+        viewport_size: (f32, f32),
+        origin: (f32, f32),
+        area_size: (f32, f32),
+    ) -> Self {
         Self {
             partition,
             viewport_size,
+            origin,
+            area_size,
             text: None,
         }
     }
@@ -249,29 +445,88 @@ impl TextPartition {
         self.viewport_size
     }
 
-    fn pixel_scale(&self) -> f32 {
-        // Compute the pixel scale of the text, which depends on the viewport size
-        (self.viewport_size.1 * 20_f32 / 512_f32).round().max(25f32)
+    fn pixel_scale(&self, scale_factor: f32) -> f32 {
+        // Compute the pixel scale of the text, which depends on the size of the area.
+        // In logical pixels, so the text keeps its size on HiDPI monitors.
+        let height = self.area_size.1 / scale_factor;
+        (height * 20_f32 / 512_f32).round().max(25f32) * scale_factor
     }
 
     pub fn add_text(&mut self, lines: Vec<&str>) {
         self.text = Some(lines.join("\n"));
     }
 
-    pub fn section(&self) -> Section {
+    pub fn section(&self, style: &TextStyle, scale_factor: f32) -> Section {
         let text = if let Some(ref text) = self.text {
             text.as_str()
         } else {
             "" // Lifetimes are covariant
         };
+        let align = self.partition.corner_align(&style.align);
+        let h_align = match align {
+            Some(Align::Left) => HorizontalAlign::Left,
+            Some(Align::Center) => HorizontalAlign::Center,
+            Some(Align::Right) => HorizontalAlign::Right,
+            None => self.partition.horizontal_alignment(),
+        };
         Section::default()
             .with_layout(
                 Layout::default_wrap()
-                    .h_align(self.partition.horizontal_alignment())
+                    .h_align(h_align)
                     .v_align(self.partition.vertical_alignment()),
             )
-            .with_screen_position(self.partition.screen_position(self.viewport_size))
-            .with_bounds(self.partition.bounds(self.viewport_size))
-            .add_text(Text::new(text).with_scale(self.pixel_scale()))
+            .with_screen_position({
+                let (x, y) = self.partition.screen_position(self.area_size, align);
+                (self.origin.0 + x, self.origin.1 + y)
+            })
+            .with_bounds(self.partition.bounds(self.area_size))
+            .add_text(Text::new(text).with_scale(self.pixel_scale(scale_factor) * style.size))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scale_factor() {
+        let partition = TextPartition::new(Partition::TL, (1280.0, 1024.0));
+        assert_eq!(partition.pixel_scale(1.0), 40.0);
+        // The same window on a HiDPI monitor is 512 logical pixels high.
+        assert_eq!(partition.pixel_scale(2.0), 50.0);
+        let style = TextStyle {
+            size: 1.5,
+            ..TextStyle::default()
+        };
+        assert_eq!(partition.section(&style, 2.0).text[0].scale.y, 75.0);
+    }
+
+    #[test]
+    fn test_corner_align() {
+        let style = TextStyle {
+            align: CornerAlign {
+                top_right: Some(Align::Left),
+                bottom_left: Some(Align::Center),
+                ..CornerAlign::default()
+            },
+            ..TextStyle::default()
+        };
+        let position = |partition| {
+            TextPartition::in_area(partition, (400.0, 200.0), (100.0, 0.0), (200.0, 100.0))
+                .section(&style, 1.0)
+                .screen_position
+        };
+        // Within the right half of the pane.
+        assert_eq!(position(Partition::TR), (200.0, 0.0));
+        assert_eq!(position(Partition::BL), (150.0, 100.0));
+        assert_eq!(position(Partition::BR), (300.0, 100.0));
+        assert_eq!(position(Partition::Center), (200.0, 50.0));
+    }
+
+    #[test]
+    fn test_effect_offsets() {
+        assert_eq!(TextStyle::plain().effect_offsets(1.0), vec![]);
+        assert_eq!(TextStyle::default().effect_offsets(2.0).len(), 8);
+        assert_eq!(TextStyle::shadow().effect_offsets(1.5), vec![(3.0, 3.0)]);
     }
 }
@@ -0,0 +1,56 @@
+use std::time::Duration;
+
+/// Exponential backoff used when retrying a failed operation.
+/// The delay doubles for each failure, starting at `min` and capped at `max`.
+#[derive(Debug)]
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+    current: Option<Duration>,
+}
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        Self {
+            min,
+            max,
+            current: None,
+        }
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let next = self
+            .current
+            .map_or(self.min, |current| (current * 2).min(self.max));
+        self.current = Some(next);
+        next
+    }
+
+    pub fn reset(&mut self) {
+        self.current = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_doubles_until_max() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_millis(500));
+        assert_eq!(backoff.next_delay(), Duration::from_millis(100));
+        assert_eq!(backoff.next_delay(), Duration::from_millis(200));
+        assert_eq!(backoff.next_delay(), Duration::from_millis(400));
+        assert_eq!(backoff.next_delay(), Duration::from_millis(500));
+        assert_eq!(backoff.next_delay(), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_millis(500));
+        backoff.next_delay();
+        backoff.next_delay();
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_millis(100));
+    }
+}
@@ -18,6 +18,10 @@ pub struct ElementTimer {
 impl ElementTimer {
     const MAX_PENDING: usize = 100;
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     pub fn new(name: &str, sink_element: gst::Element, source_element: gst::Element) -> Self {
         let pending = Arc::new(Mutex::new(Vec::new()));
         let probe_mask: gst::PadProbeType =
@@ -0,0 +1,197 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+// Ask again if the key frame hasn't arrived by then.
+const KEY_FRAME_RETRY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Clean,
+    // Frames depend on lost data until the next key frame.
+    Corrupt,
+    // A clean key frame with this pts went into the decoder, the frames from it on are good.
+    Recovering(Option<u64>),
+}
+
+#[derive(Debug)]
+struct Inner {
+    state: State,
+    // Set once the first packet went into the depayloader.
+    started: bool,
+    last_request: Option<Instant>,
+    held: u64,
+    requests: u64,
+}
+
+/// Tracks unrecovered loss in a stream. Decoded frames from the loss up to the next
+/// key frame are held back, so the view keeps the last good frame instead of showing
+/// artifacts. Called from the streaming threads of the decode bin.
+#[derive(Debug)]
+pub struct FrameGuard {
+    // If false frames are only tracked, e.g. to request key frames.
+    hold: bool,
+    inner: Mutex<Inner>,
+}
+
+impl FrameGuard {
+    pub fn new(hold: bool) -> Self {
+        Self {
+            hold,
+            inner: Mutex::new(Inner {
+                state: State::Clean,
+                started: false,
+                last_request: None,
+                held: 0,
+                requests: 0,
+            }),
+        }
+    }
+
+    /// A packet was lost or the decoder failed on a frame.
+    /// Returns true if a key frame should be requested.
+    pub fn mark_corrupt(&self) -> bool {
+        self.mark_corrupt_at(Instant::now())
+    }
+
+    fn mark_corrupt_at(&self, now: Instant) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let was_corrupt = inner.state == State::Corrupt;
+        inner.state = State::Corrupt;
+        // One request per loss burst, retries are made by `retry_request`.
+        if was_corrupt {
+            false
+        } else {
+            inner.request(now);
+            true
+        }
+    }
+
+    /// An RTP packet goes into the depayloader, `discont` is set on packets that follow a gap.
+    /// Returns true if a key frame should be requested.
+    pub fn on_packet(&self, discont: bool) -> bool {
+        self.on_packet_at(discont, Instant::now())
+    }
+
+    fn on_packet_at(&self, discont: bool, now: Instant) -> bool {
+        let started = std::mem::replace(&mut self.inner.lock().unwrap().started, true);
+        // The first packet of a stream is always marked.
+        discont && started && self.mark_corrupt_at(now)
+    }
+
+    /// A frame goes into the decoder. `discont` is set on data that follows lost packets.
+    pub fn on_input(&self, key_frame: bool, discont: bool, pts: Option<u64>) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.state == State::Corrupt && key_frame && !discont {
+            inner.state = State::Recovering(pts);
+        }
+    }
+
+    /// A decoded frame comes out of the decoder, returns false if it has to be held back.
+    /// Decoded frames are all key frames, they are matched to the input on the pts.
+    pub fn on_output(&self, pts: Option<u64>) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.state {
+            State::Clean => return true,
+            State::Recovering(from) if from.is_none() || pts >= from => {
+                inner.state = State::Clean;
+                return true;
+            }
+            _ => {}
+        }
+        if self.hold {
+            inner.held += 1;
+        }
+        !self.hold
+    }
+
+    /// Returns true if the stream is still corrupt and the last request was a while ago.
+    pub fn retry_request(&self) -> bool {
+        self.retry_request_at(Instant::now())
+    }
+
+    fn retry_request_at(&self, now: Instant) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let due = match inner.last_request {
+            Some(last) => now.saturating_duration_since(last) >= KEY_FRAME_RETRY,
+            None => true,
+        };
+        if inner.state == State::Corrupt && due {
+            inner.request(now);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Frames held back and key frames requested.
+    pub fn counts(&self) -> (u64, u64) {
+        let inner = self.inner.lock().unwrap();
+        (inner.held, inner.requests)
+    }
+}
+
+impl Inner {
+    fn request(&mut self, now: Instant) {
+        self.last_request = Some(now);
+        self.requests += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hold_until_key_frame() {
+        let guard = FrameGuard::new(true);
+        assert!(guard.on_output(Some(0)));
+        assert!(guard.mark_corrupt());
+        // Further loss before the key frame doesn't request again.
+        assert!(!guard.mark_corrupt());
+        assert!(!guard.on_output(Some(1)));
+        // A key frame that was damaged itself doesn't end it.
+        guard.on_input(true, true, Some(2));
+        guard.on_input(false, false, Some(3));
+        guard.on_input(true, false, Some(4));
+        assert!(!guard.on_output(Some(2)));
+        assert!(!guard.on_output(Some(3)));
+        assert!(guard.on_output(Some(4)));
+        assert!(guard.on_output(Some(5)));
+        assert_eq!(guard.counts(), (3, 1));
+    }
+
+    #[test]
+    fn test_discont_is_loss() {
+        let guard = FrameGuard::new(true);
+        let now = Instant::now();
+        assert!(!guard.on_packet_at(true, now));
+        assert!(!guard.on_packet_at(false, now));
+        assert!(guard.on_output(Some(0)));
+        // A gap after the start is a loss even without a lost packet event.
+        assert!(guard.on_packet_at(true, now));
+        assert!(!guard.on_packet_at(true, now));
+        assert!(!guard.on_output(Some(1)));
+        assert_eq!(guard.counts(), (1, 1));
+    }
+
+    #[test]
+    fn test_retry_request() {
+        let guard = FrameGuard::new(true);
+        let now = Instant::now();
+        assert!(!guard.retry_request_at(now));
+        assert!(guard.mark_corrupt_at(now));
+        assert!(!guard.retry_request_at(now));
+        assert!(guard.retry_request_at(now + KEY_FRAME_RETRY));
+        assert_eq!(guard.counts().1, 2);
+    }
+
+    #[test]
+    fn test_track_only() {
+        let guard = FrameGuard::new(false);
+        guard.mark_corrupt();
+        assert!(guard.on_output(Some(0)));
+        assert_eq!(guard.counts().0, 0);
+    }
+}
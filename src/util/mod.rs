@@ -1,4 +1,10 @@
 pub mod timed_iter;
 pub mod element_timer;
 pub mod window_timer;
-pub mod bitrate;
\ No newline at end of file
+pub mod bitrate;
+pub mod backoff;
+pub mod recovery;
+pub mod rate_control;
+pub mod frame_guard;
+pub mod state_history;
+pub mod rtp_times;
@@ -0,0 +1,247 @@
+use std::time::{Duration, Instant};
+
+// Loss (lost + late packets) and retransmission ratios above the HIGH values mean
+// the link is congested, below the LOW values the interval counts as good.
+const HIGH_LOSS: f32 = 0.02;
+const LOW_LOSS: f32 = 0.005;
+const HIGH_RTX: f32 = 0.05;
+const LOW_RTX: f32 = 0.01;
+const HIGH_JITTER_MS: f32 = 30.0;
+const LOW_JITTER_MS: f32 = 10.0;
+// Consecutive intervals needed before the rate is changed.
+const DECREASE_AFTER: usize = 2;
+const INCREASE_AFTER: usize = 5;
+const DECREASE_FACTOR: f32 = 0.7;
+const INCREASE_STEP: f32 = 0.05;
+// How long the controller leaves a rate set by hand alone.
+const MANUAL_HOLD: Duration = Duration::from_secs(10);
+// Longer gaps between frames are the server idling, not jitter.
+const MAX_FRAME_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Cumulative receive counters of a stream.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReceiveCounters {
+    pub received: u64,
+    pub lost: u64,
+    pub late: u64,
+    pub retransmitted: u64,
+}
+
+/// Scales the bitrate of a stream between `min` and `max` from the loss,
+/// retransmissions and frame jitter it sees. Lowers the rate quickly on congestion
+/// and raises it slowly once the link has been clean for a while.
+#[derive(Debug)]
+pub struct RateController {
+    min: f32,
+    max: f32,
+    factor: f32,
+    previous: Option<ReceiveCounters>,
+    congested: usize,
+    good: usize,
+    hold_until: Option<Instant>,
+}
+
+impl RateController {
+    pub fn new(min: f32, max: f32) -> Self {
+        Self {
+            min,
+            max,
+            factor: 1.0_f32.max(min).min(max),
+            previous: None,
+            congested: 0,
+            good: 0,
+            hold_until: None,
+        }
+    }
+
+    /// The factor to apply to the scheduled bitrate.
+    pub fn factor(&self) -> f32 {
+        self.factor
+    }
+
+    /// Feeds the counters and frame jitter of the last interval, returns true if the factor changed.
+    pub fn update(&mut self, counters: ReceiveCounters, jitter_ms: f32) -> bool {
+        self.update_at(counters, jitter_ms, Instant::now())
+    }
+
+    fn update_at(&mut self, counters: ReceiveCounters, jitter_ms: f32, now: Instant) -> bool {
+        let previous = match self.previous.replace(counters) {
+            // Counters start over when the stream is rebuilt.
+            Some(p) if counters.received >= p.received => p,
+            _ => return false,
+        };
+        let received = counters.received - previous.received;
+        if received == 0 {
+            // Nothing was sent, there is nothing to judge the link on.
+            return false;
+        }
+        if matches!(self.hold_until, Some(until) if now < until) {
+            return false;
+        }
+
+        let lost = counters.lost.saturating_sub(previous.lost)
+            + counters.late.saturating_sub(previous.late);
+        let loss = lost as f32 / (received + lost) as f32;
+        let rtx = counters
+            .retransmitted
+            .saturating_sub(previous.retransmitted) as f32
+            / received as f32;
+
+        if loss > HIGH_LOSS || rtx > HIGH_RTX || jitter_ms > HIGH_JITTER_MS {
+            self.congested += 1;
+            self.good = 0;
+        } else if loss < LOW_LOSS && rtx < LOW_RTX && jitter_ms < LOW_JITTER_MS {
+            self.good += 1;
+            self.congested = 0;
+        } else {
+            // In between the thresholds, keep the current rate.
+            self.congested = 0;
+            self.good = 0;
+        }
+
+        let factor = if self.congested >= DECREASE_AFTER {
+            self.congested = 0;
+            self.factor * DECREASE_FACTOR
+        } else if self.good >= INCREASE_AFTER {
+            self.good = 0;
+            self.factor + INCREASE_STEP
+        } else {
+            return false;
+        };
+        let factor = factor.max(self.min).min(self.max);
+        let changed = (factor - self.factor).abs() > f32::EPSILON;
+        self.factor = factor;
+        changed
+    }
+
+    /// The user sets the rate by hand. Returns how much the manual scale has to change
+    /// to keep the current rate, the controller then starts over from there.
+    pub fn take_over(&mut self) -> f32 {
+        self.take_over_at(Instant::now())
+    }
+
+    fn take_over_at(&mut self, now: Instant) -> f32 {
+        let previous = self.factor;
+        self.factor = 1.0_f32.max(self.min).min(self.max);
+        self.congested = 0;
+        self.good = 0;
+        self.hold_until = Some(now + MANUAL_HOLD);
+        previous / self.factor
+    }
+
+    /// Forget the counters, e.g. when the session is rebuilt.
+    pub fn reset_counters(&mut self) {
+        self.previous = None;
+    }
+}
+
+/// Variation of the time between frames, measured as the mean absolute deviation.
+#[derive(Debug, Default)]
+pub struct ArrivalJitter {
+    last: Option<Instant>,
+    intervals: Vec<f32>,
+}
+
+impl ArrivalJitter {
+    pub fn record(&mut self, arrival: Instant) {
+        if let Some(last) = self.last.replace(arrival) {
+            let interval = arrival.saturating_duration_since(last);
+            if interval <= MAX_FRAME_INTERVAL {
+                self.intervals.push(interval.as_secs_f32() * 1000.0);
+            }
+        }
+    }
+
+    /// The jitter in ms since the last call.
+    pub fn take(&mut self) -> f32 {
+        let intervals = std::mem::take(&mut self.intervals);
+        if intervals.len() < 2 {
+            return 0.0;
+        }
+        let mean = intervals.iter().sum::<f32>() / intervals.len() as f32;
+        intervals.iter().map(|i| (i - mean).abs()).sum::<f32>() / intervals.len() as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counters(received: u64, lost: u64) -> ReceiveCounters {
+        ReceiveCounters {
+            received,
+            lost,
+            ..ReceiveCounters::default()
+        }
+    }
+
+    #[test]
+    fn test_decrease_on_loss() {
+        let mut rate = RateController::new(0.25, 1.0);
+        let now = Instant::now();
+        assert!(!rate.update_at(counters(0, 0), 0.0, now));
+        // One bad interval is not enough.
+        assert!(!rate.update_at(counters(100, 10), 0.0, now));
+        assert!(rate.update_at(counters(200, 20), 0.0, now));
+        assert!((rate.factor() - DECREASE_FACTOR).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_hysteresis() {
+        let mut rate = RateController::new(0.25, 1.0);
+        let now = Instant::now();
+        rate.update_at(counters(0, 0), 0.0, now);
+        // 1% loss is neither congested nor good, the rate is kept.
+        for i in 1..20 {
+            assert!(!rate.update_at(counters(i * 99, i), 0.0, now));
+        }
+        assert_eq!(rate.factor(), 1.0);
+    }
+
+    #[test]
+    fn test_bounds() {
+        let mut rate = RateController::new(0.5, 1.0);
+        let now = Instant::now();
+        rate.update_at(counters(0, 0), 0.0, now);
+        for i in 1..20 {
+            rate.update_at(counters(i * 100, i * 10), 0.0, now);
+        }
+        assert_eq!(rate.factor(), 0.5);
+        for i in 20..200 {
+            rate.update_at(counters(i * 100, 190), 0.0, now);
+        }
+        assert_eq!(rate.factor(), 1.0);
+    }
+
+    #[test]
+    fn test_take_over_holds() {
+        let mut rate = RateController::new(0.25, 1.0);
+        let now = Instant::now();
+        rate.update_at(counters(0, 0), 0.0, now);
+        rate.update_at(counters(100, 10), 0.0, now);
+        rate.update_at(counters(200, 20), 0.0, now);
+        let scale = rate.take_over_at(now);
+        assert!((scale - DECREASE_FACTOR).abs() < 1e-6);
+        assert_eq!(rate.factor(), 1.0);
+        // Congestion is ignored while the manual rate is held.
+        rate.update_at(counters(300, 30), 0.0, now);
+        assert!(!rate.update_at(counters(400, 40), 0.0, now));
+        let later = now + MANUAL_HOLD;
+        rate.update_at(counters(500, 50), 0.0, later);
+        assert!(rate.update_at(counters(600, 60), 0.0, later));
+    }
+
+    #[test]
+    fn test_arrival_jitter() {
+        let mut jitter = ArrivalJitter::default();
+        let now = Instant::now();
+        for (i, ms) in [0, 20, 40, 60].iter().enumerate() {
+            jitter.record(now + Duration::from_millis(*ms) + Duration::from_millis(i as u64 % 2));
+        }
+        assert!(jitter.take() < 1.0);
+        jitter.record(now + Duration::from_millis(100));
+        jitter.record(now + Duration::from_millis(150));
+        jitter.record(now + Duration::from_millis(160));
+        assert!(jitter.take() > 10.0);
+    }
+}
@@ -0,0 +1,67 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// Limits how often a stream is rebuilt. When a stream has used up its attempts
+/// within the window, rebuilding it doesn't help and the session should be restarted.
+#[derive(Debug)]
+pub struct RecoveryTracker {
+    max_attempts: usize,
+    window: Duration,
+    attempts: HashMap<usize, Vec<Instant>>,
+}
+
+impl RecoveryTracker {
+    pub fn new(max_attempts: usize, window: Duration) -> Self {
+        Self {
+            max_attempts,
+            window,
+            attempts: HashMap::new(),
+        }
+    }
+
+    /// Records a recovery attempt for the stream, returns false if there are no attempts left.
+    pub fn try_attempt(&mut self, stream: usize) -> bool {
+        self.try_attempt_at(stream, Instant::now())
+    }
+
+    fn try_attempt_at(&mut self, stream: usize, now: Instant) -> bool {
+        let window = self.window;
+        let attempts = self.attempts.entry(stream).or_default();
+        attempts.retain(|t| now.duration_since(*t) < window);
+        if attempts.len() >= self.max_attempts {
+            return false;
+        }
+        attempts.push(now);
+        true
+    }
+
+    pub fn reset(&mut self) {
+        self.attempts.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attempts_per_stream() {
+        let mut tracker = RecoveryTracker::new(2, Duration::from_secs(10));
+        let now = Instant::now();
+        assert!(tracker.try_attempt_at(0, now));
+        assert!(tracker.try_attempt_at(0, now));
+        assert!(!tracker.try_attempt_at(0, now));
+        assert!(tracker.try_attempt_at(1, now));
+    }
+
+    #[test]
+    fn test_attempts_expire() {
+        let mut tracker = RecoveryTracker::new(1, Duration::from_secs(10));
+        let now = Instant::now();
+        assert!(tracker.try_attempt_at(0, now));
+        assert!(!tracker.try_attempt_at(0, now + Duration::from_secs(5)));
+        assert!(tracker.try_attempt_at(0, now + Duration::from_secs(11)));
+    }
+}
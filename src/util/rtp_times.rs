@@ -0,0 +1,77 @@
+use std::{collections::VecDeque, sync::Mutex};
+
+// Frames between the depayloader and the appsink are far fewer than this.
+const MAX_FRAMES: usize = 64;
+
+/// The RTP timestamps of the frames going into the depayloader by their pts, so a
+/// decoded frame can be matched with the RTP timestamp the server sent it with.
+/// Called from the streaming threads of the decode bin.
+#[derive(Debug, Default)]
+pub struct RtpTimes(Mutex<VecDeque<(u64, u32)>>);
+
+impl RtpTimes {
+    /// An RTP packet with the pts goes into the depayloader.
+    pub fn record(&self, pts: u64, rtp_time: u32) {
+        let mut times = self.0.lock().unwrap();
+        // The packets of a frame share the pts and the RTP timestamp.
+        if times.back().map(|(last, _)| *last) == Some(pts) {
+            return;
+        }
+        if times.len() == MAX_FRAMES {
+            times.pop_front();
+        }
+        times.push_back((pts, rtp_time));
+    }
+
+    /// The RTP timestamp of a decoded frame, which keeps the pts of its packets.
+    pub fn lookup(&self, pts: u64) -> Option<u32> {
+        let times = self.0.lock().unwrap();
+        times
+            .iter()
+            .rev()
+            .find(|(p, _)| *p == pts)
+            .map(|(_, rtp_time)| *rtp_time)
+    }
+}
+
+/// The timestamp in the header of an RTP packet.
+pub fn rtp_timestamp(packet: &[u8]) -> Option<u32> {
+    // Version 2, the timestamp follows the flags and sequence number.
+    if packet.len() < 12 || packet[0] >> 6 != 2 {
+        return None;
+    }
+    Some(u32::from_be_bytes([
+        packet[4], packet[5], packet[6], packet[7],
+    ]))
+}
+
+/// True if the RTP timestamp `a` is not later than `b`, allowing for wrap around.
+pub fn rtp_not_after(a: u32, b: u32) -> bool {
+    b.wrapping_sub(a) as i32 >= 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let times = RtpTimes::default();
+        times.record(100, 9000);
+        times.record(100, 9000);
+        times.record(133, 12000);
+        assert_eq!(times.lookup(133), Some(12000));
+        assert_eq!(times.lookup(100), Some(9000));
+        assert_eq!(times.lookup(166), None);
+    }
+
+    #[test]
+    fn test_rtp_timestamp() {
+        let packet = [0x80, 96, 0, 1, 0x12, 0x34, 0x56, 0x78, 0, 0, 0, 1, 0xff];
+        assert_eq!(rtp_timestamp(&packet), Some(0x1234_5678));
+        assert_eq!(rtp_timestamp(&packet[..8]), None);
+        assert!(rtp_not_after(u32::MAX - 10, 5));
+        assert!(!rtp_not_after(5, u32::MAX - 10));
+        assert!(rtp_not_after(7, 7));
+    }
+}
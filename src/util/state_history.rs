@@ -0,0 +1,161 @@
+use std::collections::VecDeque;
+
+use crate::{
+    util::rtp_times::rtp_not_after,
+    view_state::{Prediction, ViewState},
+};
+
+// The server is hopelessly behind if it echoes a state older than this.
+const MAX_STATES: usize = 64;
+
+/// The pane states sent to the server by sequence number. The server echoes the seq
+/// of a state with the RTP timestamp of the first frame rendered from it, so the
+/// frame on screen can be compared to the latest state. The echo comes over the
+/// datachannel and usually arrives before the frames that were sent ahead of it.
+#[derive(Debug, Default)]
+pub struct StateHistory {
+    sent: VecDeque<(u64, Vec<ViewState>)>,
+    // The echoed seqs with the RTP timestamp of their first frame, oldest first.
+    rendered: VecDeque<(u32, u64)>,
+    // RTP timestamp of the frame on screen, if known.
+    frame: Option<u32>,
+}
+
+impl StateHistory {
+    pub fn record(&mut self, seq: u64, states: Vec<ViewState>) {
+        if self.sent.len() == MAX_STATES {
+            self.sent.pop_front();
+        }
+        self.sent.push_back((seq, states));
+    }
+
+    /// The server echoed the seq of a state and the RTP timestamp of its first frame.
+    pub fn set_rendered(&mut self, seq: u64, rtp_time: u32) {
+        // Older echoes don't move it back.
+        if matches!(self.rendered.back(), Some((_, last)) if *last >= seq) {
+            return;
+        }
+        if self.rendered.len() == MAX_STATES {
+            self.rendered.pop_front();
+        }
+        self.rendered.push_back((rtp_time, seq));
+    }
+
+    /// A new frame with the RTP timestamp is shown.
+    pub fn push_frame(&mut self, rtp_time: Option<u32>) {
+        self.frame = rtp_time;
+    }
+
+    pub fn clear(&mut self) {
+        self.sent.clear();
+        self.rendered.clear();
+        self.frame = None;
+    }
+
+    /// Seq of the state the frame on screen was rendered from, the latest echoed
+    /// state whose first frame is not later than it. None if no echo matches yet.
+    pub fn displayed(&self) -> Option<u64> {
+        let frame = self.frame?;
+        self.rendered
+            .iter()
+            .rev()
+            .find(|(rtp_time, _)| rtp_not_after(*rtp_time, frame))
+            .map(|(_, seq)| *seq)
+    }
+
+    /// The pane states of the frame on screen, if known.
+    pub fn displayed_states(&self) -> Option<&[ViewState]> {
+        let seq = self.displayed()?;
+        self.sent
+            .iter()
+            .find(|(sent, _)| *sent == seq)
+            .map(|(_, states)| states.as_slice())
+    }
+
+    /// Predictions for the panes of the frame on screen, given their sizes.
+    /// Empty if the frame is up to date or its state is unknown.
+    pub fn predictions(&self, viewports: &[(f32, f32)]) -> Vec<Option<Prediction>> {
+        let (displayed, latest) = match (self.displayed_states(), self.sent.back()) {
+            (Some(displayed), Some((_, latest))) => (displayed, latest),
+            _ => return Vec::new(),
+        };
+        if displayed.len() != latest.len() || latest.len() != viewports.len() {
+            return Vec::new();
+        }
+        let predictions: Vec<_> = displayed
+            .iter()
+            .zip(latest.iter())
+            .zip(viewports.iter())
+            .map(|((displayed, latest), viewport)| {
+                Prediction::between(displayed, latest, *viewport)
+            })
+            .collect();
+        if predictions.iter().all(Option::is_none) {
+            Vec::new()
+        } else {
+            predictions
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::view_state::Zoom;
+
+    fn state(mag: f32, pos: (f32, f32)) -> ViewState {
+        let mut state = ViewState::new();
+        state.set_zoom_mode(Zoom::Fit(mag));
+        state.set_position(pos);
+        state
+    }
+
+    #[test]
+    fn test_predict_until_echoed() {
+        let viewports = [(100.0, 100.0)];
+        let mut history = StateHistory::default();
+        history.record(0, vec![state(1.0, (0.0, 0.0))]);
+        history.record(1, vec![state(1.0, (10.0, -5.0))]);
+        // Nothing is known about the frame on screen yet.
+        history.push_frame(Some(900));
+        assert!(history.predictions(&viewports).is_empty());
+
+        // The echo may arrive after its frame.
+        history.set_rendered(0, 900);
+        let pan = Prediction {
+            scale: 1.0,
+            offset: (10.0, -5.0),
+        };
+        assert_eq!(history.predictions(&viewports), vec![Some(pan)]);
+
+        // The echo of the new state arrives ahead of the frames still rendered
+        // from the old one, and an older echo doesn't move it back.
+        history.set_rendered(1, 3900);
+        history.set_rendered(0, 900);
+        history.push_frame(Some(1900));
+        assert_eq!(history.displayed(), Some(0));
+        assert_eq!(history.predictions(&viewports), vec![Some(pan)]);
+        history.push_frame(Some(3900));
+        assert_eq!(history.displayed(), Some(1));
+        assert!(history.predictions(&viewports).is_empty());
+        // Frames without an RTP timestamp have an unknown state.
+        history.push_frame(None);
+        assert_eq!(history.displayed(), None);
+    }
+
+    #[test]
+    fn test_predict_zoom() {
+        let viewports = [(100.0, 50.0), (100.0, 50.0)];
+        let mut history = StateHistory::default();
+        history.record(4, vec![state(1.0, (10.0, 0.0)), state(1.0, (0.0, 0.0))]);
+        history.record(5, vec![state(2.0, (10.0, 0.0)), state(1.0, (0.0, 0.0))]);
+        history.set_rendered(4, 0);
+        history.push_frame(Some(0));
+        // Zooms about the image center, at (60, 25) in the first pane.
+        let zoom = Prediction {
+            scale: 2.0,
+            offset: (-60.0, -25.0),
+        };
+        assert_eq!(history.predictions(&viewports), vec![Some(zoom), None]);
+    }
+}
@@ -1,4 +1,4 @@
-use super::view_state::{Position, ViewState, Zoom};
+use super::view_state::{Position, Prediction, ViewState, Zoom};
 use cgmath::prelude::*;
 
 pub const NUM_VERTEX_COORDS: usize = 2;
@@ -12,11 +12,30 @@ pub struct Vertex {
     tex_coords: TextureCoordinate,
 }
 
+impl Vertex {
+    pub fn new(position: VertexCoordinate, tex_coords: TextureCoordinate) -> Self {
+        Self {
+            position,
+            tex_coords,
+        }
+    }
+
+    pub fn position(&self) -> VertexCoordinate {
+        self.position
+    }
+
+    pub fn tex_coords(&self) -> TextureCoordinate {
+        self.tex_coords
+    }
+}
+
 #[derive(Debug)]
 pub struct Quad {
     vertices: Vec<Vertex>,
     indexes: Vec<u16>,
     shader_to_screen: ViewTransform,
+    // Moves the image to where it is predicted to be, in viewport pixels.
+    prediction: ViewTransform,
     viewport_size: (f32, f32),
     texture_size: (f32, f32),
     image_size: (f32, f32),
@@ -49,6 +68,7 @@ impl Quad {
             vertices: Self::VERTICES.iter().map(Vertex::clone).collect(),
             indexes: Self::INDICES.iter().map(u16::clone).collect(),
             shader_to_screen: ViewTransform::identity(),
+            prediction: ViewTransform::identity(),
             viewport_size: (1_f32, 1_f32),
             texture_size: (1_f32, 1_f32),
             image_size: (1_f32, 1_f32),
@@ -91,10 +111,24 @@ impl Quad {
         // x_trans += disp.0;
         // y_trans += disp.1;
         transform.compose_mut(&ViewTransform::translate(x_trans, y_trans));
+        transform.compose_mut(&self.prediction);
 
         transform
     }
 
+    /// Maps image pixels to viewport pixels, y down.
+    pub fn image_to_viewport(&self, state: &ViewState) -> ViewTransform {
+        self.compute_image_to_screen(state)
+    }
+
+    pub fn set_prediction(&mut self, prediction: Option<&Prediction>) {
+        self.prediction = match prediction {
+            Some(p) => ViewTransform::scale_diag(p.scale)
+                .compose(&ViewTransform::translate(p.offset.0, p.offset.1)),
+            None => ViewTransform::identity(),
+        };
+    }
+
     pub fn get_vertex(&self, state: &ViewState) -> Vec<Vertex> {
         //&self.vertices
         let mut vertex_tranform = self.compute_image_to_screen(state); //self.image_to_screen.clone();
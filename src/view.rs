@@ -1,6 +1,7 @@
 use std::{
     convert::TryFrom,
     ops::Deref,
+    path::Path,
     sync::{Arc, Mutex},
 };
 
@@ -13,18 +14,32 @@ use gstreamer_video as gst_video;
 use gstreamer_webrtc as gst_webrtc;
 
 use crate::{
+    info_overlay::{Corners, InfoOverlay, PaneInfo},
     interaction::{InteractionState, SyncOperation},
+    measure::{MeasurementStore, Point},
     message::{
         CaseMeta, ClientConfig, DataMessage, LayoutCfg, LayoutRect, PaneState, Protocols,
         RenderState, ViewportSize,
     },
-    util::bitrate::Schedule,
+    pane_style::{PaneBadges, PaneStyle},
+    recording::StateLog,
+    renderer::{PaneFrame, PaneOverlay},
+    snapshot::{PaneSnapshot, PendingSnapshot},
+    stats::{LatencySummary, SessionStats, StreamStats},
+    util::{
+        bitrate::Schedule,
+        rate_control::{ArrivalJitter, RateController, ReceiveCounters},
+        state_history::StateHistory,
+    },
     view,
     view_state::ViewState,
     window_message::ViewSample,
     AppConfig,
 };
 
+// How long a notice, e.g. the filter after F, is shown over the views.
+const NOTICE_DURATION: std::time::Duration = std::time::Duration::from_millis(1500);
+
 fn tile(view_size: (u32, u32), rows: usize, columns: usize) -> Vec<LayoutRect> {
     // Align to 4 pixels
     let view_width = view_size.0 as f32 / columns as f32;
@@ -53,6 +68,8 @@ pub struct Pane {
     interaction: InteractionState,
     dirty: bool,
     case: Option<CaseMeta>,
+    // The case and store revision the measurements were loaded at.
+    measured: Option<(String, u64)>,
 }
 
 impl Default for Pane {
@@ -68,6 +85,7 @@ impl Default for Pane {
             interaction: InteractionState::new(),
             dirty: false,
             case: None,
+            measured: None,
         }
     }
 }
@@ -126,6 +144,20 @@ impl Pane {
                         self.interaction.adjust_cine_speec(-1);
                         true
                     }
+                    Some(VirtualKeyCode::M) => {
+                        let measurements = self.interaction.measurements_mut();
+                        measurements.cycle_tool();
+                        log::info!("Measuring with {:?}", measurements.tool());
+                        true
+                    }
+                    Some(VirtualKeyCode::Back) => {
+                        self.interaction.measurements_mut().undo();
+                        true
+                    }
+                    Some(VirtualKeyCode::Delete) => {
+                        self.interaction.measurements_mut().clear();
+                        true
+                    }
                     _ => false,
                 }
             }
@@ -171,10 +203,18 @@ impl Pane {
     pub fn set_case(&mut self, case: Option<CaseMeta>) {
         // Reset the interaction state
         self.interaction = InteractionState::new();
+        self.interaction
+            .set_viewport((self.layout.width as f32, self.layout.height as f32));
         self.case = case;
+        // Load the measurements of the case on the next sync.
+        self.measured = None;
 
         if let Some(case) = &self.case {
             self.interaction.set_image_count(case.number_of_images);
+            self.interaction.set_image_size(
+                case.image_size
+                    .map(|(width, height)| (width as f32, height as f32)),
+            );
         } else {
             self.interaction.set_image_count(0);
         }
@@ -191,10 +231,96 @@ impl Pane {
 
     pub fn set_layout(&mut self, layout: LayoutRect) {
         self.layout = layout;
+        self.interaction
+            .set_viewport((layout.width as f32, layout.height as f32));
         // Assume the layout changes us so we are dirty
         self.dirty = true;
     }
 
+    // Stores the edits and reloads the measurements when the case, or the list of the case
+    // in the store, changed. Returns true if the overlay has to be redrawn.
+    fn sync_measurements(&mut self, store: &mut MeasurementStore) -> bool {
+        let (redraw, changes) = self.interaction.measurements_mut().take_changes();
+        if let (Some((key, _)), false) = (&self.measured, changes.is_empty()) {
+            store.apply(key, changes);
+        }
+        let current = self
+            .case
+            .as_ref()
+            .map(|c| (c.key.clone(), store.revision()));
+        if current == self.measured {
+            return redraw;
+        }
+        let measurements = current
+            .as_ref()
+            .map(|(key, _)| store.get(key))
+            .unwrap_or_default();
+        self.interaction.measurements_mut().set_done(measurements);
+        self.measured = current;
+        true
+    }
+
+    // The outlines of the measurements and their labels, placed in the view.
+    fn measurement_overlay(&self, width: f32) -> Option<(PaneOverlay, Vec<(Point, String)>)> {
+        let measurements = self.interaction.measurements();
+        let preview = measurements.preview();
+        let shapes: Vec<_> = measurements.on_image().chain(preview.iter()).collect();
+        if shapes.is_empty() {
+            return None;
+        }
+        let transform = self.interaction.image_to_pane()?;
+        let place = |p: Point| {
+            let p = transform.transform_vertex(&[p.0, p.1]);
+            (p[0], p[1])
+        };
+        // Millimetres only if the points are known to be in image pixels.
+        let spacing = self
+            .case
+            .as_ref()
+            .filter(|c| c.image_size.is_some())
+            .and_then(|c| c.pixel_spacing);
+        let mut lines = Vec::new();
+        let mut labels = Vec::new();
+        for shape in shapes {
+            lines.extend(shape.lines().iter().map(|[a, b]| [place(*a), place(*b)]));
+            // Labels outside the pane would end up in its neighbours.
+            let (x, y) = place(shape.anchor());
+            if x >= 0.0 && y >= 0.0 && x < self.layout.width as f32 && y < self.layout.height as f32
+            {
+                let position = (x + self.layout.x as f32, y + self.layout.y as f32);
+                labels.push((position, shape.label(spacing)));
+            }
+        }
+        Some((
+            PaneOverlay {
+                rect: self.layout,
+                lines,
+                width,
+            },
+            labels,
+        ))
+    }
+
+    fn badges(&self) -> PaneBadges {
+        PaneBadges {
+            synchronized: self.interaction.is_synchronized(),
+            cine: self.interaction.cine_fps().is_some(),
+        }
+    }
+
+    // The state of the pane for the info overlay, the view fills in the stream.
+    // The state is that of the frame on screen, if known.
+    fn info(&self, state: Option<ViewState>) -> PaneInfo {
+        PaneInfo {
+            case: self.case.as_ref().map(|c| c.key.clone()),
+            frames: self.case.as_ref().map(|c| c.number_of_images),
+            state: self.case.as_ref().and(state),
+            synchronized: self.interaction.is_synchronized(),
+            cine: self.interaction.cine_fps(),
+            ..PaneInfo::default()
+        }
+    }
+
     pub fn invalidate(&mut self) {
         self.dirty = true;
     }
@@ -219,6 +345,17 @@ impl Pane {
     pub fn handle_timer_event(&mut self) -> bool {
         self.interaction.cine_update()
     }
+
+    pub fn snapshot(&self) -> PaneSnapshot {
+        let state = self.interaction.get_render_state();
+        PaneSnapshot {
+            case: self.case.as_ref().map(|c| c.key.clone()),
+            frame: state.frame,
+            wl: state.wl,
+            zoom: state.zoom,
+            layout: self.layout,
+        }
+    }
 }
 
 impl LayoutRect {
@@ -251,6 +388,29 @@ pub struct View {
     seq: u64,
     timer: std::time::Instant,
     schedule: Schedule,
+    // Set while the stream is rebuilt after an error, until a new sample arrives.
+    recovering: bool,
+    show_stats: bool,
+    // Latest stats of the stream and the session round trip time.
+    stats: Option<(StreamStats, Option<f64>)>,
+    // Adapts the bitrate to the link, if enabled.
+    rate: Option<RateController>,
+    arrival_jitter: ArrivalJitter,
+    // Set when the bitrate changed and has to be sent to the server.
+    bitrate_changed: bool,
+    // Logs the states sent while the stream is recorded.
+    state_log: Option<StateLog>,
+    // Waits for the frame of a snapshot request.
+    snapshot: Option<PendingSnapshot>,
+    // Sent states, to predict where the panes of the current frame have moved.
+    history: StateHistory,
+    // Frames shown since the last stats, and the rate computed from them.
+    frames_shown: u32,
+    fps_timer: std::time::Instant,
+    fps: Option<f32>,
+    // Set once the current sample has been presented.
+    presented: bool,
+    present_latency: Option<LatencySummary>,
 }
 
 impl View {
@@ -266,6 +426,8 @@ impl View {
         video_scaling: f32,
         fullrange: bool,
         schedule: Schedule,
+        show_stats: bool,
+        adaptive_bitrate: Option<(f32, f32)>,
     ) -> Self {
         // This is the expected name of the data channel.
         let data_id = format!("video{}-data", video_id);
@@ -289,6 +451,20 @@ impl View {
             seq: 0,
             timer: std::time::Instant::now(),
             schedule,
+            recovering: false,
+            show_stats,
+            stats: None,
+            rate: adaptive_bitrate.map(|(min, max)| RateController::new(min, max)),
+            arrival_jitter: ArrivalJitter::default(),
+            bitrate_changed: false,
+            state_log: None,
+            snapshot: None,
+            history: StateHistory::default(),
+            frames_shown: 0,
+            fps_timer: std::time::Instant::now(),
+            fps: None,
+            presented: false,
+            present_latency: None,
         }
     }
 
@@ -322,6 +498,17 @@ impl View {
         true
     }
 
+    pub fn reset_session(&mut self) {
+        // Drop everything that belongs to the old webrtc session.
+        self.datachannel.take();
+        self.current_sample.take();
+        self.history.clear();
+        self.dirty = true;
+        if let Some(rate) = self.rate.as_mut() {
+            rate.reset_counters();
+        }
+    }
+
     fn accept_sample(&self, sample: &ViewSample) -> bool {
         if self.dirty {
             // Check if the size of the sample is within bounds.
@@ -341,12 +528,104 @@ impl View {
     }
 
     pub fn push_sample(&mut self, sample: ViewSample) {
+        self.arrival_jitter.record(sample.timer);
         if self.accept_sample(&sample) {
+            self.history.push_frame(sample.rtp_time);
+            // Checked for every sample, more than one can arrive between renders.
+            if let Some(pending) = self.snapshot.as_mut() {
+                pending.offer(self.history.displayed(), &sample);
+            }
             self.current_sample = Some(sample);
+            self.frames_shown += 1;
+            self.presented = false;
             self.dirty = false;
+            self.recovering = false;
+        }
+    }
+
+    pub fn set_recovering(&mut self) {
+        // Samples from the failed decoder are not valid anymore.
+        self.current_sample.take();
+        self.recovering = true;
+    }
+
+    pub fn clear_stream(&mut self) {
+        self.current_sample.take();
+        self.history.clear();
+        self.state_log.take();
+        self.recovering = false;
+        // Force a state update if the stream comes back.
+        self.dirty = true;
+    }
+
+    pub fn is_recovering(&self) -> bool {
+        self.recovering
+    }
+
+    pub fn set_stats(&mut self, stats: &SessionStats) {
+        self.stats = stats
+            .stream(self.video_id)
+            .map(|s| (s.clone(), stats.round_trip_time));
+        self.present_latency = stats.present_latency;
+        let jitter = self.arrival_jitter.take();
+        let elapsed = self.fps_timer.elapsed().as_secs_f32();
+        self.fps = Some(self.frames_shown as f32 / elapsed);
+        self.frames_shown = 0;
+        self.fps_timer = std::time::Instant::now();
+
+        // Stats arrive once a second, let the rate controller react to them.
+        let counters = self.stats.as_ref().and_then(|(s, _)| receive_counters(s));
+        if let (Some(rate), Some(counters)) = (self.rate.as_mut(), counters) {
+            if rate.update(counters, jitter) {
+                self.bitrate_changed = true;
+                log::info!(
+                    "Adapting bitrate of view {} to {:.2}",
+                    self.video_id,
+                    self.get_bitrate()
+                );
+            }
         }
     }
 
+    pub fn toggle_stats(&mut self) {
+        self.show_stats = !self.show_stats;
+    }
+
+    pub fn shows_stats(&self) -> bool {
+        self.show_stats
+    }
+
+    /// The lines of the stats overlay, if it is enabled.
+    pub fn get_stats_overlay(&self) -> Option<Vec<String>> {
+        if !self.show_stats {
+            return None;
+        }
+        match &self.stats {
+            Some((stats, rtt)) => {
+                let mut lines = stats.overlay_lines(*rtt);
+                if let Some(rate) = &self.rate {
+                    lines.push(format!("Rate: {:.2}x", rate.factor()));
+                }
+                if let Some(latency) = &self.present_latency {
+                    lines.push(latency.overlay_line());
+                }
+                Some(lines)
+            }
+            None => Some(vec!["No stats".to_owned()]),
+        }
+    }
+
+    pub fn start_state_log(&mut self, path: &Path) {
+        match StateLog::create(path) {
+            Ok(log) => self.state_log = Some(log),
+            Err(e) => log::error!("Failed to create state log {}: {:?}", path.display(), e),
+        }
+    }
+
+    pub fn stop_state_log(&mut self) {
+        self.state_log.take();
+    }
+
     pub fn push_render_state(&self, state: RenderState) {
         self.try_send_message(DataMessage::NewState(state));
     }
@@ -378,7 +657,19 @@ impl View {
             lossless: self.lossless,
             video_scaling: self.video_scaling,
             fullrange: self.fullrange,
+            // Filled in by the App which knows the decoders.
+            codecs: Vec::new(),
+        }
+    }
+
+    /// The arrival time of the current sample, the first time it is presented.
+    pub fn take_unpresented(&mut self) -> Option<std::time::Instant> {
+        let sample = self.current_sample.as_ref()?;
+        if self.presented {
+            return None;
         }
+        self.presented = true;
+        Some(sample.timer)
     }
 
     pub fn get_current_sample(&self) -> Option<ViewSample> {
@@ -399,6 +690,7 @@ impl View {
             .scaling((self.layout.width, self.layout.height));
         // Remove the stale sample
         self.current_sample.take();
+        self.history.clear();
         self.dirty = true;
     }
 
@@ -481,6 +773,14 @@ impl View {
                         self.adjust_bitrate_scaling(-1);
                         true
                     }
+                    Some(VirtualKeyCode::H) => {
+                        self.toggle_stats();
+                        true
+                    }
+                    Some(VirtualKeyCode::P) => {
+                        self.request_snapshot(self.focus);
+                        true
+                    }
                     _ => self.handle_translated_event(event),
                 }
             }
@@ -520,23 +820,161 @@ impl View {
         (self.timer.elapsed().as_millis() % 1000) as f32
     }
 
-    pub fn push_state(&mut self) {
-        let dirty = self.panes.iter().any(|p| p.dirty) || self.dirty;
+    // Returns true if a new state was sent.
+    pub fn push_state(&mut self) -> bool {
+        let dirty = self.panes.iter().any(|p| p.dirty) || self.dirty || self.bitrate_changed;
         if dirty {
-            let pane_states: Vec<_> = self.panes.iter_mut().map(|p| p.get_state()).collect();
-
-            self.push_render_state(RenderState {
-                layout: self.layout.clone(),
-                seq: self.seq,
-                panes: pane_states,
-                snapshot: false,
-                timestamp: self.get_timestamp(),
-                bitrate: self.get_bitrate(),
-                scaling: self.video_scaling,
-            });
-            // Increase the sequence number
-            self.seq += 1;
+            self.bitrate_changed = false;
+            self.send_state(false);
+        }
+        dirty
+    }
+
+    fn sync_measurements(&mut self, store: &mut MeasurementStore) -> bool {
+        let mut redraw = false;
+        for pane in self.panes.iter_mut() {
+            redraw = pane.sync_measurements(store) || redraw;
+        }
+        redraw
+    }
+
+    /// The measurements drawn over the panes with lines of the width, in window pixels,
+    /// and their labels in view pixels.
+    pub fn get_measurement_overlays(&self, width: f32) -> (Vec<PaneOverlay>, Vec<(Point, String)>) {
+        let mut overlays = Vec::new();
+        let mut labels = Vec::new();
+        let panes = self
+            .panes
+            .iter()
+            .filter_map(|p| p.measurement_overlay(width));
+        for (overlay, pane_labels) in panes {
+            overlays.push(overlay);
+            labels.extend(pane_labels);
+        }
+        (overlays, labels)
+    }
+
+    /// The text in the corners of each pane, placed in the view.
+    pub fn get_pane_info(&self, overlay: &InfoOverlay) -> Vec<(LayoutRect, Corners)> {
+        let bitrate = self.stats.as_ref().and_then(|(stats, _)| stats.bitrate);
+        // Not the latest local states, the server may lag behind them.
+        let displayed = self
+            .history
+            .displayed_states()
+            .filter(|states| states.len() == self.panes.len());
+        self.panes
+            .iter()
+            .enumerate()
+            .map(|(idx, pane)| {
+                let info = PaneInfo {
+                    bitrate,
+                    fps: self.fps,
+                    ..pane.info(displayed.map(|states| states[idx]))
+                };
+                (pane.layout, overlay.corners(&info))
+            })
+            .collect()
+    }
+
+    /// The panes with their sync and cine state, placed in the view.
+    pub fn get_pane_badges(&self) -> Vec<(LayoutRect, PaneBadges)> {
+        self.panes.iter().map(|p| (p.layout, p.badges())).collect()
+    }
+
+    /// The server rendered the state with this seq, from the frame with the RTP timestamp on.
+    pub fn set_rendered(&mut self, seq: u64, rtp_time: u32) {
+        self.history.set_rendered(seq, rtp_time);
+    }
+
+    /// The panes and how they are predicted to have moved, empty if the current frame is up to date.
+    pub fn get_predictions(&self) -> Vec<PaneFrame> {
+        let viewports: Vec<_> = self
+            .panes
+            .iter()
+            .map(|p| (p.layout.width as f32, p.layout.height as f32))
+            .collect();
+        self.history
+            .predictions(&viewports)
+            .into_iter()
+            .zip(self.panes.iter())
+            .map(|(prediction, pane)| PaneFrame {
+                rect: pane.layout,
+                prediction,
+            })
+            .collect()
+    }
+
+    // Sends the state of all panes, returns its sequence number.
+    fn send_state(&mut self, snapshot: bool) -> u64 {
+        let pane_states: Vec<_> = self.panes.iter_mut().map(|p| p.get_state()).collect();
+
+        let seq = self.seq;
+        self.history
+            .record(seq, pane_states.iter().map(|p| p.view_state).collect());
+        let state = RenderState {
+            layout: self.layout.clone(),
+            seq,
+            panes: pane_states,
+            snapshot,
+            timestamp: self.get_timestamp(),
+            bitrate: self.get_bitrate(),
+            scaling: self.video_scaling,
+        };
+        if let Some(log) = self.state_log.as_mut() {
+            if let Err(e) = log.append(&state) {
+                log::error!("Failed to log state: {:?}", e);
+            }
+        }
+        self.push_render_state(state);
+        // Increase the sequence number
+        self.seq += 1;
+        seq
+    }
+
+    /// Asks the server for a lossless frame of the current state, it is saved once it arrives.
+    /// `pane` crops the snapshot to one pane, otherwise the whole view is saved.
+    pub fn request_snapshot(&mut self, pane: Option<usize>) {
+        let panes: Vec<_> = match pane {
+            Some(idx) => self
+                .panes
+                .get(idx)
+                .map(|p| p.snapshot())
+                .into_iter()
+                .collect(),
+            None => self.panes.iter().map(|p| p.snapshot()).collect(),
+        };
+        if panes.is_empty() {
+            log::warn!("No pane {:?} to snapshot in view {}", pane, self.video_id);
+            return;
+        }
+        let pane_layout = pane.map(|_| panes[0].layout);
+        let seq = self.send_state(true);
+        log::info!("Requested snapshot {} of view {}", seq, self.video_id);
+        self.snapshot = Some(PendingSnapshot::new(
+            self.video_id,
+            seq,
+            panes,
+            pane_layout,
+            (self.layout.width, self.layout.height),
+        ));
+    }
+
+    /// The pending snapshot and the sample to save, once its frame has arrived.
+    pub fn take_snapshot(&mut self) -> Option<(PendingSnapshot, ViewSample)> {
+        let pending = self.snapshot.as_mut()?;
+        if let Some(sample) = pending.take_sample() {
+            return self.snapshot.take().map(|pending| (pending, sample));
         }
+        if pending.timed_out() {
+            // Any other frame is lossy or shows another state than the metadata says.
+            log::error!(
+                "No frame rendered from snapshot state {} of view {}, nothing saved",
+                pending.seq(),
+                self.video_id
+            );
+            self.snapshot = None;
+        }
+        None
     }
 
     pub fn set_case(&mut self, case: Option<CaseMeta>) {
@@ -594,12 +1032,19 @@ impl View {
         }
 
         // Check the rate schedule
+        let factor = self.rate.as_ref().map_or(1.0, |r| r.factor());
         self.schedule
             .bitrate((self.layout.width, self.layout.height))
             * self.bitrate_scale
+            * factor
     }
 
     pub fn adjust_bitrate_scaling(&mut self, direction: i32) {
+        // A manual change starts from the rate the controller picked and overrides it.
+        if let Some(rate) = self.rate.as_mut() {
+            self.bitrate_scale *= rate.take_over();
+        }
+        self.bitrate_changed = true;
         self.bitrate_scale += direction as f32 * Self::BITRATE_SCALE_DELTA;
         self.bitrate_scale = self.bitrate_scale.max(0.1);
         println!(
@@ -612,6 +1057,25 @@ impl View {
     }
 }
 
+fn receive_counters(stats: &StreamStats) -> Option<ReceiveCounters> {
+    // Prefer the jitterbuffer, it knows about late packets and retransmissions.
+    match (&stats.jitter_buffer, &stats.inbound) {
+        (Some(jb), _) => Some(ReceiveCounters {
+            received: jb.num_pushed,
+            lost: jb.num_lost,
+            late: jb.num_late,
+            retransmitted: jb.rtx_count,
+        }),
+        (None, Some(inbound)) => Some(ReceiveCounters {
+            received: inbound.packets_received,
+            lost: inbound.packets_lost.max(0) as u64,
+            late: 0,
+            retransmitted: inbound.nack_count,
+        }),
+        (None, None) => None,
+    }
+}
+
 #[derive(Debug)]
 pub struct ViewControl {
     views: Vec<View>,
@@ -626,6 +1090,13 @@ pub struct ViewControl {
     partition: (usize, usize),
     last_click: std::time::Instant,
     parked: Option<ParkedState>,
+    measurements: MeasurementStore,
+    info_overlay: InfoOverlay,
+    pane_style: PaneStyle,
+    // Physical pixels per logical pixel, layouts are in physical pixels.
+    scale_factor: f32,
+    // A short message shown over the views, and when it goes away.
+    notice: Option<(String, std::time::Instant)>,
 }
 
 impl ViewControl {
@@ -652,6 +1123,8 @@ impl ViewControl {
                     config.video_scaling,
                     !config.narrow,
                     config.schedule,
+                    config.stats_overlay,
+                    config.adaptive_bitrate,
                 )
             })
             .collect();
@@ -674,6 +1147,11 @@ impl ViewControl {
             partition: (1, 1),
             last_click: std::time::Instant::now(),
             parked: None,
+            measurements: MeasurementStore::open(config.measurements.clone()),
+            info_overlay: config.info_overlay.clone(),
+            pane_style: config.pane_style,
+            scale_factor: 1.0,
+            notice: None,
         }
     }
 
@@ -897,8 +1375,89 @@ impl ViewControl {
         }
     }
 
-    pub fn push_state(&mut self) {
-        self.active_apply_mut(View::push_state);
+    /// Keeps the measurements of the panes in step with their cases,
+    /// returns true if they have to be redrawn.
+    pub fn sync_measurements(&mut self) -> bool {
+        let mut redraw = false;
+        for view in self.views.iter_mut() {
+            redraw = view.sync_measurements(&mut self.measurements) || redraw;
+        }
+        redraw
+    }
+
+    pub fn set_scale_factor(&mut self, scale_factor: f32) {
+        self.scale_factor = scale_factor;
+    }
+
+    pub fn scale_factor(&self) -> f32 {
+        self.scale_factor
+    }
+
+    pub fn get_pane_style(&self) -> &PaneStyle {
+        &self.pane_style
+    }
+
+    /// The video id of the view under the pointer and the index of its pane under the pointer.
+    pub fn focused_pane(&self) -> Option<(usize, usize)> {
+        let view = self.views.get(self.focus?)?;
+        Some((view.video_id(), view.focus?))
+    }
+
+    /// What the pane outlines and badges are drawn from, they are redrawn when it changes.
+    pub fn decoration_state(&self) -> (Option<(usize, usize)>, Vec<PaneBadges>) {
+        let badges = self
+            .active
+            .iter()
+            .flat_map(|idx| self.views[*idx].panes.iter().map(Pane::badges))
+            .collect();
+        (self.focused_pane(), badges)
+    }
+
+    /// Shows the text in the middle of the views for a moment.
+    pub fn show_notice(&mut self, text: String) {
+        self.notice = Some((text, std::time::Instant::now() + NOTICE_DURATION));
+    }
+
+    pub fn get_notice(&self) -> Option<&str> {
+        self.notice.as_ref().map(|(text, _)| text.as_str())
+    }
+
+    // Returns true if the notice went away and the views have to be redrawn.
+    pub fn expire_notice(&mut self) -> bool {
+        match &self.notice {
+            Some((_, until)) if *until <= std::time::Instant::now() => {
+                self.notice = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Shows or hides the information in the corners of the panes.
+    pub fn toggle_info(&mut self) {
+        self.info_overlay.toggle();
+    }
+
+    /// The templates of the info overlay, None if it is hidden.
+    pub fn get_info_overlay(&self) -> Option<&InfoOverlay> {
+        if self.info_overlay.visible {
+            Some(&self.info_overlay)
+        } else {
+            None
+        }
+    }
+
+    // Returns true if an active view sent a new state.
+    pub fn push_state(&mut self) -> bool {
+        let mut sent = false;
+        for idx in &self.active {
+            let view = self
+                .views
+                .get_mut(*idx)
+                .expect("Failed to find active view index");
+            sent = view.push_state() || sent;
+        }
+        sent
     }
 
     pub fn set_case(&mut self, case: Option<CaseMeta>) {
@@ -979,6 +1538,72 @@ impl ViewControl {
         }
     }
 
+    pub fn set_rendered(&mut self, data_id: &str, seq: u64, rtp_time: u32) {
+        match self.views.iter_mut().find(|v| v.data_id() == data_id) {
+            Some(view) => view.set_rendered(seq, rtp_time),
+            None => log::error!("Failed to find view for datachannel with label {}", data_id),
+        }
+    }
+
+    pub fn request_snapshot(&mut self, video_id: usize, pane: Option<usize>) {
+        if let Some(view) = self.views.get_mut(video_id) {
+            view.request_snapshot(pane);
+        }
+    }
+
+    /// Arrival times of the samples of the active views that were just presented for the first time.
+    pub fn take_unpresented(&mut self) -> Vec<std::time::Instant> {
+        let views = &mut self.views;
+        self.active
+            .iter()
+            .filter_map(|idx| views[*idx].take_unpresented())
+            .collect()
+    }
+
+    pub fn take_snapshots(&mut self) -> Vec<(PendingSnapshot, ViewSample)> {
+        self.views
+            .iter_mut()
+            .filter_map(|view| view.take_snapshot())
+            .collect()
+    }
+
+    pub fn start_state_log(&mut self, video_id: usize, path: &Path) {
+        if let Some(view) = self.views.get_mut(video_id) {
+            view.start_state_log(path);
+        }
+    }
+
+    pub fn stop_state_logs(&mut self) {
+        for view in self.views.iter_mut() {
+            view.stop_state_log();
+        }
+    }
+
+    pub fn clear_stream(&mut self, video_id: usize) {
+        if let Some(view) = self.views.get_mut(video_id) {
+            view.clear_stream();
+        }
+    }
+
+    /// Marks the view of a stream as recovering, or all views if `video_id` is None.
+    pub fn set_recovering(&mut self, video_id: Option<usize>) {
+        for view in self.views.iter_mut() {
+            if video_id.is_none() || video_id == Some(view.video_id()) {
+                view.set_recovering();
+            }
+        }
+    }
+
+    /// Hands the stats to the views, returns true if an active view shows them.
+    pub fn set_stats(&mut self, stats: &SessionStats) -> bool {
+        for view in self.views.iter_mut() {
+            view.set_stats(stats);
+        }
+        self.active_map(|view| view.shows_stats())
+            .into_iter()
+            .any(|shows| shows)
+    }
+
     pub fn push_sample(&mut self, sample: ViewSample) {
         // We should be able to find the view based on index.
         if let Some(view) = self.views.get_mut(sample.id) {
@@ -1168,6 +1793,47 @@ impl ViewControl {
         self.active_apply_mut(View::invalidate);
     }
 
+    pub fn reset_session(&mut self) {
+        self.views.iter_mut().for_each(View::reset_session);
+    }
+
+    fn resolve_parked(
+        &self,
+        states: Vec<(Option<CaseMeta>, ViewState)>,
+    ) -> Vec<(Option<CaseMeta>, ViewState)> {
+        // Look up the cases again, the server might have changed them.
+        states
+            .into_iter()
+            .map(|(case, state)| {
+                let case = case.and_then(|c| self.get_case_for_key(&c.key));
+                (case, state)
+            })
+            .collect()
+    }
+
+    pub fn resume_session(&mut self) {
+        // Keep the current partition but restore the cases and view states
+        // of the panes with the (possibly updated) case list.
+        let states: Vec<_> = self
+            .active_map(|v| v.park_state())
+            .into_iter()
+            .map(|states| self.resolve_parked(states))
+            .collect();
+        for (idx, states) in self.active.iter().zip(states) {
+            let view = self.views.get_mut(*idx).expect("Failed to get view");
+            view.restore_parked(states);
+        }
+
+        if let Some(mut parked) = self.parked.take() {
+            parked.states = parked
+                .states
+                .into_iter()
+                .map(|states| self.resolve_parked(states))
+                .collect();
+            self.parked = Some(parked);
+        }
+    }
+
     pub fn handle_timer_event(&mut self) {
         // Let each View/Pane handle the timer event, then run update
         let mut sync_ops = Vec::new();
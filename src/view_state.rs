@@ -131,3 +131,42 @@ impl ViewState {
         }
     }
 }
+
+/// Maps a pane as it was rendered for one state to where it is for a later state,
+/// in pane pixels. Lets the client move the last frame before the server catches up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Prediction {
+    pub scale: f32,
+    pub offset: (f32, f32),
+}
+
+impl Prediction {
+    /// None if the states place the image the same, or can't be compared.
+    pub fn between(
+        displayed: &ViewState,
+        latest: &ViewState,
+        viewport: (f32, f32),
+    ) -> Option<Self> {
+        let scale = match (displayed.zoom, latest.zoom) {
+            (Zoom::Fit(from), Zoom::Fit(to)) | (Zoom::Pixel(from), Zoom::Pixel(to)) => to / from,
+            _ => return None,
+        };
+        // The point in the pane that the image center is placed at.
+        let (from, to) = match (displayed.pos, latest.pos) {
+            (Position::Relative(from), Position::Relative(to)) => {
+                let center = (viewport.0 / 2.0, viewport.1 / 2.0);
+                (
+                    (center.0 + from.0, center.1 + from.1),
+                    (center.0 + to.0, center.1 + to.1),
+                )
+            }
+            (Position::Aboslute(from), Position::Aboslute(to)) => (from, to),
+            _ => return None,
+        };
+        let offset = (to.0 - scale * from.0, to.1 - scale * from.1);
+        if !scale.is_finite() || (scale == 1.0 && offset == (0.0, 0.0)) {
+            return None;
+        }
+        Some(Prediction { scale, offset })
+    }
+}
@@ -1,24 +1,45 @@
 use glutin::event::Event;
 
-use crate::message::{CaseMeta, Protocols};
-use std::time::Duration;
+use crate::{
+    display::DisplaySettings,
+    message::{CaseMeta, Protocols},
+};
+use std::{path::PathBuf, time::Duration};
 
 #[derive(Debug, Clone)]
 pub struct ViewSample {
     pub sample: gstreamer::Sample,
     pub id: usize,
     pub timer: std::time::Instant,
+    // RTP timestamp of the frame, if its packets were seen.
+    pub rtp_time: Option<u32>,
 }
 
 #[derive(Debug, Clone)]
 pub enum WindowMessage {
     Cases((Option<Protocols>, Vec<CaseMeta>)),
-    PipelineError,
+    // The stream the error came from, if known.
+    PipelineError(Option<usize>),
     Timer(Duration),
     Sample(usize),
+    StreamRemoved(usize),
     Datachannel(gstreamer_webrtc::WebRTCDataChannel),
+    // The seq of the state a server rendered and the RTP timestamp of its first frame,
+    // by datachannel label.
+    Rendered(String, u64, u32),
     UpdateLayout,
-    JitterStats,
+    Stats,
+    // Jitterbuffer latency in ms, for one stream or all.
+    SetLatency(Option<usize>, u32),
+    SetRecording(bool),
+    // Snapshot of a view, or of one of its panes.
+    Snapshot(usize, Option<usize>),
+    SetDisplay(DisplaySettings),
+    // A stream started recording, with the path of its state log.
+    RecordingStarted(usize, PathBuf),
+    Reconnected,
+    // The scale factor of the monitor the window is on, and the new size of the window.
+    ScaleFactor(f32, (u32, u32)),
 }
 
 impl<'a> Into<Event<'a, WindowMessage>> for WindowMessage {
@@ -0,0 +1,312 @@
+// Loopback fixture, a local signalling server that streams test video to the client.
+#![allow(dead_code)]
+
+use std::{
+    convert::TryInto,
+    sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use async_std::net::{TcpListener, TcpStream};
+use async_tungstenite::tungstenite::Message;
+use futures::{
+    channel::mpsc::{unbounded, UnboundedSender},
+    future, SinkExt, StreamExt,
+};
+use gst::prelude::*;
+use gstreamer as gst;
+use gstreamer_sdp as gst_sdp;
+use gstreamer_webrtc as gst_webrtc;
+
+use wsclient::message::{AppMessage, CaseMeta, ClientConfig, DataMessage, Protocols, RenderState};
+
+/// A RenderState received on the datachannel of view `video_id`.
+#[derive(Debug)]
+pub struct ReceivedState {
+    pub video_id: usize,
+    pub state: RenderState,
+}
+
+/// Local WebSocket server speaking the AppMessage protocol.
+/// For each Connect it starts a `videotestsrc ! x264enc ! webrtcbin` sender
+/// with one video stream and one `video{N}-data` datachannel per view.
+pub struct LoopbackServer {
+    url: String,
+    states: Receiver<ReceivedState>,
+    _thread: std::thread::JoinHandle<()>,
+}
+
+impl LoopbackServer {
+    pub fn start(protocols: Option<Protocols>, cases: Vec<CaseMeta>) -> Self {
+        gst::init().expect("Failed to initialize GStreamer");
+
+        let (state_snd, states) = channel();
+        let (addr_snd, addr_rcv) = channel();
+        let thread = std::thread::spawn(move || {
+            async_std::task::block_on(async move {
+                let listener = TcpListener::bind("127.0.0.1:0")
+                    .await
+                    .expect("Failed to bind loopback server");
+                addr_snd
+                    .send(listener.local_addr().expect("No local address"))
+                    .expect("Failed to send server address");
+                // Serve one session at a time, a reconnecting client gets a new session.
+                while let Some(Ok(stream)) = listener.incoming().next().await {
+                    let session =
+                        run_session(stream, protocols.clone(), cases.clone(), state_snd.clone());
+                    if let Err(e) = session.await {
+                        log::warn!("Loopback session ended: {:?}", e);
+                    }
+                }
+            });
+        });
+        let addr = addr_rcv.recv().expect("Loopback server failed to start");
+
+        Self {
+            url: format!("ws://{}", addr),
+            states,
+            _thread: thread,
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Waits for a received RenderState that satisfies `predicate`.
+    pub fn wait_for_state<F>(&self, timeout: Duration, mut predicate: F) -> Option<ReceivedState>
+    where
+        F: FnMut(&ReceivedState) -> bool,
+    {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.checked_duration_since(Instant::now())?;
+            match self.states.recv_timeout(remaining) {
+                Ok(state) if predicate(&state) => return Some(state),
+                Ok(_) => continue,
+                Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => {
+                    return None
+                }
+            }
+        }
+    }
+}
+
+async fn run_session(
+    stream: TcpStream,
+    protocols: Option<Protocols>,
+    cases: Vec<CaseMeta>,
+    states: Sender<ReceivedState>,
+) -> Result<()> {
+    let ws = async_tungstenite::accept_async(stream).await?;
+    let (outgoing, mut incomming) = ws.split();
+    let (snd, rcv) = unbounded::<AppMessage>();
+
+    let forward = rcv
+        .map(|m| m.try_into())
+        .forward(outgoing.sink_map_err(anyhow::Error::from));
+    futures::pin_mut!(forward);
+
+    let receive = async {
+        let mut sender: Option<TestSender> = None;
+        while let Some(msg) = incomming.next().await {
+            let msg: AppMessage = match msg? {
+                Message::Close(_) => break,
+                msg if msg.is_text() => msg.try_into()?,
+                _ => continue,
+            };
+            match msg {
+                AppMessage::Connect(configs) => {
+                    sender = Some(TestSender::new(&configs, snd.clone(), states.clone())?);
+                }
+                AppMessage::Sdp { type_, sdp } => {
+                    if let Some(sender) = sender.as_ref() {
+                        sender.handle_sdp(&type_, &sdp)?;
+                    }
+                }
+                AppMessage::Ice {
+                    sdp_mline_index,
+                    candidate,
+                } => {
+                    if let Some(sender) = sender.as_ref() {
+                        sender.handle_ice(sdp_mline_index, &candidate);
+                    }
+                }
+                AppMessage::GetCases => {
+                    snd.unbounded_send(AppMessage::Case((protocols.clone(), cases.clone())))?;
+                }
+                AppMessage::Close => break,
+                msg => log::debug!("Loopback server ignoring {:?}", msg),
+            }
+        }
+        Ok::<(), anyhow::Error>(())
+    };
+    futures::pin_mut!(receive);
+
+    match future::select(forward, receive).await {
+        future::Either::Left((result, _)) => result,
+        future::Either::Right((result, _)) => result,
+    }
+}
+
+// The sending side of the session, plays the role of the render server.
+struct TestSender {
+    pipeline: gst::Pipeline,
+    webrtcbin: gst::Element,
+}
+
+impl TestSender {
+    fn new(
+        configs: &[ClientConfig],
+        signaller: UnboundedSender<AppMessage>,
+        states: Sender<ReceivedState>,
+    ) -> Result<Self> {
+        let pipeline = gst::Pipeline::new(None);
+        let webrtcbin = gst::ElementFactory::make("webrtcbin", Some("webrtcbin"))?;
+        webrtcbin.set_property_from_str("bundle-policy", "max-bundle");
+        pipeline.add(&webrtcbin)?;
+
+        let mut capsfilters = Vec::with_capacity(configs.len());
+        for (idx, config) in configs.iter().enumerate() {
+            let (width, height) = video_size(
+                config.viewport.width,
+                config.viewport.height,
+                config.video_scaling,
+            );
+            let description = format!(
+                "videotestsrc is-live=true ! capsfilter name=caps{idx} caps=video/x-raw,width={width},height={height},framerate=30/1 \
+                 ! videoconvert ! x264enc tune=zerolatency speed-preset=ultrafast key-int-max=30 \
+                 ! rtph264pay config-interval=-1 ! application/x-rtp,media=video,encoding-name=H264,payload={pt}",
+                idx = idx,
+                width = width,
+                height = height,
+                pt = 96 + idx,
+            );
+            let bin = gst::parse_bin_from_description(&description, true)?;
+            pipeline.add(&bin)?;
+            // Requests sink_{idx} on webrtcbin, so the mline index matches the view.
+            bin.link(&webrtcbin)?;
+            let capsfilter = bin
+                .get_by_name(&format!("caps{}", idx))
+                .expect("Failed to get capsfilter");
+            capsfilters.push(capsfilter);
+        }
+
+        let sender = signaller.clone();
+        webrtcbin.connect("on-ice-candidate", false, move |values| {
+            let sdp_mline_index = values[1]
+                .get_some::<u32>()
+                .expect("Failed to get sdp line index");
+            let candidate = values[2]
+                .get::<String>()
+                .expect("Failed to get ice candidate")
+                .unwrap();
+            let _ = sender.unbounded_send(AppMessage::Ice {
+                sdp_mline_index,
+                candidate,
+            });
+            None
+        })?;
+
+        let sender = signaller;
+        webrtcbin.connect("on-negotiation-needed", false, move |values| {
+            let webrtcbin = values[0]
+                .get::<gst::Element>()
+                .expect("Invalid argument")
+                .unwrap();
+            let promise_webrtcbin = webrtcbin.clone();
+            let sender = sender.clone();
+            let promise = gst::Promise::with_change_func(move |reply| {
+                let webrtcbin = promise_webrtcbin;
+                let offer = reply
+                    .ok()
+                    .flatten()
+                    .and_then(|reply| reply.get_value("offer").ok())
+                    .and_then(|offer| offer.get::<gst_webrtc::WebRTCSessionDescription>().ok())
+                    .flatten()
+                    .expect("Failed to create offer");
+                webrtcbin
+                    .emit("set-local-description", &[&offer, &None::<gst::Promise>])
+                    .expect("Failed to set local description");
+                let _ = sender.unbounded_send(AppMessage::Sdp {
+                    type_: "offer".to_string(),
+                    sdp: offer.get_sdp().as_text().unwrap(),
+                });
+            });
+            webrtcbin
+                .emit("create-offer", &[&None::<gst::Structure>, &promise])
+                .expect("Failed to emit create-offer");
+            None
+        })?;
+
+        pipeline.set_state(gst::State::Playing)?;
+
+        // The client matches datachannels to views on the label.
+        for (idx, capsfilter) in capsfilters.into_iter().enumerate() {
+            let datachannel = webrtcbin
+                .emit(
+                    "create-data-channel",
+                    &[&format!("video{}-data", idx), &None::<gst::Structure>],
+                )?
+                .and_then(|value| value.get::<gst_webrtc::WebRTCDataChannel>().ok())
+                .flatten()
+                .expect("Failed to create datachannel");
+            let states = states.clone();
+            datachannel.connect_on_message_string(move |_, msg| {
+                let msg = msg.map(|msg| msg.to_string().try_into());
+                if let Some(Ok(DataMessage::NewState(state))) = msg {
+                    // Render at the size the client asks for, it rejects other sizes.
+                    let (width, height) =
+                        video_size(state.layout.width, state.layout.height, state.scaling);
+                    let caps = gst::Caps::builder("video/x-raw")
+                        .field("width", &(width as i32))
+                        .field("height", &(height as i32))
+                        .field("framerate", &gst::Fraction::new(30, 1))
+                        .build();
+                    capsfilter
+                        .set_property("caps", &caps)
+                        .expect("Failed to set caps");
+                    let _ = states.send(ReceivedState {
+                        video_id: idx,
+                        state,
+                    });
+                }
+            });
+        }
+
+        Ok(Self {
+            pipeline,
+            webrtcbin,
+        })
+    }
+
+    fn handle_sdp(&self, type_: &str, sdp: &str) -> Result<()> {
+        anyhow::ensure!(type_ == "answer", "Expected an SDP answer, got: {}", type_);
+        let msg = gst_sdp::SDPMessage::parse_buffer(sdp.as_bytes())
+            .map_err(|_| anyhow::anyhow!("Failed to parse SDP answer"))?;
+        let answer =
+            gst_webrtc::WebRTCSessionDescription::new(gst_webrtc::WebRTCSDPType::Answer, msg);
+        self.webrtcbin
+            .emit("set-remote-description", &[&answer, &None::<gst::Promise>])?;
+        Ok(())
+    }
+
+    fn handle_ice(&self, sdp_mline_index: u32, candidate: &str) {
+        self.webrtcbin
+            .emit("add-ice-candidate", &[&sdp_mline_index, &candidate])
+            .expect("Failed to add ice candidate");
+    }
+}
+
+impl Drop for TestSender {
+    fn drop(&mut self) {
+        let _ = self.pipeline.set_state(gst::State::Null);
+    }
+}
+
+fn video_size(width: u32, height: u32, scaling: f32) -> (u32, u32) {
+    // x264 wants even dimensions.
+    let scale = |v: u32| (((v as f32 * scaling) as u32) & !1).max(2);
+    (scale(width), scale(height))
+}
@@ -0,0 +1,175 @@
+// End to end test against the loopback server, runs the client headless.
+// Needs the GStreamer webrtc, nice, x264 and libav plugins and an X display, e.g.
+// xvfb-run cargo test --test loopback -- --ignored
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+
+use glutin::{event_loop::EventLoop, platform::unix::EventLoopExtUnix};
+use wsclient::{
+    message::{CaseMeta, LayoutCfg, PaneCfg, Protocols},
+    AppConfig, Frame,
+};
+
+use common::LoopbackServer;
+
+const TIMEOUT: Duration = Duration::from_secs(20);
+
+fn test_config(url: &str, protocol_key: Option<String>, n_views: usize) -> AppConfig {
+    AppConfig::new(
+        url.to_owned(),
+        (256, 256),
+        None,
+        protocol_key,
+        1.0,
+        false,
+        "default".to_owned(),
+        false,
+        1.0,
+        false,
+        false,
+        false,
+        true,
+        None,
+        50,
+        n_views,
+        "default".to_owned(),
+        false,
+        None,
+        None,
+        "default".to_owned(),
+        ".".to_owned(),
+        "mkv".to_owned(),
+        false,
+        "lanczos".to_owned(),
+        "default".to_owned(),
+        None,
+        "default".to_owned(),
+        "default".to_owned(),
+        "immediate".to_owned(),
+        "default".to_owned(),
+    )
+    .expect("Invalid test config")
+}
+
+fn test_cases() -> Vec<CaseMeta> {
+    vec![CaseMeta {
+        key: "case0".to_owned(),
+        number_of_images: 10,
+        number_of_variates: None,
+        pixel_spacing: None,
+        image_size: None,
+    }]
+}
+
+fn is_blank(frame: &Frame) -> bool {
+    // The renderer clears to black, anything else comes from a video sample.
+    frame
+        .data
+        .chunks_exact(4)
+        .all(|pixel| pixel[0] == 0 && pixel[1] == 0 && pixel[2] == 0)
+}
+
+#[test]
+#[ignore]
+fn test_loopback_session() {
+    let _ = env_logger::try_init();
+    let server = LoopbackServer::start(None, test_cases());
+
+    // Headless contexts still need an event loop, tests don't run on the main thread.
+    let event_loop = EventLoop::<()>::new_any_thread();
+    let (app_control, frames) =
+        wsclient::start_headless(test_config(server.url(), None, 1), &event_loop)
+            .expect("Failed to start headless client");
+
+    // The default case is selected once the cases are received.
+    let received = server
+        .wait_for_state(TIMEOUT, |s| {
+            s.state
+                .panes
+                .iter()
+                .any(|p| p.key.as_deref() == Some("case0"))
+        })
+        .expect("No render state for the default case");
+    assert_eq!(received.video_id, 0);
+    assert_eq!(received.state.layout.width, 256);
+    assert_eq!(received.state.layout.height, 256);
+
+    // Samples reach the view and end up in a rendered frame.
+    let deadline = Instant::now() + TIMEOUT;
+    let frame = loop {
+        let remaining = deadline
+            .checked_duration_since(Instant::now())
+            .expect("No video rendered before timeout");
+        let frame = frames.recv_timeout(remaining).expect("No frame rendered");
+        if !is_blank(&frame) {
+            break frame;
+        }
+    };
+    assert_eq!((frame.width, frame.height), (256, 256));
+
+    // Stats are collected once a second and count the decoded frames.
+    let deadline = Instant::now() + TIMEOUT;
+    let stats = loop {
+        let decoded = app_control
+            .stats()
+            .filter(|s| matches!(s.stream(0), Some(stream) if stream.frames_decoded > 0));
+        if let Some(stats) = decoded {
+            break stats;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "No stats collected before timeout"
+        );
+        std::thread::sleep(Duration::from_millis(100));
+    };
+    assert!(stats.stream(0).unwrap().decoder.is_some());
+
+    drop(app_control);
+}
+
+#[test]
+#[ignore]
+fn test_loopback_view_per_pane() {
+    let _ = env_logger::try_init();
+    let protocols = Protocols {
+        layout: vec![LayoutCfg {
+            name: "side-by-side".to_owned(),
+            rows: 1,
+            columns: 2,
+            panes: vec![
+                PaneCfg {
+                    case: "case0".to_owned(),
+                },
+                PaneCfg {
+                    case: "case0".to_owned(),
+                },
+            ],
+        }],
+    };
+    let server = LoopbackServer::start(Some(protocols), test_cases());
+
+    let event_loop = EventLoop::<()>::new_any_thread();
+    let config = test_config(server.url(), Some("side-by-side".to_owned()), 2);
+    let (app_control, _frames) =
+        wsclient::start_headless(config, &event_loop).expect("Failed to start headless client");
+
+    // With two views each pane gets its own stream and datachannel.
+    let mut seen = HashSet::new();
+    server
+        .wait_for_state(TIMEOUT, |s| {
+            if s.state.panes[0].key.as_deref() == Some("case0") {
+                assert_eq!(s.state.layout.width, 128);
+                seen.insert(s.video_id);
+            }
+            seen.len() == 2
+        })
+        .unwrap_or_else(|| panic!("Render states only received for views {:?}", seen));
+
+    drop(app_control);
+}